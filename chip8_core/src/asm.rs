@@ -0,0 +1,746 @@
+// An assembler for the same syntax the disassembler writes in Cowgod mode, so a ROM can be
+// disassembled, edited and assembled again.
+//
+//     ; Comments start with a semicolon.
+//     WIDTH equ 64              ; Constants can be used anywhere a number can.
+//     start:                    ; Labels end with a colon, and can share a line with an instruction.
+//         LD I, sprite
+//         LD V0, (WIDTH - 8) / 2
+//         DRW V0, V1, 5
+//     loop: JP loop
+//     sprite:
+//         db 0xF0, 0x90, 0b11110000, "text"
+//         dw 0x1234             ; Words are big-endian, like opcodes.
+//     include "more.asm"        ; Paths are relative to the file doing the including.
+//
+// Mnemonics, register names and directives are case-insensitive. Labels and constants are not.
+// `org ADDR` skips forward to an address, filling the gap with zeros. Directives can also be
+// written with a leading dot (`.db`), like most other assemblers.
+mod expr;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::opcode::Op;
+use crate::START_ADDR;
+use expr::{Expr, ExprError};
+
+// How deep includes can nest before we assume a file is including itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+// The highest address a program can reach. XO-CHIP has 64K of RAM.
+const MAX_ADDR: u32 = 0xFFFF;
+
+// An assembled program, ready to pass to Emu::load_rom.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Assembly {
+    // The program, with its first byte at START_ADDR.
+    pub bytes: Vec<u8>,
+    // Every label and the address it ended up at.
+    pub labels: BTreeMap<String, u16>,
+    // Where each instruction came from in the source, in address order.
+    pub source_map: Vec<SourceLine>,
+}
+
+// The address of an instruction and the line it was written on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub addr: u16,
+    pub file: String,
+    pub line: usize, // Counting from 1.
+}
+
+// A problem in the source, and where it is. Lines and columns count from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Assembles source text. Any includes are found relative to the current directory.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut asm = Assembler::default();
+    asm.parse_source("<input>", source, Path::new(""), 0)?;
+    asm.finish()
+}
+
+// Assembles a source file and everything it includes.
+pub fn assemble_file(path: &Path) -> Result<Assembly, AsmError> {
+    let mut asm = Assembler::default();
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    asm.parse_source(&path.display().to_string(), &source, dir, 0)?;
+    asm.finish()
+}
+
+// Where something is in the source. `file` indexes Assembler::files.
+#[derive(Debug, Clone, Copy)]
+struct Loc {
+    file: usize,
+    line: usize,
+    column: usize,
+}
+
+// One operand of an instruction.
+#[derive(Debug, Clone)]
+enum Arg {
+    Reg(u8),
+    Range(u8, u8), // Vx-Vy
+    I,
+    IndirectI, // [I]
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Hf,
+    R,
+    Long(Value),
+    Number(Value),
+}
+
+// An expression and the column it starts at, so errors about its value can point at it.
+#[derive(Debug, Clone)]
+struct Value {
+    expr: Expr,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Data {
+    Value(Value),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+enum StmtKind {
+    Instr { mnemonic: String, args: Vec<Arg> },
+    Bytes(Vec<Data>),
+    Words(Vec<Value>),
+    Org(Value),
+}
+
+#[derive(Debug, Clone)]
+struct Stmt {
+    kind: StmtKind,
+    loc: Loc,
+    addr: u32, // Filled in once addresses are assigned.
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    // A label, and its address once that is known.
+    Label(Option<u32>),
+    Constant(Expr),
+}
+
+#[derive(Default)]
+struct Assembler {
+    files: Vec<String>,
+    stmts: Vec<Stmt>,
+    symbols: HashMap<String, Symbol>,
+    // The statement each label comes before, so its address can be filled in.
+    label_positions: Vec<(String, usize)>,
+    // The address after the last byte of the program.
+    end_addr: u32,
+}
+
+impl Assembler {
+    fn error(&self, loc: Loc, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.files[loc.file].clone(),
+            line: loc.line,
+            column: loc.column,
+            message: message.into(),
+        }
+    }
+
+    fn expr_error(&self, loc: Loc, err: ExprError) -> AsmError {
+        self.error(Loc { column: err.column, ..loc }, err.message)
+    }
+
+    fn parse_source(&mut self, name: &str, source: &str, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        let file = self.files.len();
+        self.files.push(name.to_string());
+
+        for (index, line) in source.lines().enumerate() {
+            self.parse_line(line, Loc { file, line: index + 1, column: 1 }, dir, depth)?;
+        }
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line: &str, loc: Loc, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        let at = |text: &str| Loc { column: column_of(line, text), ..loc };
+        let mut rest = strip_comment(line).trim();
+
+        // A label, which might be followed by an instruction.
+        if let Some(colon) = rest.find(':') {
+            let name = rest[..colon].trim_end();
+            if is_identifier(name) {
+                self.define(name, Symbol::Label(None), at(name))?;
+                self.label_positions.push((name.to_string(), self.stmts.len()));
+                rest = rest[colon + 1..].trim();
+            }
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let (word, operands) = split_word(rest);
+        let (second, value) = split_word(operands);
+        if second.eq_ignore_ascii_case("equ") || second.eq_ignore_ascii_case(".equ") {
+            if !is_identifier(word) {
+                return Err(self.error(at(word), format!("'{}' is not a valid name for a constant", word)));
+            }
+            let expr = self.parse_expr(value, at(value), loc)?;
+            return self.define(word, Symbol::Constant(expr), at(word));
+        }
+
+        let mnemonic = word.trim_start_matches('.').to_ascii_uppercase();
+        let args = split_operands(operands);
+        let kind = match mnemonic.as_str() {
+            "DB" | "BYTE" => {
+                let mut data = Vec::new();
+                for arg in &args {
+                    if arg.starts_with('"') {
+                        data.push(Data::Bytes(self.parse_string(arg, at(arg))?));
+                    } else {
+                        data.push(Data::Value(self.parse_value(arg, at(arg), loc)?));
+                    }
+                }
+                StmtKind::Bytes(data)
+            },
+            "DW" | "WORD" => {
+                let mut words = Vec::new();
+                for arg in &args {
+                    words.push(self.parse_value(arg, at(arg), loc)?);
+                }
+                StmtKind::Words(words)
+            },
+            "ORG" => {
+                let [arg] = args.as_slice() else {
+                    return Err(self.error(at(word), "org takes a single address"));
+                };
+                StmtKind::Org(self.parse_value(arg, at(arg), loc)?)
+            },
+            "INCLUDE" => {
+                let [arg] = args.as_slice() else {
+                    return Err(self.error(at(word), "include takes a single file name in quotes"));
+                };
+                return self.include(arg, at(arg), dir, depth);
+            },
+            _ => {
+                let mut parsed = Vec::new();
+                for arg in &args {
+                    parsed.push(self.parse_arg(arg, at(arg), loc)?);
+                }
+                StmtKind::Instr { mnemonic, args: parsed }
+            },
+        };
+
+        self.stmts.push(Stmt { kind, loc: at(word), addr: 0 });
+        Ok(())
+    }
+
+    fn include(&mut self, arg: &str, loc: Loc, dir: &Path, depth: usize) -> Result<(), AsmError> {
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(self.error(loc, "includes are nested too deeply, does a file include itself?"));
+        }
+        let bytes = self.parse_string(arg, loc)?;
+        let path: PathBuf = dir.join(String::from_utf8_lossy(&bytes).as_ref());
+        let source = fs::read_to_string(&path)
+            .map_err(|err| self.error(loc, format!("cannot read {}: {}", path.display(), err)))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.parse_source(&path.display().to_string(), &source, dir, depth + 1)
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, loc: Loc) -> Result<(), AsmError> {
+        if parse_register(name).is_some() || reserved_operand(name).is_some() {
+            return Err(self.error(loc, format!("'{}' is a register name and cannot be redefined", name)));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(self.error(loc, format!("'{}' is defined more than once", name)));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    // `column_loc` is where the expression starts. `loc` is the line it is on.
+    fn parse_expr(&self, text: &str, column_loc: Loc, loc: Loc) -> Result<Expr, AsmError> {
+        expr::parse(text, column_loc.column).map_err(|err| self.expr_error(loc, err))
+    }
+
+    fn parse_value(&self, text: &str, column_loc: Loc, loc: Loc) -> Result<Value, AsmError> {
+        let expr = self.parse_expr(text, column_loc, loc)?;
+        Ok(Value { expr, column: column_loc.column })
+    }
+
+    fn parse_string(&self, text: &str, loc: Loc) -> Result<Vec<u8>, AsmError> {
+        let inner = text
+            .strip_prefix('"')
+            .and_then(|text| text.strip_suffix('"'))
+            .filter(|inner| !inner.contains('"'))
+            .ok_or_else(|| self.error(loc, "strings must be in double quotes"))?;
+        Ok(inner.as_bytes().to_vec())
+    }
+
+    fn parse_arg(&self, text: &str, column_loc: Loc, loc: Loc) -> Result<Arg, AsmError> {
+        if let Some(reg) = parse_register(text) {
+            return Ok(Arg::Reg(reg));
+        }
+        if let Some(arg) = reserved_operand(text) {
+            return Ok(arg);
+        }
+        if let Some((first, last)) = text.split_once('-') {
+            if let (Some(x), Some(y)) = (parse_register(first.trim()), parse_register(last.trim())) {
+                return Ok(Arg::Range(x, y));
+            }
+        }
+        let (word, rest) = split_word(text);
+        if word.eq_ignore_ascii_case("long") {
+            let offset = text.len() - rest.len();
+            let column = Loc { column: column_loc.column + offset, ..column_loc };
+            return Ok(Arg::Long(self.parse_value(rest, column, loc)?));
+        }
+        Ok(Arg::Number(self.parse_value(text, column_loc, loc)?))
+    }
+
+    // Looks up a symbol's value. Labels with no address yet are an error, which only happens
+    // when `org` refers to a label further down. `outer` is the constants whose values are being worked out,
+    // outermost first, so that one which needs its own value can be caught.
+    fn resolve(&self, name: &str, column: usize, here: i64, outer: &[String]) -> Result<i64, ExprError> {
+        match self.symbols.get(name) {
+            Some(Symbol::Label(Some(addr))) => Ok(*addr as i64),
+            Some(Symbol::Label(None)) => {
+                Err(ExprError::new(column, format!("the address of '{}' is not known yet", name)))
+            },
+            Some(Symbol::Constant(expr)) => {
+                if let Some(start) = outer.iter().position(|outer| outer == name) {
+                    let cycle: Vec<&str> = outer[start..].iter().map(String::as_str).chain([name]).collect();
+                    let message = format!("constant '{}' refers to itself: {}", name, cycle.join(" -> "));
+                    return Err(ExprError::new(column, message));
+                }
+                let chain: Vec<String> = outer.iter().cloned().chain([name.to_string()]).collect();
+                let value = expr.eval(&|name, inner| self.resolve(name, inner, here, &chain), here);
+                // Errors deep inside constants are reported where the outermost one is used.
+                match value {
+                    Err(err) if outer.is_empty() => {
+                        Err(ExprError::new(column, format!("in constant '{}': {}", name, err.message)))
+                    },
+                    value => value,
+                }
+            },
+            None => Err(ExprError::new(column, format!("'{}' is not defined", name))),
+        }
+    }
+
+    fn eval(&self, expr: &Expr, loc: Loc, here: u32) -> Result<i64, AsmError> {
+        let here = here as i64;
+        expr.eval(&|name, column| self.resolve(name, column, here, &[]), here)
+            .map_err(|err| self.expr_error(loc, err))
+    }
+
+    // Evaluates a value and checks that it fits in the given range.
+    fn eval_in(&self, value: &Value, loc: Loc, here: u32, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let loc = Loc { column: value.column, ..loc };
+        let value = self.eval(&value.expr, loc, here)?;
+        if value < min || value > max {
+            return Err(self.error(loc, format!("{} {} is out of range ({} to {})", what, value, min, max)));
+        }
+        Ok(value)
+    }
+
+    // Gives every statement and label its address.
+    fn assign_addresses(&mut self) -> Result<(), AsmError> {
+        let mut labels = self.label_positions.iter().peekable();
+        let mut addr = START_ADDR as u32;
+        for index in 0..=self.stmts.len() {
+            while let Some((name, _)) = labels.next_if(|(_, position)| *position == index) {
+                self.symbols.insert(name.clone(), Symbol::Label(Some(addr)));
+            }
+            if index == self.stmts.len() {
+                self.end_addr = addr;
+                break;
+            }
+
+            let stmt = &self.stmts[index];
+            let size = match &stmt.kind {
+                StmtKind::Instr { mnemonic, args } => {
+                    if mnemonic == "LD" && matches!(args.as_slice(), [Arg::I, Arg::Long(_)]) {
+                        4
+                    } else {
+                        2
+                    }
+                },
+                StmtKind::Bytes(data) => data
+                    .iter()
+                    .map(|item| match item {
+                        Data::Value(..) => 1,
+                        Data::Bytes(bytes) => bytes.len() as u32,
+                    })
+                    .sum(),
+                StmtKind::Words(words) => 2 * words.len() as u32,
+                StmtKind::Org(value) => {
+                    let target = self.eval_in(value, stmt.loc, addr, 0, MAX_ADDR as i64 + 1, "address")? as u32;
+                    if target < addr {
+                        let message = format!("org {:#X} is behind the current address {:#X}", target, addr);
+                        return Err(self.error(stmt.loc, message));
+                    }
+                    target - addr
+                },
+            };
+
+            self.stmts[index].addr = addr;
+            addr += size;
+            if addr > MAX_ADDR + 1 {
+                return Err(self.error(self.stmts[index].loc, "the program does not fit in memory"));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Assembly, AsmError> {
+        self.assign_addresses()?;
+
+        let mut assembly = Assembly::default();
+        for stmt in &self.stmts {
+            let here = stmt.addr;
+            // Addresses only jump ahead after an org, and the gap is filled with zeros.
+            assembly.bytes.resize((here - START_ADDR as u32) as usize, 0);
+            match &stmt.kind {
+                StmtKind::Instr { mnemonic, args } => {
+                    let op = self.encode(mnemonic, args, stmt.loc, here)?;
+                    assembly.bytes.extend(op.encode());
+                    assembly.source_map.push(SourceLine {
+                        addr: here as u16,
+                        file: self.files[stmt.loc.file].clone(),
+                        line: stmt.loc.line,
+                    });
+                },
+                StmtKind::Bytes(data) => {
+                    for item in data {
+                        match item {
+                            Data::Value(value) => {
+                                let value = self.eval_in(value, stmt.loc, here, -128, 255, "byte")?;
+                                assembly.bytes.push(value as u8);
+                            },
+                            Data::Bytes(bytes) => assembly.bytes.extend(bytes),
+                        }
+                    }
+                },
+                StmtKind::Words(words) => {
+                    for value in words {
+                        let value = self.eval_in(value, stmt.loc, here, -32768, 0xFFFF, "word")?;
+                        assembly.bytes.extend((value as u16).to_be_bytes());
+                    }
+                },
+                StmtKind::Org(_) => {},
+            }
+        }
+        assembly.bytes.resize((self.end_addr - START_ADDR as u32) as usize, 0);
+
+        for (name, symbol) in &self.symbols {
+            if let Symbol::Label(Some(addr)) = symbol {
+                assembly.labels.insert(name.clone(), *addr as u16);
+            }
+        }
+        Ok(assembly)
+    }
+
+    // Turns one instruction into an Op.
+    fn encode(&self, mnemonic: &str, args: &[Arg], loc: Loc, here: u32) -> Result<Op, AsmError> {
+        let addr = |value: &Value| self.eval_in(value, loc, here, 0, 0xFFF, "address").map(|value| value as u16);
+        let long = |value: &Value| self.eval_in(value, loc, here, 0, 0xFFFF, "address").map(|value| value as u16);
+        let byte = |value: &Value| self.eval_in(value, loc, here, -128, 255, "byte").map(|value| value as u8);
+        let nibble = |value: &Value| self.eval_in(value, loc, here, 0, 15, "value").map(|value| value as u8);
+
+        use Arg::*;
+        let op = match (mnemonic, args) {
+            ("SYS", [Number(a)]) => Op::Sys(addr(a)?),
+            ("CLS", []) => Op::Cls,
+            ("RET", []) => Op::Ret,
+            ("JP", [Number(a)]) => Op::Jump(addr(a)?),
+            ("JP", [Reg(0), Number(a)]) => Op::JumpV0(addr(a)?),
+            ("CALL", [Number(a)]) => Op::Call(addr(a)?),
+            ("SE", [Reg(x), Number(b)]) => Op::SkipEqByte(*x, byte(b)?),
+            ("SE", [Reg(x), Reg(y)]) => Op::SkipEqReg(*x, *y),
+            ("SNE", [Reg(x), Number(b)]) => Op::SkipNeByte(*x, byte(b)?),
+            ("SNE", [Reg(x), Reg(y)]) => Op::SkipNeReg(*x, *y),
+            ("LD", [Reg(x), Number(b)]) => Op::SetByte(*x, byte(b)?),
+            ("LD", [Reg(x), Reg(y)]) => Op::SetReg(*x, *y),
+            ("LD", [I, Number(a)]) => Op::SetI(addr(a)?),
+            ("LD", [I, Long(a)]) => Op::LongI(long(a)?),
+            ("LD", [Reg(x), Dt]) => Op::GetDelay(*x),
+            ("LD", [Reg(x), K]) => Op::WaitKey(*x),
+            ("LD", [Dt, Reg(x)]) => Op::SetDelay(*x),
+            ("LD", [St, Reg(x)]) => Op::SetSound(*x),
+            ("LD", [F, Reg(x)]) => Op::Font(*x),
+            ("LD", [B, Reg(x)]) => Op::Bcd(*x),
+            ("LD", [IndirectI, Reg(x)]) => Op::Store(*x),
+            ("LD", [Reg(x), IndirectI]) => Op::Load(*x),
+            ("LD", [Hf, Reg(x)]) => Op::BigFont(*x),
+            ("LD", [R, Reg(x)]) => Op::SaveFlags(*x),
+            ("LD", [Reg(x), R]) => Op::LoadFlags(*x),
+            ("LD", [IndirectI, Range(x, y)]) => Op::StoreRange(*x, *y),
+            ("LD", [Range(x, y), IndirectI]) => Op::LoadRange(*x, *y),
+            ("ADD", [Reg(x), Number(b)]) => Op::AddByte(*x, byte(b)?),
+            ("ADD", [Reg(x), Reg(y)]) => Op::Add(*x, *y),
+            ("ADD", [I, Reg(x)]) => Op::AddI(*x),
+            ("OR", [Reg(x), Reg(y)]) => Op::Or(*x, *y),
+            ("AND", [Reg(x), Reg(y)]) => Op::And(*x, *y),
+            ("XOR", [Reg(x), Reg(y)]) => Op::Xor(*x, *y),
+            ("SUB", [Reg(x), Reg(y)]) => Op::Sub(*x, *y),
+            ("SUBN", [Reg(x), Reg(y)]) => Op::SubN(*x, *y),
+            // The one operand forms are common in older sources, where VY is ignored.
+            ("SHR", [Reg(x)]) => Op::Shr(*x, *x),
+            ("SHR", [Reg(x), Reg(y)]) => Op::Shr(*x, *y),
+            ("SHL", [Reg(x)]) => Op::Shl(*x, *x),
+            ("SHL", [Reg(x), Reg(y)]) => Op::Shl(*x, *y),
+            ("RND", [Reg(x), Number(b)]) => Op::Rand(*x, byte(b)?),
+            ("DRW", [Reg(x), Reg(y), Number(n)]) => Op::Draw(*x, *y, nibble(n)?),
+            ("SKP", [Reg(x)]) => Op::SkipKey(*x),
+            ("SKNP", [Reg(x)]) => Op::SkipNotKey(*x),
+            ("SCD", [Number(n)]) => Op::ScrollDown(nibble(n)?),
+            ("SCR", []) => Op::ScrollRight,
+            ("SCL", []) => Op::ScrollLeft,
+            ("EXIT", []) => Op::Exit,
+            ("LOW", []) => Op::Lores,
+            ("HIGH", []) => Op::Hires,
+            ("SCU", [Number(n)]) => Op::ScrollUp(nibble(n)?),
+            ("PLANE", [Number(n)]) => Op::Plane(nibble(n)?),
+            ("AUDIO", []) => Op::Audio,
+            ("PITCH", [Reg(x)]) => Op::Pitch(*x),
+            _ if MNEMONICS.contains(&mnemonic) => {
+                return Err(self.error(loc, format!("invalid operands for {}", mnemonic)));
+            },
+            _ => return Err(self.error(loc, format!("unknown instruction '{}'", mnemonic))),
+        };
+        Ok(op)
+    }
+}
+
+const MNEMONICS: [&str; 30] = [
+    "SYS", "CLS", "RET", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL",
+    "RND", "DRW", "SKP", "SKNP", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SCU", "PLANE", "AUDIO", "PITCH",
+];
+
+// Parses V0 to VF.
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('V').or_else(|| text.strip_prefix('v'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+// Operands that are names rather than values.
+fn reserved_operand(text: &str) -> Option<Arg> {
+    let arg = match text.to_ascii_uppercase().as_str() {
+        "I" => Arg::I,
+        "[I]" => Arg::IndirectI,
+        "DT" => Arg::Dt,
+        "ST" => Arg::St,
+        "K" => Arg::K,
+        "F" => Arg::F,
+        "B" => Arg::B,
+        "HF" => Arg::Hf,
+        "R" => Arg::R,
+        _ => return None,
+    };
+    Some(arg)
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// The column (from 1) where `part`, a slice of `line`, starts.
+fn column_of(line: &str, part: &str) -> usize {
+    part.as_ptr() as usize - line.as_ptr() as usize + 1
+}
+
+// Removes a comment from the end of a line, leaving semicolons inside strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..index],
+            _ => {},
+        }
+    }
+    line
+}
+
+// Splits off the first word, returning it and the rest with whitespace trimmed.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim()),
+        None => (text, &text[text.len()..]),
+    }
+}
+
+// Splits operands on commas, except commas inside strings.
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                operands.push(text[start..index].trim());
+                start = index + 1;
+            },
+            _ => {},
+        }
+    }
+    operands.push(text[start..].trim());
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::disasm::{self, Syntax};
+
+    fn error(source: &str) -> String {
+        assemble(source).unwrap_err().to_string()
+    }
+
+    // A fresh directory for a test's source files.
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8_asm_{}_{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn labels_can_be_used_before_they_are_defined() {
+        let assembly = assemble("start: JP end\n  CLS\nend: CALL start ; Back to the top.\n").unwrap();
+        assert_eq!(assembly.bytes, [0x12, 0x04, 0x00, 0xE0, 0x22, 0x00]);
+        assert_eq!(assembly.labels["start"], 0x200);
+        assert_eq!(assembly.labels["end"], 0x204);
+        let lines: Vec<(u16, usize)> = assembly.source_map.iter().map(|line| (line.addr, line.line)).collect();
+        assert_eq!(lines, [(0x200, 1), (0x202, 2), (0x204, 3)]);
+    }
+
+    #[test]
+    fn constants_and_data() {
+        let source = "WIDTH equ 64\nSPRITE .equ WIDTH - 8\n  LD V0, SPRITE / 2\n  db 0xF0, -1, \"hi\"\n  dw 0x1234, $\n";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.bytes, [0x60, 0x1C, 0xF0, 0xFF, b'h', b'i', 0x12, 0x34, 0x02, 0x06]);
+    }
+
+    #[test]
+    fn org_skips_ahead() {
+        let assembly = assemble("CLS\norg 0x208\nhere: db 1\n.org here + 4\n").unwrap();
+        assert_eq!(assembly.bytes, [0x00, 0xE0, 0, 0, 0, 0, 0, 0, 0x01, 0, 0, 0]);
+        assert_eq!(assembly.labels["here"], 0x208);
+
+        assert_eq!(error("CLS\nCLS\norg 0x202"), "<input>:3:1: org 0x202 is behind the current address 0x204");
+        assert_eq!(error("org later\nlater: CLS"), "<input>:1:5: the address of 'later' is not known yet");
+    }
+
+    #[test]
+    fn long_addresses() {
+        let assembly = assemble("LD I, long data\norg 0x1234\ndata: db 7").unwrap();
+        assert_eq!(assembly.bytes[..4], [0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(error("LD I, data\norg 0x1234\ndata: db 7"), "<input>:1:7: address 4660 is out of range (0 to 4095)");
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let dir = scratch_dir("include");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("main.asm"), "CALL draw\ninclude \"lib/draw.asm\"\n").unwrap();
+        fs::write(dir.join("lib").join("draw.asm"), "draw: LD I, sprite\n  RET\ninclude \"sprite.asm\"\n").unwrap();
+        fs::write(dir.join("lib").join("sprite.asm"), "sprite: db 0x80\n").unwrap();
+
+        let assembly = assemble_file(&dir.join("main.asm")).unwrap();
+        assert_eq!(assembly.bytes, [0x22, 0x02, 0xA2, 0x06, 0x00, 0xEE, 0x80]);
+        assert_eq!(assembly.source_map[1].file, dir.join("lib").join("draw.asm").display().to_string());
+        assert_eq!(assembly.source_map[1].line, 1);
+
+        // Errors in an included file say which file they are in.
+        fs::write(dir.join("lib").join("sprite.asm"), "sprite: db 0x100\n").unwrap();
+        let err = assemble_file(&dir.join("main.asm")).unwrap_err();
+        assert_eq!((err.file, err.line, err.column), (dir.join("lib").join("sprite.asm").display().to_string(), 1, 12));
+
+        fs::write(dir.join("lib").join("sprite.asm"), "include \"sprite.asm\"\n").unwrap();
+        let err = assemble_file(&dir.join("main.asm")).unwrap_err();
+        assert_eq!(err.message, "includes are nested too deeply, does a file include itself?");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn out_of_range_values_say_where_they_are() {
+        assert_eq!(error("CLS\n  LD V0, 256"), "<input>:2:10: byte 256 is out of range (-128 to 255)");
+        assert_eq!(error("loop: DRW V0, V1, 16"), "<input>:1:19: value 16 is out of range (0 to 15)");
+        assert_eq!(error("JP 0x1000"), "<input>:1:4: address 4096 is out of range (0 to 4095)");
+        assert_eq!(error("dw 0x10000"), "<input>:1:4: word 65536 is out of range (-32768 to 65535)");
+        assert_eq!(error("LD V0, 1 +"), "<input>:1:11: expected a value");
+        assert_eq!(error("BIG equ 1000\nLD V0, BIG"), "<input>:2:8: byte 1000 is out of range (-128 to 255)");
+    }
+
+    #[test]
+    fn mistakes_say_where_they_are() {
+        assert_eq!(error("  JUMP 0x200"), "<input>:1:3: unknown instruction 'JUMP'");
+        assert_eq!(error("LD DT, 5"), "<input>:1:1: invalid operands for LD");
+        assert_eq!(error("JP nowhere"), "<input>:1:4: 'nowhere' is not defined");
+        assert_eq!(error("a: CLS\na: CLS"), "<input>:2:1: 'a' is defined more than once");
+        assert_eq!(error("VA equ 1"), "<input>:1:1: 'VA' is a register name and cannot be redefined");
+        assert_eq!(
+            error("X equ Y\nY equ X\nLD V0, X"),
+            "<input>:3:8: in constant 'X': constant 'X' refers to itself: X -> Y -> X"
+        );
+        assert_eq!(
+            error("X equ X + 1\nZ equ X\nLD V0, Z"),
+            "<input>:3:8: in constant 'Z': constant 'X' refers to itself: X -> X"
+        );
+        assert_eq!(error("db \"open"), "<input>:1:4: strings must be in double quotes");
+    }
+
+    // Every ROM disassembles into source that assembles back into the same bytes.
+    #[test]
+    fn roms_survive_a_round_trip() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("rom_files");
+        let mut roms: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        roms.sort();
+        assert!(!roms.is_empty());
+        for path in roms {
+            let rom = fs::read(&path).unwrap();
+            let lines = disasm::disassemble(&rom, START_ADDR, Syntax::Cowgod, true);
+            let source: Vec<String> = lines.into_iter().map(|line| line.text).collect();
+            let assembly = assemble(&source.join("\n"))
+                .unwrap_or_else(|err| panic!("{} doesn't reassemble: {}", path.display(), err));
+            assert!(assembly.bytes == rom, "{} reassembles into different bytes", path.display());
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// Expressions for operands, constants and data, like `SPRITE + 5` or `(WIDTH - 8) / 2`.
+// Operators and precedence follow C: | ^ & << >> + - * / %, then unary - and ~, then parentheses.
+// Numbers can be decimal, hex (0x1F) or binary (0b0110). `$` is the address of the current line.
+
+#[derive(Debug, Clone)]
+pub(crate) enum Expr {
+    Num(i64),
+    Symbol { name: String, column: usize },
+    Here,
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary { op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr>, column: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+// Something wrong with an expression, and the column it was found at.
+#[derive(Debug, Clone)]
+pub(crate) struct ExprError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl ExprError {
+    pub(crate) fn new(column: usize, message: impl Into<String>) -> Self {
+        Self { column, message: message.into() }
+    }
+}
+
+// Looks up the value of a symbol by name. The column is where the name appears, for error messages.
+pub(crate) type Resolver<'a> = &'a dyn Fn(&str, usize) -> Result<i64, ExprError>;
+
+impl Expr {
+    pub(crate) fn eval(&self, resolve: Resolver, here: i64) -> Result<i64, ExprError> {
+        Ok(match self {
+            Expr::Num(value) => *value,
+            Expr::Symbol { name, column } => resolve(name, *column)?,
+            Expr::Here => here,
+            Expr::Neg(inner) => inner.eval(resolve, here)?.wrapping_neg(),
+            Expr::Not(inner) => !inner.eval(resolve, here)?,
+            Expr::Binary { op, lhs, rhs, column } => {
+                let lhs = lhs.eval(resolve, here)?;
+                let rhs = rhs.eval(resolve, here)?;
+                match op {
+                    BinaryOp::Or => lhs | rhs,
+                    BinaryOp::Xor => lhs ^ rhs,
+                    BinaryOp::And => lhs & rhs,
+                    BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+                    BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                    BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                    BinaryOp::Div | BinaryOp::Rem if rhs == 0 => {
+                        return Err(ExprError::new(*column, "division by zero"));
+                    },
+                    BinaryOp::Div => lhs.wrapping_div(rhs),
+                    BinaryOp::Rem => lhs.wrapping_rem(rhs),
+                }
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+// Parses an expression. `column` is where the text starts on its line, so errors can point at the right place.
+pub(crate) fn parse(text: &str, column: usize) -> Result<Expr, ExprError> {
+    let tokens = tokenize(text, column)?;
+    if tokens.is_empty() {
+        return Err(ExprError::new(column, "expected a value"));
+    }
+
+    let mut parser = Parser { tokens, pos: 0, end_column: column + text.len() };
+    let expr = parser.binary(0)?;
+    match parser.tokens.get(parser.pos) {
+        Some((_, column)) => Err(ExprError::new(*column, "unexpected text after the end of the expression")),
+        None => Ok(expr),
+    }
+}
+
+// Splits an expression up into numbers, names and operators, each with its column.
+fn tokenize(text: &str, column: usize) -> Result<Vec<(Token, usize)>, ExprError> {
+    const OPERATORS: [&str; 14] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", "$"];
+
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                pos += 1;
+            }
+            let word = text[start..pos].replace('_', "");
+            let parsed = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(bin) = word.strip_prefix("0b").or_else(|| word.strip_prefix("0B")) {
+                i64::from_str_radix(bin, 2)
+            } else {
+                word.parse()
+            };
+            let value = parsed.map_err(|_| ExprError::new(column + start, format!("'{}' is not a number", &text[start..pos])))?;
+            tokens.push((Token::Num(value), column + start));
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'.' {
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_' || bytes[pos] == b'.') {
+                pos += 1;
+            }
+            tokens.push((Token::Ident(text[start..pos].to_string()), column + start));
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| text[pos..].starts_with(**op))
+                .ok_or_else(|| ExprError::new(column + start, format!("unexpected '{}'", text[start..].chars().next().unwrap())))?;
+            pos += op.len();
+            tokens.push((Token::Op(op), column + start));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end_column: usize, // Where the text ends, for errors about missing values.
+}
+
+// Binary operators from loosest to tightest binding.
+const PRECEDENCE: [&[(&str, BinaryOp)]; 6] = [
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+];
+
+impl Parser {
+    fn peek_op(&self) -> Option<(&'static str, usize)> {
+        match self.tokens.get(self.pos) {
+            Some((Token::Op(op), column)) => Some((op, *column)),
+            _ => None,
+        }
+    }
+
+    // Parses operators at this precedence level and tighter.
+    fn binary(&mut self, level: usize) -> Result<Expr, ExprError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some((text, column)) = self.peek_op() {
+            let op = match PRECEDENCE[level].iter().find(|(op, _)| *op == text) {
+                Some((_, op)) => *op,
+                None => break,
+            };
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs), column };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        let (token, column) = match self.tokens.get(self.pos) {
+            Some(token) => token.clone(),
+            None => return Err(ExprError::new(self.end_column, "expected a value")),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Num(value) => Ok(Expr::Num(value)),
+            Token::Ident(name) => Ok(Expr::Symbol { name, column }),
+            Token::Op("$") => Ok(Expr::Here),
+            Token::Op("-") => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Token::Op("~") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::Op("(") => {
+                let inner = self.binary(0)?;
+                match self.peek_op() {
+                    Some((")", _)) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    },
+                    _ => Err(ExprError::new(column, "'(' is never closed")),
+                }
+            },
+            Token::Op(op) => Err(ExprError::new(column, format!("expected a value, found '{}'", op))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Evaluates an expression that starts at column 1, with WIDTH = 64 and `$` at 0x200.
+    fn eval(text: &str) -> Result<i64, ExprError> {
+        let resolve = |name: &str, column| match name {
+            "WIDTH" => Ok(64),
+            _ => Err(ExprError::new(column, format!("'{}' is not defined", name))),
+        };
+        parse(text, 1)?.eval(&resolve, 0x200)
+    }
+
+    // The column and message of an expression that doesn't evaluate.
+    fn error(text: &str) -> (usize, String) {
+        let err = eval(text).unwrap_err();
+        (err.column, err.message)
+    }
+
+    #[test]
+    fn precedence_follows_c() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval("1 << 2 + 1").unwrap(), 8);
+        assert_eq!(eval("1 | 2 ^ 3 & 6").unwrap(), 1);
+        assert_eq!(eval("0xF0 >> 4 & 3").unwrap(), 3);
+        assert_eq!(eval("7 / 2 % 3").unwrap(), 0);
+        assert_eq!(eval("-2 * 3").unwrap(), -6);
+        assert_eq!(eval("~0 & 0xF").unwrap(), 15);
+        assert_eq!(eval("-(1 + 2)").unwrap(), -3);
+    }
+
+    #[test]
+    fn operators_group_from_the_left() {
+        assert_eq!(eval("10 - 4 - 3").unwrap(), 3);
+        assert_eq!(eval("64 / 4 / 2").unwrap(), 8);
+        assert_eq!(eval("1 << 2 << 3").unwrap(), 32);
+    }
+
+    #[test]
+    fn numbers_names_and_here() {
+        assert_eq!(eval("0b1010 + 0x10 + 1_000").unwrap(), 1026);
+        assert_eq!(eval("0XfF").unwrap(), 255);
+        assert_eq!(eval("(WIDTH - 8) / 2").unwrap(), 28);
+        assert_eq!(eval("$ + 2").unwrap(), 0x202);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(error(""), (1, "expected a value".to_string()));
+        assert_eq!(error("1 +"), (4, "expected a value".to_string()));
+        assert_eq!(error("(1 + 2"), (1, "'(' is never closed".to_string()));
+        assert_eq!(error("1 2"), (3, "unexpected text after the end of the expression".to_string()));
+        assert_eq!(error("2 @ 3"), (3, "unexpected '@'".to_string()));
+        assert_eq!(error("0x1G"), (1, "'0x1G' is not a number".to_string()));
+        assert_eq!(error("* 2"), (1, "expected a value, found '*'".to_string()));
+        assert_eq!(error("1 + 4 / (2 - 2)"), (7, "division by zero".to_string()));
+        assert_eq!(error("HEIGHT / 2"), (1, "'HEIGHT' is not defined".to_string()));
+    }
+}
@@ -0,0 +1,7 @@
+// Somewhere for the buzzer to go. The core has no idea how to make a sound itself,
+// so frontends hand it one of these with Emu::set_audio_sink.
+pub trait AudioSink {
+    // Called from tick_timers whenever the buzzer switches on or off.
+    // The buzzer is on for every frame that starts with the sound timer above zero.
+    fn buzzer(&mut self, on: bool);
+}
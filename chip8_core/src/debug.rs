@@ -0,0 +1,324 @@
+// Breakpoints, watchpoints and register watches, for debuggers that want the core to tell them when
+// something interesting happens instead of checking after every instruction themselves.
+//
+// Memory watchpoints see the RAM that instructions read and write as data: FX55 and FX33 writes,
+// FX65 reads, DXYN sprite reads, and XO-CHIP's 5XY2, 5XY3 and F002. Fetching instructions doesn't count.
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Emu, EmuError};
+
+// The kind of memory access. Watchpoints can look for either kind, or both with Any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Any, // Only used when setting a watchpoint. Accesses that are found are always a read or a write.
+}
+
+impl Access {
+    fn covers(self, access: Access) -> bool {
+        self == Access::Any || self == access
+    }
+}
+
+// A register whose changes can be watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(u8), // V0 through VF.
+    I,
+}
+
+// Why Emu::run_until stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // The instruction at pc has a breakpoint on it, and hasn't run yet.
+    Breakpoint { pc: u16 },
+    // The instruction at pc matches an opcode breakpoint, and hasn't run yet.
+    Opcode { pc: u16, op: u16 },
+    // The instruction at pc read or wrote a watched address. The value is what was read or written.
+    // Only the first watched access of an instruction is reported.
+    Watchpoint { pc: u16, addr: usize, access: Access, value: u8 },
+    // The instruction at pc changed a watched register.
+    RegisterChanged { pc: u16, register: Register, old: u16, new: u16 },
+    // The program ran 00FD.
+    Exited,
+    // All of the instructions asked for were run.
+    TickLimit,
+}
+
+// Everything the debugging API has been asked to look out for. Kept across resets and save state loads.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Watches {
+    breakpoints: BTreeSet<u16>,
+    opcodes: Vec<(u16, u16)>, // Masks and the values the masked opcode has to equal.
+    memory: BTreeMap<usize, Access>,
+    registers: BTreeSet<Register>,
+    hit: Option<(usize, Access, u8)>, // The first watched access made by the current instruction.
+}
+
+impl Watches {
+    pub(crate) fn clear_hit(&mut self) {
+        self.hit = None;
+    }
+}
+
+impl Emu {
+    // Stops run_until before the instruction at addr runs.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.watches.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.watches.breakpoints.remove(&addr)
+    }
+
+    // Stops run_until before any instruction whose opcode, ANDed with mask, equals value.
+    // For example a mask of 0xF000 and a value of 0xD000 stops on every draw.
+    pub fn add_opcode_breakpoint(&mut self, mask: u16, value: u16) {
+        self.watches.opcodes.push((mask, value));
+    }
+
+    pub fn remove_opcode_breakpoint(&mut self, mask: u16, value: u16) -> bool {
+        let before = self.watches.opcodes.len();
+        self.watches.opcodes.retain(|&opcode| opcode != (mask, value));
+        self.watches.opcodes.len() != before
+    }
+
+    // Stops run_until after an instruction reads or writes addr, depending on access.
+    pub fn add_watchpoint(&mut self, addr: usize, access: Access) {
+        self.watches.memory.insert(addr, access);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watches.memory.remove(&addr).is_some()
+    }
+
+    // Stops run_until after an instruction changes the register.
+    pub fn watch_register(&mut self, register: Register) {
+        self.watches.registers.insert(register);
+    }
+
+    pub fn unwatch_register(&mut self, register: Register) -> bool {
+        self.watches.registers.remove(&register)
+    }
+
+    // The addresses with breakpoints on them.
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.watches.breakpoints
+    }
+
+    // The masks and values of every opcode breakpoint.
+    pub fn opcode_breakpoints(&self) -> &[(u16, u16)] {
+        &self.watches.opcodes
+    }
+
+    // The watched addresses, and which kind of access each one is watched for.
+    pub fn watchpoints(&self) -> &BTreeMap<usize, Access> {
+        &self.watches.memory
+    }
+
+    pub fn watched_registers(&self) -> &BTreeSet<Register> {
+        &self.watches.registers
+    }
+
+    // Removes every breakpoint, watchpoint and register watch.
+    pub fn clear_watches(&mut self) {
+        self.watches = Watches::default();
+    }
+
+    // Runs up to max_ticks instructions, stopping early at breakpoints, watched accesses and changes,
+    // or when the program exits. Breakpoints are checked before every instruction, including the
+    // first, so to carry on from one, run that instruction with step before calling this again.
+    // Like tick, this doesn't touch the timers. Call tick_timers between calls to keep time.
+    // Returns how many instructions were run and why it stopped.
+    pub fn run_until(&mut self, max_ticks: u32) -> Result<(u32, StopReason), EmuError> {
+        for ticks in 0..max_ticks {
+            if self.exited {
+                return Ok((ticks, StopReason::Exited));
+            }
+            let pc = self.pc;
+            if self.watches.breakpoints.contains(&pc) {
+                return Ok((ticks, StopReason::Breakpoint { pc }));
+            }
+            if !self.watches.opcodes.is_empty() {
+                let op = self.ram.get(pc as usize..pc as usize + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+                if let Some(op) = op.filter(|op| self.watches.opcodes.iter().any(|&(mask, value)| op & mask == value)) {
+                    return Ok((ticks, StopReason::Opcode { pc, op }));
+                }
+            }
+
+            if let Some(reason) = self.run_one()? {
+                return Ok((ticks + 1, reason));
+            }
+        }
+
+        Ok((max_ticks, if self.exited { StopReason::Exited } else { StopReason::TickLimit }))
+    }
+
+    // Runs the instruction at pc like tick does, ignoring any breakpoint on it, but still reporting
+    // watched accesses and changes. This is how a debugger carries on from a breakpoint.
+    // Returns TickLimit if nothing was watched, or Exited without running anything if the program has exited.
+    pub fn step(&mut self) -> Result<StopReason, EmuError> {
+        if self.exited {
+            return Ok(StopReason::Exited);
+        }
+        Ok(self.run_one()?.unwrap_or(StopReason::TickLimit))
+    }
+
+    // Runs one instruction, and returns the first watched access or change it made.
+    fn run_one(&mut self) -> Result<Option<StopReason>, EmuError> {
+        let pc = self.pc;
+        let before = self.register_values();
+        self.tick()?;
+
+        if let Some((addr, access, value)) = self.watches.hit.take() {
+            return Ok(Some(StopReason::Watchpoint { pc, addr, access, value }));
+        }
+        let after = self.register_values();
+        let changed = before.iter().zip(&after).find(|(old, new)| old.1 != new.1);
+        Ok(changed.map(|(&(register, old), &(_, new))| StopReason::RegisterChanged { pc, register, old, new }))
+    }
+
+    // The current value of every watched register.
+    fn register_values(&self) -> Vec<(Register, u16)> {
+        self.watches
+            .registers
+            .iter()
+            .map(|&register| {
+                let value = match register {
+                    Register::V(x) => self.v_reg[(x & 0xF) as usize] as u16,
+                    Register::I => self.i_reg,
+                };
+                (register, value)
+            })
+            .collect()
+    }
+
+    // Notes a data access for the watchpoints. Only the first one an instruction makes is kept.
+    pub(crate) fn watch_access(&mut self, addr: usize, access: Access, value: u8) {
+        let watched = self.watches.memory.get(&addr).is_some_and(|watch| watch.covers(access));
+        if watched && self.watches.hit.is_none() {
+            self.watches.hit = Some((addr, access, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quirks;
+
+    fn emu_with(program: &[u8]) -> Emu {
+        let mut emu = Emu::new(Quirks::CHIP_48);
+        emu.load_rom(program).unwrap();
+        emu
+    }
+
+    // V0 = 7, I = 0x300, then stores V0 with FX55 and spins.
+    const STORE: [u8; 8] = [0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+    // V0 = 123, I = 0x300, then writes its digits with FX33 and spins.
+    const BCD: [u8; 8] = [0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33, 0x12, 0x06];
+
+    #[test]
+    fn watchpoint_on_store() {
+        let mut emu = emu_with(&STORE);
+        emu.add_watchpoint(0x300, Access::Write);
+        let reason = StopReason::Watchpoint { pc: 0x204, addr: 0x300, access: Access::Write, value: 7 };
+        assert_eq!(emu.run_until(10), Ok((3, reason)));
+        assert_eq!(emu.ram[0x300], 7);
+
+        // A watch for reads doesn't see writes.
+        let mut emu = emu_with(&STORE);
+        emu.add_watchpoint(0x300, Access::Read);
+        assert_eq!(emu.run_until(10), Ok((10, StopReason::TickLimit)));
+    }
+
+    #[test]
+    fn watchpoint_on_bcd() {
+        let mut emu = emu_with(&BCD);
+        emu.add_watchpoint(0x302, Access::Any);
+        let reason = StopReason::Watchpoint { pc: 0x204, addr: 0x302, access: Access::Write, value: 3 };
+        assert_eq!(emu.run_until(10), Ok((3, reason)));
+
+        // Only the first watched byte an instruction writes is reported.
+        let mut emu = emu_with(&BCD);
+        emu.add_watchpoint(0x302, Access::Write);
+        emu.add_watchpoint(0x301, Access::Write);
+        let reason = StopReason::Watchpoint { pc: 0x204, addr: 0x301, access: Access::Write, value: 2 };
+        assert_eq!(emu.run_until(10), Ok((3, reason)));
+    }
+
+    #[test]
+    fn register_watch_fires_on_changes_only() {
+        // V0 = 5, V0 = 5 again, V1 = 5, V0 += 1.
+        let mut emu = emu_with(&[0x60, 0x05, 0x60, 0x05, 0x61, 0x05, 0x70, 0x01, 0x12, 0x08]);
+        emu.watch_register(Register::V(0));
+
+        let changed = |pc, old, new| StopReason::RegisterChanged { pc, register: Register::V(0), old, new };
+        assert_eq!(emu.run_until(10), Ok((1, changed(0x200, 0, 5))));
+        // Setting it to the value it already has, and changing other registers, doesn't stop.
+        assert_eq!(emu.run_until(10), Ok((3, changed(0x206, 5, 6))));
+        assert_eq!(emu.run_until(10), Ok((10, StopReason::TickLimit)));
+
+        emu.unwatch_register(Register::V(0));
+        emu.watch_register(Register::I);
+        assert_eq!(emu.run_until(10), Ok((10, StopReason::TickLimit)));
+    }
+
+    #[test]
+    fn run_until_stops_at_breakpoints() {
+        let mut emu = emu_with(&STORE);
+        emu.add_breakpoint(0x204);
+        assert_eq!(emu.run_until(10), Ok((2, StopReason::Breakpoint { pc: 0x204 })));
+        // The breakpoint is checked before anything runs, so it stops straight away until stepped over.
+        assert_eq!(emu.run_until(10), Ok((0, StopReason::Breakpoint { pc: 0x204 })));
+        assert_eq!(emu.step(), Ok(StopReason::TickLimit));
+        assert_eq!(emu.run_until(10), Ok((10, StopReason::TickLimit)));
+        assert_eq!(emu.pc, 0x206);
+
+        // Opcode breakpoints stop on the first match.
+        let mut emu = emu_with(&STORE);
+        emu.add_opcode_breakpoint(0xF0FF, 0xF055);
+        assert_eq!(emu.run_until(10), Ok((2, StopReason::Opcode { pc: 0x204, op: 0xF055 })));
+    }
+
+    #[test]
+    fn run_until_stops_at_the_step_limit_and_exit() {
+        let mut emu = emu_with(&STORE);
+        assert_eq!(emu.run_until(0), Ok((0, StopReason::TickLimit)));
+        assert_eq!(emu.run_until(2), Ok((2, StopReason::TickLimit)));
+        assert_eq!(emu.pc, 0x204);
+
+        let mut emu = Emu::new(Quirks::SUPER_CHIP);
+        emu.load_rom(&[0x60, 0x01, 0x00, 0xFD]).unwrap();
+        assert_eq!(emu.run_until(10), Ok((2, StopReason::Exited)));
+        assert_eq!(emu.run_until(10), Ok((0, StopReason::Exited)));
+        assert_eq!(emu.step(), Ok(StopReason::Exited));
+    }
+
+    #[test]
+    fn watches_survive_reset_and_load_state() {
+        let mut emu = emu_with(&STORE);
+        let state = emu.save_state();
+        emu.add_breakpoint(0x204);
+        emu.add_opcode_breakpoint(0xF000, 0xD000);
+        emu.add_watchpoint(0x300, Access::Write);
+        emu.watch_register(Register::I);
+
+        let check = |emu: &Emu| {
+            assert_eq!(emu.breakpoints(), &BTreeSet::from([0x204]));
+            assert_eq!(emu.opcode_breakpoints(), [(0xF000, 0xD000)]);
+            assert_eq!(emu.watchpoints(), &BTreeMap::from([(0x300, Access::Write)]));
+            assert_eq!(emu.watched_registers(), &BTreeSet::from([Register::I]));
+        };
+        emu.reset();
+        check(&emu);
+        // The state was saved before there were any watches, but they belong to the debugger, not the state.
+        emu.load_state(&state).unwrap();
+        check(&emu);
+
+        emu.clear_watches();
+        assert!(emu.breakpoints().is_empty() && emu.watchpoints().is_empty() && emu.watched_registers().is_empty());
+        assert!(emu.opcode_breakpoints().is_empty());
+    }
+}
@@ -0,0 +1,252 @@
+// Turns CHIP-8 machine code back into readable assembly.
+//
+// Two syntaxes are supported. Cowgod's is the classic one from his technical reference
+// (`LD V1, 0x0A`, `DRW V0, V1, 5`). Octo's is the one used by the Octo assembler (`v1 := 0x0A`).
+// The SUPER-CHIP and XO-CHIP instructions are decoded too, with names in the same style.
+use crate::opcode::Op;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Cowgod,
+    Octo,
+}
+
+// One disassembled instruction, or a run of bytes that are not an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16, // Where the first byte is in memory.
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+// Disassembles every instruction in `data` one after another, with the first byte at `start_addr`.
+// To disassemble part of RAM, pass in a slice of Emu::memory and the address it starts from.
+// xo_chip says whether F000 NNNN is an instruction, as it is for Op::decode.
+pub fn disassemble(data: &[u8], start_addr: u16, syntax: Syntax, xo_chip: bool) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let addr = start_addr.wrapping_add(pos as u16);
+        let (text, size) = match Op::decode_bytes(&data[pos..], xo_chip) {
+            Some(op) => (format_op(&op, syntax), op.size()),
+            None => {
+                // Not an instruction, so show it as data. Opcodes are 2 bytes, unless we are at the very end.
+                let size = 2.min(data.len() - pos);
+                (format_data(&data[pos..pos + size], syntax), size)
+            },
+        };
+
+        lines.push(Line { addr, bytes: data[pos..pos + size].to_vec(), text });
+        pos += size;
+    }
+
+    lines
+}
+
+// Writes out bytes that are not code.
+pub fn format_data(bytes: &[u8], syntax: Syntax) -> String {
+    let values: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+    match syntax {
+        Syntax::Cowgod => format!("db {}", values.join(", ")),
+        Syntax::Octo => values.join(" "),
+    }
+}
+
+// Writes out a single instruction.
+pub fn format_op(op: &Op, syntax: Syntax) -> String {
+    format_op_with(op, syntax, &hex_addr)
+}
+
+// Writes out a single instruction, using `addr` to write any addresses in it.
+// This lets the control flow disassembler put labels in place of addresses.
+pub(crate) fn format_op_with(op: &Op, syntax: Syntax, addr: &dyn Fn(u16, usize) -> String) -> String {
+    match syntax {
+        Syntax::Cowgod => format_cowgod(op, addr),
+        Syntax::Octo => format_octo(op, addr),
+    }
+}
+
+// Writes an address as hex with the given number of digits.
+fn hex_addr(addr: u16, digits: usize) -> String {
+    format!("0x{:0width$X}", addr, width = digits)
+}
+
+fn format_cowgod(op: &Op, addr: &dyn Fn(u16, usize) -> String) -> String {
+    let v = |reg: &u8| format!("V{:X}", reg);
+    match op {
+        Op::Sys(nnn) => format!("SYS {}", addr(*nnn, 3)),
+        Op::Cls => "CLS".to_string(),
+        Op::Ret => "RET".to_string(),
+        Op::Jump(nnn) => format!("JP {}", addr(*nnn, 3)),
+        Op::Call(nnn) => format!("CALL {}", addr(*nnn, 3)),
+        Op::SkipEqByte(x, nn) => format!("SE {}, 0x{:02X}", v(x), nn),
+        Op::SkipNeByte(x, nn) => format!("SNE {}, 0x{:02X}", v(x), nn),
+        Op::SkipEqReg(x, y) => format!("SE {}, {}", v(x), v(y)),
+        Op::SetByte(x, nn) => format!("LD {}, 0x{:02X}", v(x), nn),
+        Op::AddByte(x, nn) => format!("ADD {}, 0x{:02X}", v(x), nn),
+        Op::SetReg(x, y) => format!("LD {}, {}", v(x), v(y)),
+        Op::Or(x, y) => format!("OR {}, {}", v(x), v(y)),
+        Op::And(x, y) => format!("AND {}, {}", v(x), v(y)),
+        Op::Xor(x, y) => format!("XOR {}, {}", v(x), v(y)),
+        Op::Add(x, y) => format!("ADD {}, {}", v(x), v(y)),
+        Op::Sub(x, y) => format!("SUB {}, {}", v(x), v(y)),
+        Op::Shr(x, y) => format!("SHR {}, {}", v(x), v(y)),
+        Op::SubN(x, y) => format!("SUBN {}, {}", v(x), v(y)),
+        Op::Shl(x, y) => format!("SHL {}, {}", v(x), v(y)),
+        Op::SkipNeReg(x, y) => format!("SNE {}, {}", v(x), v(y)),
+        Op::SetI(nnn) => format!("LD I, {}", addr(*nnn, 3)),
+        Op::JumpV0(nnn) => format!("JP V0, {}", addr(*nnn, 3)),
+        Op::Rand(x, nn) => format!("RND {}, 0x{:02X}", v(x), nn),
+        Op::Draw(x, y, n) => format!("DRW {}, {}, {}", v(x), v(y), n),
+        Op::SkipKey(x) => format!("SKP {}", v(x)),
+        Op::SkipNotKey(x) => format!("SKNP {}", v(x)),
+        Op::GetDelay(x) => format!("LD {}, DT", v(x)),
+        Op::WaitKey(x) => format!("LD {}, K", v(x)),
+        Op::SetDelay(x) => format!("LD DT, {}", v(x)),
+        Op::SetSound(x) => format!("LD ST, {}", v(x)),
+        Op::AddI(x) => format!("ADD I, {}", v(x)),
+        Op::Font(x) => format!("LD F, {}", v(x)),
+        Op::Bcd(x) => format!("LD B, {}", v(x)),
+        Op::Store(x) => format!("LD [I], {}", v(x)),
+        Op::Load(x) => format!("LD {}, [I]", v(x)),
+        Op::ScrollDown(n) => format!("SCD {}", n),
+        Op::ScrollRight => "SCR".to_string(),
+        Op::ScrollLeft => "SCL".to_string(),
+        Op::Exit => "EXIT".to_string(),
+        Op::Lores => "LOW".to_string(),
+        Op::Hires => "HIGH".to_string(),
+        Op::BigFont(x) => format!("LD HF, {}", v(x)),
+        Op::SaveFlags(x) => format!("LD R, {}", v(x)),
+        Op::LoadFlags(x) => format!("LD {}, R", v(x)),
+        Op::ScrollUp(n) => format!("SCU {}", n),
+        Op::StoreRange(x, y) => format!("LD [I], {}-{}", v(x), v(y)),
+        Op::LoadRange(x, y) => format!("LD {}-{}, [I]", v(x), v(y)),
+        Op::LongI(nnnn) => format!("LD I, LONG {}", addr(*nnnn, 4)),
+        Op::Plane(n) => format!("PLANE {}", n),
+        Op::Audio => "AUDIO".to_string(),
+        Op::Pitch(x) => format!("PITCH {}", v(x)),
+    }
+}
+
+fn format_octo(op: &Op, addr: &dyn Fn(u16, usize) -> String) -> String {
+    let v = |reg: &u8| format!("v{:x}", reg);
+    match op {
+        Op::Sys(nnn) => format!("native {}", addr(*nnn, 3)),
+        Op::Cls => "clear".to_string(),
+        Op::Ret => "return".to_string(),
+        Op::Jump(nnn) => format!("jump {}", addr(*nnn, 3)),
+        Op::Call(nnn) => format!(":call {}", addr(*nnn, 3)),
+        // Octo's `if ... then` runs the next instruction when the condition holds,
+        // so each skip is written with the opposite condition.
+        Op::SkipEqByte(x, nn) => format!("if {} != 0x{:02X} then", v(x), nn),
+        Op::SkipNeByte(x, nn) => format!("if {} == 0x{:02X} then", v(x), nn),
+        Op::SkipEqReg(x, y) => format!("if {} != {} then", v(x), v(y)),
+        Op::SetByte(x, nn) => format!("{} := 0x{:02X}", v(x), nn),
+        Op::AddByte(x, nn) => format!("{} += 0x{:02X}", v(x), nn),
+        Op::SetReg(x, y) => format!("{} := {}", v(x), v(y)),
+        Op::Or(x, y) => format!("{} |= {}", v(x), v(y)),
+        Op::And(x, y) => format!("{} &= {}", v(x), v(y)),
+        Op::Xor(x, y) => format!("{} ^= {}", v(x), v(y)),
+        Op::Add(x, y) => format!("{} += {}", v(x), v(y)),
+        Op::Sub(x, y) => format!("{} -= {}", v(x), v(y)),
+        Op::Shr(x, y) => format!("{} >>= {}", v(x), v(y)),
+        Op::SubN(x, y) => format!("{} =- {}", v(x), v(y)),
+        Op::Shl(x, y) => format!("{} <<= {}", v(x), v(y)),
+        Op::SkipNeReg(x, y) => format!("if {} == {} then", v(x), v(y)),
+        Op::SetI(nnn) => format!("i := {}", addr(*nnn, 3)),
+        Op::JumpV0(nnn) => format!("jump0 {}", addr(*nnn, 3)),
+        Op::Rand(x, nn) => format!("{} := random 0x{:02X}", v(x), nn),
+        Op::Draw(x, y, n) => format!("sprite {} {} {}", v(x), v(y), n),
+        Op::SkipKey(x) => format!("if {} -key then", v(x)),
+        Op::SkipNotKey(x) => format!("if {} key then", v(x)),
+        Op::GetDelay(x) => format!("{} := delay", v(x)),
+        Op::WaitKey(x) => format!("{} := key", v(x)),
+        Op::SetDelay(x) => format!("delay := {}", v(x)),
+        Op::SetSound(x) => format!("buzzer := {}", v(x)),
+        Op::AddI(x) => format!("i += {}", v(x)),
+        Op::Font(x) => format!("i := hex {}", v(x)),
+        Op::Bcd(x) => format!("bcd {}", v(x)),
+        Op::Store(x) => format!("save {}", v(x)),
+        Op::Load(x) => format!("load {}", v(x)),
+        Op::ScrollDown(n) => format!("scroll-down {}", n),
+        Op::ScrollRight => "scroll-right".to_string(),
+        Op::ScrollLeft => "scroll-left".to_string(),
+        Op::Exit => "exit".to_string(),
+        Op::Lores => "lores".to_string(),
+        Op::Hires => "hires".to_string(),
+        Op::BigFont(x) => format!("i := bighex {}", v(x)),
+        Op::SaveFlags(x) => format!("saveflags {}", v(x)),
+        Op::LoadFlags(x) => format!("loadflags {}", v(x)),
+        Op::ScrollUp(n) => format!("scroll-up {}", n),
+        Op::StoreRange(x, y) => format!("save {} - {}", v(x), v(y)),
+        Op::LoadRange(x, y) => format!("load {} - {}", v(x), v(y)),
+        Op::LongI(nnnn) => format!("i := long {}", addr(*nnnn, 4)),
+        Op::Plane(n) => format!("plane {}", n),
+        Op::Audio => "audio".to_string(),
+        Op::Pitch(x) => format!("pitch := {}", v(x)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Formats an opcode in both syntaxes.
+    fn both(bytes: &[u8]) -> (String, String) {
+        let op = Op::decode_bytes(bytes, true).unwrap();
+        (format_op(&op, Syntax::Cowgod), format_op(&op, Syntax::Octo))
+    }
+
+    #[test]
+    fn chip8_instructions() {
+        let cases: [(&[u8], &str, &str); 12] = [
+            (&[0x00, 0xE0], "CLS", "clear"),
+            (&[0x12, 0x34], "JP 0x234", "jump 0x234"),
+            (&[0x23, 0x00], "CALL 0x300", ":call 0x300"),
+            (&[0x31, 0x0A], "SE V1, 0x0A", "if v1 != 0x0A then"),
+            (&[0x9A, 0xB0], "SNE VA, VB", "if va == vb then"),
+            (&[0x6A, 0x02], "LD VA, 0x02", "va := 0x02"),
+            (&[0x8C, 0xD7], "SUBN VC, VD", "vc =- vd"),
+            (&[0xA2, 0xEA], "LD I, 0x2EA", "i := 0x2EA"),
+            (&[0xB3, 0x00], "JP V0, 0x300", "jump0 0x300"),
+            (&[0xD0, 0x15], "DRW V0, V1, 5", "sprite v0 v1 5"),
+            (&[0xE1, 0xA1], "SKNP V1", "if v1 key then"),
+            (&[0xF2, 0x65], "LD V2, [I]", "load v2"),
+        ];
+        for (bytes, cowgod, octo) in cases {
+            assert_eq!(both(bytes), (cowgod.to_string(), octo.to_string()));
+        }
+    }
+
+    #[test]
+    fn super_chip_and_xo_chip_instructions() {
+        let cases: [(&[u8], &str, &str); 7] = [
+            (&[0x00, 0xC4], "SCD 4", "scroll-down 4"),
+            (&[0x00, 0xFF], "HIGH", "hires"),
+            (&[0xF3, 0x30], "LD HF, V3", "i := bighex v3"),
+            (&[0x52, 0x53], "LD V2-V5, [I]", "load v2 - v5"),
+            (&[0xF0, 0x00, 0x12, 0x34], "LD I, LONG 0x1234", "i := long 0x1234"),
+            (&[0xF2, 0x01], "PLANE 2", "plane 2"),
+            (&[0xF4, 0x3A], "PITCH V4", "pitch := v4"),
+        ];
+        for (bytes, cowgod, octo) in cases {
+            assert_eq!(both(bytes), (cowgod.to_string(), octo.to_string()));
+        }
+    }
+
+    #[test]
+    fn data_between_instructions() {
+        let data = [0x00, 0xE0, 0xFF, 0xFF, 0xF0, 0x00, 0x12, 0x34, 0x01];
+
+        let lines = disassemble(&data, 0x200, Syntax::Cowgod, true);
+        let text: Vec<(u16, &str)> = lines.iter().map(|line| (line.addr, line.text.as_str())).collect();
+        assert_eq!(text, [(0x200, "CLS"), (0x202, "db 0xFF, 0xFF"), (0x204, "LD I, LONG 0x1234"), (0x208, "db 0x01")]);
+        assert_eq!(lines[2].bytes, [0xF0, 0x00, 0x12, 0x34]);
+
+        // Without XO-CHIP, F000 is data and the word after it is an instruction of its own.
+        let lines = disassemble(&data, 0x200, Syntax::Octo, false);
+        let text: Vec<(u16, &str)> = lines.iter().map(|line| (line.addr, line.text.as_str())).collect();
+        assert_eq!(text, [(0x200, "clear"), (0x202, "0xFF 0xFF"), (0x204, "0xF0 0x00"), (0x206, "jump 0x234"), (0x208, "0x01")]);
+    }
+}
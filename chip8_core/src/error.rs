@@ -0,0 +1,41 @@
+use std::fmt;
+
+// Everything that can go wrong while the CPU is running a program.
+// These are returned from Emu::tick so that a frontend can show what happened and halt,
+// instead of the whole process panicking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmuError {
+    // The opcode at this address is not part of the instruction set.
+    InvalidOpcode { pc: u16, op: u16 },
+    // A subroutine was called while the stack was already full.
+    StackOverflow,
+    // A subroutine returned while the stack was empty.
+    StackUnderflow,
+    // An instruction tried to read or write past the end of RAM.
+    MemoryOutOfBounds { addr: usize },
+    // A ROM was too big to fit in RAM after START_ADDR.
+    RomTooLarge { size: usize, max: usize },
+    // A save state could not be restored because it is damaged or from an incompatible version.
+    InvalidSaveState { reason: &'static str },
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::InvalidOpcode { pc, op } => {
+                write!(f, "invalid opcode {:#06X} at address {:#05X}", op, pc)
+            },
+            EmuError::StackOverflow => write!(f, "stack overflow"),
+            EmuError::StackUnderflow => write!(f, "stack underflow"),
+            EmuError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at address {:#X}", addr)
+            },
+            EmuError::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, but at most {} bytes fit in memory", size, max)
+            },
+            EmuError::InvalidSaveState { reason } => write!(f, "invalid save state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
@@ -0,0 +1,240 @@
+// Control flow disassembly. Instead of decoding every byte in order, we start where the program starts and
+// follow every jump, call and skip, so that only bytes that can actually run are treated as code.
+// Everything else (sprites, tables, padding) is written out as data. Subroutines and jump targets get
+// labels, and the output can be fed back into the assembler to produce the same bytes.
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::disasm::{self, Syntax};
+use crate::opcode::Op;
+use crate::EmuError;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+// What the analysis found out about a program.
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    // The address of every instruction that can be reached, and the instruction there.
+    pub code: BTreeMap<u16, Op>,
+    // Addresses that are called with 2NNN.
+    pub subroutines: BTreeSet<u16>,
+    // Addresses that are jumped to with 1NNN or BNNN.
+    pub jump_targets: BTreeSet<u16>,
+    // Addresses that I is pointed at, which are usually sprites or other data.
+    pub data_refs: BTreeSet<u16>,
+}
+
+// Walks a program from its first byte at start_addr, following every path the program could take.
+// xo_chip says whether F000 NNNN is an instruction, as it is for Op::decode.
+pub fn analyze(data: &[u8], start_addr: u16, xo_chip: bool) -> Analysis {
+    let mut analysis = Analysis::default();
+    let end_addr = start_addr as usize + data.len();
+    let in_program = |addr: u16| addr >= start_addr && (addr as usize) < end_addr;
+    let decode_at = |addr: u16| Op::decode_bytes(&data[(addr - start_addr) as usize..], xo_chip);
+
+    // Addresses still to visit. Every path is followed until it returns, jumps somewhere we have
+    // already been, or runs into something that is not an instruction.
+    let mut pending = vec![start_addr];
+    while let Some(addr) = pending.pop() {
+        if !in_program(addr) || analysis.code.contains_key(&addr) {
+            continue;
+        }
+        let op = match decode_at(addr) {
+            Some(op) => op,
+            None => continue,
+        };
+        analysis.code.insert(addr, op);
+        let next = addr.wrapping_add(op.size() as u16);
+
+        match op {
+            Op::Jump(target) => {
+                analysis.jump_targets.insert(target);
+                pending.push(target);
+            },
+            Op::JumpV0(target) => {
+                // We can't know what V0 will be, but jump tables nearly always start at the target.
+                analysis.jump_targets.insert(target);
+                pending.push(target);
+            },
+            Op::Call(target) => {
+                analysis.subroutines.insert(target);
+                pending.push(target);
+                pending.push(next);
+            },
+            Op::Ret | Op::Exit => {},
+            Op::SkipEqByte(..)
+            | Op::SkipNeByte(..)
+            | Op::SkipEqReg(..)
+            | Op::SkipNeReg(..)
+            | Op::SkipKey(_)
+            | Op::SkipNotKey(_) => {
+                // Either the next instruction runs, or it is skipped. It might be 4 bytes long.
+                pending.push(next);
+                if in_program(next) {
+                    let skipped = decode_at(next).map_or(2, |op| op.size());
+                    pending.push(next.wrapping_add(skipped as u16));
+                }
+            },
+            Op::SetI(target) | Op::LongI(target) => {
+                analysis.data_refs.insert(target);
+                pending.push(next);
+            },
+            _ => pending.push(next),
+        }
+    }
+
+    analysis
+}
+
+// Disassembles a program using control flow analysis, giving source that can be assembled again.
+// Fails if the program runs past the end of the 16-bit address space, where it couldn't be labelled.
+pub fn listing(data: &[u8], start_addr: u16, syntax: Syntax, xo_chip: bool) -> Result<String, EmuError> {
+    let max = 0x10000 - start_addr as usize;
+    if data.len() > max {
+        return Err(EmuError::RomTooLarge { size: data.len(), max });
+    }
+
+    let analysis = analyze(data, start_addr, xo_chip);
+    let end_addr = start_addr as usize + data.len();
+
+    // Work out which instructions get their own line. Programs occasionally jump into the middle of an
+    // instruction. When two instructions overlap like that, the first one wins and the rest become data.
+    let mut instructions: BTreeMap<u16, Op> = BTreeMap::new();
+    let mut covered_until = start_addr as usize;
+    for (&addr, op) in &analysis.code {
+        let end = addr as usize + op.size();
+        if (addr as usize) >= covered_until && end <= end_addr {
+            instructions.insert(addr, *op);
+            covered_until = end;
+        }
+    }
+
+    // Labels can only go where a line starts, so that assembling the output puts them back in the same place.
+    // An address inside an instruction keeps its plain number. Later names win, so an address that is
+    // both called and pointed to by I is named as a subroutine.
+    let is_line_start = |addr: u16| {
+        let inside_instruction = instructions
+            .range(..addr)
+            .next_back()
+            .is_some_and(|(&start, op)| (addr as usize) < start as usize + op.size());
+        addr >= start_addr && (addr as usize) < end_addr && !inside_instruction
+    };
+    let mut labels = BTreeMap::new();
+    let named = [
+        (&analysis.data_refs, "data"),
+        (&analysis.jump_targets, "label"),
+        (&analysis.subroutines, "sub"),
+    ];
+    for (addrs, prefix) in named {
+        for &addr in addrs.iter().filter(|&&addr| is_line_start(addr)) {
+            labels.insert(addr, format!("{}_{:03X}", prefix, addr));
+        }
+    }
+
+    let addr_name = |addr: u16, digits: usize| match labels.get(&addr) {
+        Some(label) => label.clone(),
+        None => format!("0x{:0width$X}", addr, width = digits),
+    };
+
+    let mut out = String::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let addr = start_addr + pos as u16;
+        if let Some(label) = labels.get(&addr) {
+            match syntax {
+                Syntax::Cowgod => out.push_str(&format!("{}:\n", label)),
+                Syntax::Octo => out.push_str(&format!(": {}\n", label)),
+            }
+        }
+
+        if let Some(op) = instructions.get(&addr) {
+            out.push_str(&format!("    {}\n", disasm::format_op_with(op, syntax, &addr_name)));
+            pos += op.size();
+            continue;
+        }
+
+        // Gather data up to the next instruction or label, a few bytes per line.
+        let mut end = pos + 1;
+        while end < data.len() && end - pos < DATA_BYTES_PER_LINE {
+            let next_addr = start_addr + end as u16;
+            if instructions.contains_key(&next_addr) || labels.contains_key(&next_addr) {
+                break;
+            }
+            end += 1;
+        }
+        out.push_str(&format!("    {}\n", disasm::format_data(&data[pos..end], syntax)));
+        pos = end;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::START_ADDR;
+
+    // A program that calls a subroutine, skips over a jump, points I at a sprite and loops forever.
+    // The sprite and the byte after the subroutine's return can't run, so they are data.
+    const PROGRAM: [u8; 18] = [
+        0x22, 0x0C, // 200: call sub_20C
+        0x30, 0x01, // 202: skip the next instruction if V0 == 1
+        0x12, 0x0A, // 204: jump to label_20A
+        0xA2, 0x10, // 206: I = data_210
+        0xD0, 0x11, // 208: draw
+        0x12, 0x0A, // 20A: jump to itself
+        0x60, 0x01, // 20C: V0 = 1
+        0x00, 0xEE, // 20E: return
+        0xFF, 0x81, // 210: the sprite
+    ];
+
+    #[test]
+    fn finds_code_and_labels() {
+        let analysis = analyze(&PROGRAM, START_ADDR, false);
+        let code: Vec<u16> = analysis.code.keys().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20C, 0x20E]);
+        assert_eq!(analysis.subroutines, BTreeSet::from([0x20C]));
+        assert_eq!(analysis.jump_targets, BTreeSet::from([0x20A]));
+        assert_eq!(analysis.data_refs, BTreeSet::from([0x210]));
+    }
+
+    #[test]
+    fn listing_separates_code_from_data() {
+        let listing = listing(&PROGRAM, START_ADDR, Syntax::Cowgod, false).unwrap();
+        assert_eq!(
+            listing,
+            "    CALL sub_20C\n    SE V0, 0x01\n    JP label_20A\n    LD I, data_210\n    DRW V0, V1, 1\n\
+             label_20A:\n    JP label_20A\n\
+             sub_20C:\n    LD V0, 0x01\n    RET\n\
+             data_210:\n    db 0xFF, 0x81\n"
+        );
+    }
+
+    #[test]
+    fn skips_can_jump_over_long_instructions() {
+        // The skip can land on F000 NNNN or after it, so both are code. Without XO-CHIP, F000 is data and
+        // the skip lands in the middle of what would have been a long instruction.
+        let program = [0x30, 0x01, 0xF0, 0x00, 0x12, 0x06, 0x00, 0xEE];
+        let code: Vec<u16> = analyze(&program, START_ADDR, true).code.keys().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x206]);
+        let code: Vec<u16> = analyze(&program, START_ADDR, false).code.keys().copied().collect();
+        assert_eq!(code, [0x200, 0x204, 0x206]);
+    }
+
+    #[test]
+    fn data_that_cant_run_is_left_alone() {
+        // The jump goes over two invalid opcodes, and nothing jumps to the bytes after the return.
+        let program = [0x12, 0x04, 0xFF, 0xFF, 0x00, 0xEE, 0x00, 0xE0];
+        let listing = listing(&program, START_ADDR, Syntax::Octo, false).unwrap();
+        assert_eq!(listing, "    jump label_204\n    0xFF 0xFF\n: label_204\n    return\n    0x00 0xE0\n");
+    }
+
+    #[test]
+    fn programs_must_fit_in_the_address_space() {
+        let max = 0x10000 - START_ADDR as usize;
+        assert!(listing(&vec![0; max], START_ADDR, Syntax::Cowgod, false).is_ok());
+        assert_eq!(
+            listing(&vec![0; max + 1], START_ADDR, Syntax::Cowgod, false),
+            Err(EmuError::RomTooLarge { size: max + 1, max })
+        );
+    }
+}
@@ -1,12 +1,47 @@
+pub mod asm;
+mod audio;
+pub mod debug;
+pub mod disasm;
+mod error;
+pub mod flow;
+pub mod octo;
+pub mod opcode;
+mod quirks;
+mod rng;
+mod savestate;
+pub mod script;
+mod state;
+pub mod trace;
+
+pub use audio::AudioSink;
+use debug::{Access, Watches};
+pub use error::EmuError;
+pub use quirks::{IndexIncrement, Quirks};
+use opcode::Op;
+use rng::Rng;
+pub use state::CpuState;
+use trace::{TraceRegisters, Tracer};
+
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128; // SUPER-CHIP high resolution mode.
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+const SCREEN_SIZE: usize = HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT; // Big enough for either resolution.
 
 const RAM_SIZE: usize = 4096;
-const NUM_REGS: usize = 16; // The amount of V Registers the program uses.
-const STACK_SIZE: usize = 16;
-const NUM_KEYS: usize = 16;
-const START_ADDR: u16 = 0x200; // The memory address of the first byte. 
+const XO_RAM_SIZE: usize = 65536; // XO-CHIP can address a full 64 KiB.
+pub const NUM_REGS: usize = 16; // The amount of V Registers the program uses.
+pub const STACK_SIZE: usize = 16;
+pub const NUM_KEYS: usize = 16;
+pub const START_ADDR: u16 = 0x200; // The memory address of the first byte. 
+pub const MAX_ROM_SIZE: usize = RAM_SIZE - START_ADDR as usize; // Everything from START_ADDR to the end of RAM.
+pub const XO_MAX_ROM_SIZE: usize = XO_RAM_SIZE - START_ADDR as usize;
 const FONTSET_SIZE: usize = 80;
+const BIG_FONTSET_ADDR: usize = FONTSET_SIZE; // The large font is stored in RAM straight after the small one.
+const BIG_FONTSET_SIZE: usize = 160;
+const NUM_RPL_FLAGS: usize = 16;
+pub const AUDIO_PATTERN_SIZE: usize = 16; // XO-CHIP audio patterns are 128 one-bit samples.
+const DEFAULT_PITCH: u8 = 64; // The pitch at which a pattern plays back at 4000 samples per second.
 
 // Character sprite data using hexadecimal numbers.
 const FONTSET: [u8; FONTSET_SIZE] = [
@@ -28,10 +63,36 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80 // F
 ];
 
+// SUPER-CHIP's large character sprites. Each one is 8 pixels wide and 10 pixels tall.
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0 // F
+];
+
 pub struct Emu {
     pc: u16, // Program Counter (keeps track of current instruction index)
-    ram: [u8; RAM_SIZE], // RAM. Fixed size array of 4096 unsigned 8-bit integers.
-    screen: [bool; SCREEN_WIDTH * SCREEN_HEIGHT], // Array of 2048 booleans to determine where a pixel should be black or white.
+    ram: Vec<u8>, // RAM. 4096 unsigned 8-bit integers, or 65536 in XO-CHIP mode.
+    // Array of pixels. Each bit of a pixel is one bitplane, so plain CHIP-8 pixels are either 0 or 1,
+    // and XO-CHIP pixels can be any of 4 colors (0 to 3).
+    // Only the first width * height pixels are used, one row after another, at the current resolution.
+    screen: [u8; SCREEN_SIZE],
+    display: [bool; SCREEN_SIZE], // Whether each pixel of screen is lit at all. Kept up to date for display().
+    planes: u8, // Bitmask of the planes that drawing, clearing and scrolling affect. Set by XO-CHIP's FN01.
+    hires: bool, // Whether the SUPER-CHIP 128x64 mode is switched on.
     v_reg: [u8; NUM_REGS], // V Registers are 8-bits, and we have 16 of them. 
     i_reg: u16,
     sp: u16, // Stack Pointer. Refers to the top of our stack. 
@@ -39,36 +100,82 @@ pub struct Emu {
     keys: [bool; NUM_KEYS],
     dt: u8, // Delay Timer. Once at 0, an action is performed.
     st: u8, // Sound Timer. Once at 0, audio is played. 
+    rom: Vec<u8>, // The last loaded program, kept so that reset can put it back into RAM.
+    quirks: Quirks, // Which interpreter's behavior to follow for the ambiguous opcodes.
+    vblank: bool, // Set at the start of every frame. Used by the display wait quirk.
+    rpl: [u8; NUM_RPL_FLAGS], // SUPER-CHIP user flags. These survive a reset, like they did on the HP-48.
+    exited: bool, // Set by 00FD. Once the program has exited, tick does nothing.
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE], // XO-CHIP sample buffer that is played while the sound timer is running.
+    pitch: u8, // XO-CHIP playback rate of the audio pattern.
+    seed: u64, // The seed the random number generator starts from. Reset goes back to it.
+    rng: Rng, // Random numbers for CXNN.
+    audio: Option<Box<dyn AudioSink>>, // Where the buzzer goes, if the frontend wants sound.
+    buzzer_on: bool, // What we last told the audio sink, so we only call it when this changes.
+    watches: Watches, // Breakpoints and watchpoints for run_until.
+    tracer: Option<Tracer>, // Where every instruction is recorded, if tracing is switched on.
+}
+
+impl Default for Emu {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
 }
 
 // Implementation block for Emu struct. Allowing us to add our constructor method. 
 impl Emu {
-    pub fn new() -> Self {
-        // Initialise all values to zero. Except for PC. 
+    // Creates an emulator whose random numbers are different every run.
+    pub fn new(quirks: Quirks) -> Self {
+        Self::with_seed(quirks, Rng::random_seed())
+    }
+
+    // Creates an emulator whose random numbers come from the given seed,
+    // so that the same program with the same input always behaves exactly the same way.
+    pub fn with_seed(quirks: Quirks, seed: u64) -> Self {
+        // Initialise all values to zero. Except for PC, the quirks and the seed we were given. 
         let mut new_emu = Self {
             pc: START_ADDR,
-            ram: [0; RAM_SIZE],
-            screen: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            ram: vec![0; if quirks.xo_chip { XO_RAM_SIZE } else { RAM_SIZE }],
+            screen: [0; SCREEN_SIZE],
+            display: [false; SCREEN_SIZE],
+            planes: 1,
+            hires: false,
             v_reg: [0; NUM_REGS],
             i_reg: 0,
             sp: 0,
             stack: [0; STACK_SIZE],
             keys: [false; NUM_KEYS],
             dt: 0,
-            st: 0
+            st: 0,
+            rom: Vec::new(),
+            quirks,
+            vblank: false,
+            rpl: [0; NUM_RPL_FLAGS],
+            exited: false,
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
+            seed,
+            rng: Rng::new(seed),
+            audio: None,
+            buzzer_on: false,
+            watches: Watches::default(),
+            tracer: None,
         };
 
         // ..FONTSET_SIZE specifies all array indexes from 0 up to the size of our character sprite.
         // Then we copy the values of FONTSET into RAM.
         new_emu.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        new_emu.ram[BIG_FONTSET_ADDR..BIG_FONTSET_ADDR + BIG_FONTSET_SIZE].copy_from_slice(&BIG_FONTSET);
         
         new_emu
     }
 
     pub fn reset(&mut self) {
         self.pc = START_ADDR;
-        self.ram = [0; RAM_SIZE];
-        self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT];
+        self.ram.fill(0);
+        self.screen = [0; SCREEN_SIZE];
+        self.display = [false; SCREEN_SIZE];
+        self.planes = 1;
+        self.hires = false;
         self.v_reg = [0; NUM_REGS];
         self.i_reg = 0;
         self.sp = 0;
@@ -76,17 +183,202 @@ impl Emu {
         self.keys = [false; NUM_KEYS];
         self.dt = 0;
         self.st = 0;
+        self.vblank = false;
+        self.exited = false;
+        self.audio_pattern = [0; AUDIO_PATTERN_SIZE];
+        self.pitch = DEFAULT_PITCH;
+        self.rng = Rng::new(self.seed);
+        self.set_buzzer(false);
         self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        self.ram[BIG_FONTSET_ADDR..BIG_FONTSET_ADDR + BIG_FONTSET_SIZE].copy_from_slice(&BIG_FONTSET);
+
+        // Put the loaded program back, so that reset restarts the game rather than wiping it.
+        let start = START_ADDR as usize;
+        self.ram[start..start + self.rom.len()].copy_from_slice(&self.rom);
+    }
+
+    // Loads a program into RAM at START_ADDR and resets the machine so that it runs from the beginning.
+    // ROMs that would not fit between START_ADDR and the end of RAM are rejected and the current program is kept.
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        let max = self.ram.len() - START_ADDR as usize;
+        if data.len() > max {
+            return Err(EmuError::RomTooLarge { size: data.len(), max });
+        }
+
+        self.rom = data.to_vec();
+        self.reset();
+        Ok(())
+    }
+
+    // The width and height of the screen at the current resolution.
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT)
+        } else {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        }
+    }
+
+    // Whether every pixel on screen is lit, at the current resolution, one row after another.
+    // The pixel at (x, y) is at index x + width * y.
+    pub fn display(&self) -> &[bool] {
+        let (width, height) = self.resolution();
+        &self.display[..width * height]
+    }
+
+    // The value of every pixel on screen, at the current resolution, one row after another.
+    // Bit 0 is the first bitplane and bit 1 is the second, so outside of XO-CHIP each pixel is 0 or 1.
+    pub fn planes(&self) -> &[u8] {
+        let (width, height) = self.resolution();
+        &self.screen[..width * height]
+    }
+
+    // All of RAM, read only. 4096 bytes, or 65536 in XO-CHIP mode.
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // The XO-CHIP audio pattern. Each bit is one sample, starting from the highest bit of the first byte.
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.audio_pattern
+    }
+
+    // How many samples of the audio pattern are played per second, as set by FX3A.
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    // Whether the program has stopped itself with 00FD.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    // Gives the emulator somewhere to send the buzzer. Replaces any sink that was set before.
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio = Some(sink);
+        self.buzzer_on = false;
     }
 
-    pub fn push(&mut self, val: u16) {
+    // Whether the buzzer is sounding during the current frame.
+    pub fn is_buzzer_on(&self) -> bool {
+        self.buzzer_on
+    }
+
+    // Starts recording every instruction that runs. Replaces any tracer that was set before.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    // The tracer, to look at what it has recorded so far.
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    // Stops tracing, handing the tracer back so it can be finished.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    // Which interpreter's behavior this emulator is following.
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // The seed the random number generator started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // A copy of the CPU registers, stack and timers.
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            i: self.i_reg,
+            sp: self.sp,
+            v: self.v_reg,
+            stack: self.stack,
+            dt: self.dt,
+            st: self.st,
+        }
+    }
+
+    // Overwrites the CPU registers, stack and timers, for debuggers. The stack pointer is capped at STACK_SIZE.
+    pub fn set_cpu_state(&mut self, state: CpuState) {
+        self.pc = state.pc;
+        self.i_reg = state.i;
+        self.sp = state.sp.min(STACK_SIZE as u16);
+        self.v_reg = state.v;
+        self.stack = state.stack;
+        self.dt = state.dt;
+        self.st = state.st;
+    }
+
+    // Overwrites RAM starting from addr, for debuggers. Nothing is written if any of it is past the end of RAM.
+    pub fn write_memory(&mut self, addr: usize, data: &[u8]) -> Result<(), EmuError> {
+        let Some(end) = addr.checked_add(data.len()).filter(|&end| end <= self.ram.len()) else {
+            // The first byte that doesn't fit.
+            return Err(EmuError::MemoryOutOfBounds { addr: addr.max(self.ram.len()) });
+        };
+        self.ram[addr..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    // The instruction that the next tick will run, or None if there isn't a valid one at pc.
+    pub fn next_op(&self) -> Option<Op> {
+        self.ram.get(self.pc as usize..).and_then(|bytes| Op::decode_bytes(bytes, self.quirks.xo_chip))
+    }
+
+    // Tells the emulator whether one of the 16 hex keys (0x0 to 0xF) is held down.
+    // Indexes outside of the keypad are ignored.
+    pub fn keypress(&mut self, idx: usize, pressed: bool) {
+        if let Some(key) = self.keys.get_mut(idx) {
+            *key = pressed;
+        }
+    }
+
+    pub fn push(&mut self, val: u16) -> Result<(), EmuError> {
+        // The stack only has room for STACK_SIZE return addresses.
+        if self.sp as usize >= STACK_SIZE {
+            return Err(EmuError::StackOverflow);
+        }
+
         self.stack[self.sp as usize] = val; // We add the new 16-bit value to the location of the sp.
         self.sp += 1; // We have pushed a new value to the stack. So we must increment sp by 1.
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> u16 {
+    pub fn pop(&mut self) -> Result<u16, EmuError> {
+        // Popping an empty stack would underflow sp.
+        if self.sp == 0 {
+            return Err(EmuError::StackUnderflow);
+        }
+
         self.sp -= 1; // We have popped the last value from the stack. So we must decrement sp by 1.
-        self.stack[self.sp as usize] // We return the next item at the top of the stack.
+        Ok(self.stack[self.sp as usize]) // We return the next item at the top of the stack.
+    }
+
+    // Reads a single byte of RAM, failing instead of panicking if the address is past the end.
+    fn read_ram(&self, addr: usize) -> Result<u8, EmuError> {
+        self.ram.get(addr).copied().ok_or(EmuError::MemoryOutOfBounds { addr })
+    }
+
+    // Reads a byte that an instruction uses as data, rather than as part of an instruction.
+    fn read_data(&mut self, addr: usize) -> Result<u8, EmuError> {
+        let val = self.read_ram(addr)?;
+        self.watch_access(addr, Access::Read, val);
+        Ok(val)
+    }
+
+    // Writes a single byte of RAM, failing instead of panicking if the address is past the end.
+    fn write_ram(&mut self, addr: usize, val: u8) -> Result<(), EmuError> {
+        match self.ram.get_mut(addr) {
+            Some(byte) => {
+                *byte = val;
+                self.watch_access(addr, Access::Write, val);
+                Ok(())
+            },
+            None => Err(EmuError::MemoryOutOfBounds { addr }),
+        }
     }
 
     // Handles each step that the CPU makes (a tick) to execute instructions.
@@ -94,45 +386,139 @@ impl Emu {
     // 2. Decode the instruction.
     // 3. Execute the instruction (this may involve modifying the CPU or RAM).
     // 4. Move the PC to the next instruction and repeat.
-    pub fn tick(&mut self) {
+    // If the program does something invalid, the error is returned and the frontend should stop ticking.
+    pub fn tick(&mut self) -> Result<(), EmuError> {
+        // A program that has exited stays stopped until it is reset.
+        if self.exited {
+            return Ok(());
+        }
+
+        // Forget any watched access from the last instruction, in case it wasn't run by run_until.
+        self.watches.clear_hit();
+
+        if self.tracer.is_some() {
+            return self.traced_tick();
+        }
+
         // Fetch.
-        let op = self.fetch();
+        let op = self.fetch()?;
         // Decode & execute.
-        self.execute(op);
+        self.execute(op)
+    }
+
+    // The same as the end of tick, but also records the instruction with the tracer.
+    // Instructions that fail aren't recorded, since they didn't finish.
+    fn traced_tick(&mut self) -> Result<(), EmuError> {
+        let pc = self.pc;
+        let before = TraceRegisters { v: self.v_reg, i: self.i_reg };
+        let op = self.fetch()?;
+        // F000 NNNN's second word is read by execute, which moves pc past it. Peek at it first.
+        let operand = match op {
+            0xF000 if self.quirks.xo_chip => {
+                let addr = self.pc as usize;
+                self.ram.get(addr..addr + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            },
+            _ => None,
+        };
+        self.execute(op)?;
+        let after = TraceRegisters { v: self.v_reg, i: self.i_reg };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(pc, op, operand, before, after);
+        }
+        Ok(())
     }
 
     // Fetches the opcode stored at the specified memory address in Program Counter.
     // This function does not need to be public because it is only accessed within the Emu object.
-    fn fetch(&mut self) -> u16 {
-        // We get 
-        let higher_byte = self.ram[self.pc as usize] as u16;
-        let lower_byte = self.ram[(self.pc + 1) as usize] as u16;
+    fn fetch(&mut self) -> Result<u16, EmuError> {
+        // We get both bytes of the opcode. If PC has run off the end of RAM, this fails.
+        let higher_byte = self.read_ram(self.pc as usize)? as u16;
+        let lower_byte = self.read_ram(self.pc as usize + 1)? as u16;
 
         // We use a left shift bitwise operator here (<<).
         // This shifts the bits of a number to the left by a specified amount.
         // For each number we shift, this is equivalent to multiplying that number by 2.
         let op = (higher_byte << 8) | lower_byte; // We combine the higher and lower bytes to form an opcode.
-        self.pc += 2; // We move the PC up by 2 bytes because every opcode will be 2 bytes in size.
+        self.pc = self.pc.wrapping_add(2); // We move the PC up by 2 bytes because every opcode will be 2 bytes in size.
         
-        op
+        Ok(op)
     }
 
     // Every frame, our timers will decrement.
     // Frames operate at a different speed to CPU ticks, so we must handle them in this function.
     pub fn tick_timers(&mut self) {
+        // A new frame has started, so a draw that was waiting for it can go ahead.
+        self.vblank = true;
+
         if self.dt > 0 {
             self.dt -= 1;
         }
 
+        // The buzzer sounds for as long as the sound timer is counting down.
+        self.set_buzzer(self.st > 0);
         if self.st > 0 {
-            if self.st == 1 {
-                // BEEP
-            }          
             self.st -= 1;
         }
     }
 
-    fn execute(&mut self, op: u16) {
+    // Switches the buzzer on or off, letting the audio sink know if anything changed.
+    fn set_buzzer(&mut self, on: bool) {
+        if self.buzzer_on == on {
+            return;
+        }
+
+        self.buzzer_on = on;
+        if let Some(sink) = self.audio.as_mut() {
+            sink.buzzer(on);
+        }
+    }
+
+    // Moves the selected planes by (dx, dy) pixels. Anything scrolled in from outside the screen is blank.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.resolution();
+        let old = self.screen;
+        for y in 0..height {
+            for x in 0..width {
+                // Find the pixel that ends up here, if it was on screen to begin with.
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let moved = if src_x >= 0 && src_y >= 0 && (src_x as usize) < width && (src_y as usize) < height {
+                    old[src_x as usize + width * src_y as usize]
+                } else {
+                    0
+                };
+
+                // Planes that are not selected stay where they are.
+                let idx = x + width * y;
+                self.screen[idx] = (old[idx] & !self.planes) | (moved & self.planes);
+            }
+        }
+        self.refresh_display();
+    }
+
+    // Recalculates which pixels are lit after the whole screen has changed.
+    fn refresh_display(&mut self) {
+        for (lit, pixel) in self.display.iter_mut().zip(self.screen.iter()) {
+            *lit = *pixel != 0;
+        }
+    }
+
+    // Skips the next instruction. In XO-CHIP mode the next instruction may be the 4 byte F000 NNNN,
+    // so we need to skip over both halves of it.
+    fn skip(&mut self) -> Result<(), EmuError> {
+        let mut len = 2;
+        if self.quirks.xo_chip {
+            let next = self.read_ram(self.pc as usize)?;
+            let next_lower = self.read_ram(self.pc as usize + 1)?;
+            if next == 0xF0 && next_lower == 0x00 {
+                len = 4;
+            }
+        }
+        self.pc = self.pc.wrapping_add(len);
+        Ok(())
+    }
+
+    fn execute(&mut self, op: u16) -> Result<(), EmuError> {
         // We use the right shift bitwise operator here (>>).
         // This shifts a values bits to the right by a specified amount.
         // Here we are getting each digit in the opcode,
@@ -153,19 +539,677 @@ impl Emu {
 
         // Now that we have digits 1 through 4, we handle them based on the opcode they create.
         match (digit1, digit2, digit3, digit4) {
-            (0, 0, 0, 0) => return, // If all digits are 0, do nothing
-            (_, _, _, _) => unimplemented!("Unimplemented opcode: {}", op), // If digits are not valid, panic
-            (0, 0, 0xE, 0) => { // 000E0 clears the screen.
-                self.screen = [false; SCREEN_WIDTH * SCREEN_HEIGHT]
+            (0, 0, 0, 0) => (), // If all digits are 0, do nothing
+            (0, 0, 0xC, _) if self.quirks.super_chip => { // 00CN scrolls the screen down by N pixels.
+                self.scroll(0, digit4 as isize);
+            },
+            (0, 0, 0xD, _) if self.quirks.xo_chip => { // 00DN scrolls the screen up by N pixels.
+                self.scroll(0, -(digit4 as isize));
             },
-            (0, 0, 0xE, 0xE) => { // 000EE returns from subroutine.
-                let ret_addr = self.pop(); // Runs subroutine from top of stack.
+            (0, 0, 0xE, 0) => { // 00E0 clears the selected planes of the screen.
+                for pixel in self.screen.iter_mut() {
+                    *pixel &= !self.planes;
+                }
+                self.refresh_display();
+            },
+            (0, 0, 0xE, 0xE) => { // 00EE returns from subroutine.
+                let ret_addr = self.pop()?; // Runs subroutine from top of stack.
                 self.pc = ret_addr; // Returns the PC to the next item after popping.
             },
+            (0, 0, 0xF, 0xB) if self.quirks.super_chip => { // 00FB scrolls the screen right by 4 pixels.
+                self.scroll(4, 0);
+            },
+            (0, 0, 0xF, 0xC) if self.quirks.super_chip => { // 00FC scrolls the screen left by 4 pixels.
+                self.scroll(-4, 0);
+            },
+            (0, 0, 0xF, 0xD) if self.quirks.super_chip => { // 00FD exits the program.
+                self.exited = true;
+            },
+            (0, 0, 0xF, 0xE) if self.quirks.super_chip => { // 00FE switches to low resolution (64x32).
+                self.hires = false;
+                self.screen = [0; SCREEN_SIZE];
+                self.display = [false; SCREEN_SIZE];
+            },
+            (0, 0, 0xF, 0xF) if self.quirks.super_chip => { // 00FF switches to high resolution (128x64).
+                self.hires = true;
+                self.screen = [0; SCREEN_SIZE];
+                self.display = [false; SCREEN_SIZE];
+            },
             (1, _, _, _) => { // 1NNN jumps.
                 let nnn = op & 0xFFF;
                 self.pc = nnn;
             },
+            (2, _, _, _) => { // 2NNN calls a subroutine.
+                let nnn = op & 0xFFF;
+                self.push(self.pc)?; // Remember where to come back to once the subroutine returns.
+                self.pc = nnn;
+            },
+            (3, _, _, _) => { // 3XNN skips the next instruction if VX == NN.
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                if self.v_reg[x] == nn {
+                    self.skip()?;
+                }
+            },
+            (4, _, _, _) => { // 4XNN skips the next instruction if VX != NN.
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                if self.v_reg[x] != nn {
+                    self.skip()?;
+                }
+            },
+            (5, _, _, 0) => { // 5XY0 skips the next instruction if VX == VY.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                if self.v_reg[x] == self.v_reg[y] {
+                    self.skip()?;
+                }
+            },
+            (5, _, _, 2) if self.quirks.xo_chip => { // 5XY2 saves VX through VY to RAM, starting at I.
+                // The registers can be given in either order. I is not changed.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                let i = self.i_reg as usize;
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    self.write_ram(i + offset, self.v_reg[reg])?;
+                }
+            },
+            (5, _, _, 3) if self.quirks.xo_chip => { // 5XY3 loads VX through VY from RAM, starting at I.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                let i = self.i_reg as usize;
+                for (offset, reg) in register_range(x, y).enumerate() {
+                    self.v_reg[reg] = self.read_data(i + offset)?;
+                }
+            },
+            (6, _, _, _) => { // 6XNN sets VX to NN.
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                self.v_reg[x] = nn;
+            },
+            (7, _, _, _) => { // 7XNN adds NN to VX. The carry flag is not changed.
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                self.v_reg[x] = self.v_reg[x].wrapping_add(nn);
+            },
+            (8, _, _, 0) => { // 8XY0 sets VX to VY.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] = self.v_reg[y];
+            },
+            (8, _, _, 1) => { // 8XY1 sets VX to VX OR VY.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] |= self.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
+            },
+            (8, _, _, 2) => { // 8XY2 sets VX to VX AND VY.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] &= self.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
+            },
+            (8, _, _, 3) => { // 8XY3 sets VX to VX XOR VY.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                self.v_reg[x] ^= self.v_reg[y];
+                if self.quirks.vf_reset {
+                    self.v_reg[0xF] = 0;
+                }
+            },
+            (8, _, _, 4) => { // 8XY4 adds VY to VX. VF is set to 1 on carry, otherwise 0.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                let (new_vx, carry) = self.v_reg[x].overflowing_add(self.v_reg[y]);
+                // VF is written last, so that the flag wins if VF was also the destination.
+                self.v_reg[x] = new_vx;
+                self.v_reg[0xF] = if carry { 1 } else { 0 };
+            },
+            (8, _, _, 5) => { // 8XY5 subtracts VY from VX. VF is set to 0 on borrow, otherwise 1.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                let (new_vx, borrow) = self.v_reg[x].overflowing_sub(self.v_reg[y]);
+                self.v_reg[x] = new_vx;
+                self.v_reg[0xF] = if borrow { 0 } else { 1 };
+            },
+            (8, _, _, 6) => { // 8XY6 shifts VX right by one. VF is set to the bit that was shifted out.
+                let x = digit2 as usize;
+                if self.quirks.shift_uses_vy {
+                    self.v_reg[x] = self.v_reg[digit3 as usize];
+                }
+                let lsb = self.v_reg[x] & 1;
+                self.v_reg[x] >>= 1;
+                self.v_reg[0xF] = lsb;
+            },
+            (8, _, _, 7) => { // 8XY7 sets VX to VY minus VX. VF is set to 0 on borrow, otherwise 1.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                let (new_vx, borrow) = self.v_reg[y].overflowing_sub(self.v_reg[x]);
+                self.v_reg[x] = new_vx;
+                self.v_reg[0xF] = if borrow { 0 } else { 1 };
+            },
+            (8, _, _, 0xE) => { // 8XYE shifts VX left by one. VF is set to the bit that was shifted out.
+                let x = digit2 as usize;
+                if self.quirks.shift_uses_vy {
+                    self.v_reg[x] = self.v_reg[digit3 as usize];
+                }
+                let msb = (self.v_reg[x] >> 7) & 1;
+                self.v_reg[x] <<= 1;
+                self.v_reg[0xF] = msb;
+            },
+            (9, _, _, 0) => { // 9XY0 skips the next instruction if VX != VY.
+                let x = digit2 as usize;
+                let y = digit3 as usize;
+                if self.v_reg[x] != self.v_reg[y] {
+                    self.skip()?;
+                }
+            },
+            (0xA, _, _, _) => { // ANNN sets I to NNN.
+                let nnn = op & 0xFFF;
+                self.i_reg = nnn;
+            },
+            (0xB, _, _, _) => { // BNNN jumps to NNN plus V0 (or XNN plus VX, depending on quirks).
+                let nnn = op & 0xFFF;
+                let offset = if self.quirks.jump_uses_vx {
+                    self.v_reg[digit2 as usize]
+                } else {
+                    self.v_reg[0]
+                };
+                self.pc = (offset as u16) + nnn;
+            },
+            (0xC, _, _, _) => { // CXNN sets VX to a random byte AND NN.
+                let x = digit2 as usize;
+                let nn = (op & 0xFF) as u8;
+                self.v_reg[x] = self.rng.next_u8() & nn;
+            },
+            (0xD, _, _, _) => { // DXYN draws an 8 pixel wide, N pixel tall sprite from I at (VX, VY).
+                // With the SUPER-CHIP opcodes, DXY0 draws a 16x16 sprite instead, where each row is two bytes.
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        // Run this opcode again until the next frame starts.
+                        self.pc = self.pc.wrapping_sub(2);
+                        return Ok(());
+                    }
+                    self.vblank = false;
+                }
+
+                let (width, height) = self.resolution();
+                let (sprite_width, num_rows) = match digit4 {
+                    0 if self.quirks.super_chip => (16, 16),
+                    _ => (8, digit4 as usize),
+                };
+                let bytes_per_row = sprite_width / 8;
+
+                // The starting coordinates always wrap around the edges of the screen.
+                let x_coord = self.v_reg[digit2 as usize] as usize % width;
+                let y_coord = self.v_reg[digit3 as usize] as usize % height;
+
+                // Keep track of whether any pixel was switched off, this is our collision flag.
+                let mut flipped = false;
+                // Each selected plane gets its own copy of the sprite, one after another in RAM starting at I.
+                let mut addr = self.i_reg as usize;
+                for plane in 0..2 {
+                    let plane_bit = 1 << plane;
+                    if self.planes & plane_bit == 0 {
+                        continue;
+                    }
+
+                    for y_line in 0..num_rows {
+                        // Each row of the sprite is one or two bytes.
+                        let mut pixels = 0u16;
+                        for _ in 0..bytes_per_row {
+                            pixels = (pixels << 8) | self.read_data(addr)? as u16;
+                            addr += 1;
+                        }
+
+                        for x_line in 0..sprite_width {
+                            // Use a mask to check each bit of the row, from left to right.
+                            if (pixels & (1 << (sprite_width - 1 - x_line))) != 0 {
+                                let x = x_coord + x_line;
+                                let y = y_coord + y_line;
+
+                                // Pixels hanging off the edge are either dropped or wrapped to the other side.
+                                if self.quirks.clip_sprites && (x >= width || y >= height) {
+                                    continue;
+                                }
+                                let x = x % width;
+                                let y = y % height;
+                                let idx = x + width * y;
+
+                                // Sprites are XORed onto the screen.
+                                flipped |= self.screen[idx] & plane_bit != 0;
+                                self.screen[idx] ^= plane_bit;
+                                self.display[idx] = self.screen[idx] != 0;
+                            }
+                        }
+                    }
+                }
+
+                self.v_reg[0xF] = if flipped { 1 } else { 0 };
+            },
+            (0xE, _, 9, 0xE) => { // EX9E skips the next instruction if the key in VX is pressed.
+                let x = digit2 as usize;
+                let key = self.keys[(self.v_reg[x] & 0xF) as usize];
+                if key {
+                    self.skip()?;
+                }
+            },
+            (0xE, _, 0xA, 1) => { // EXA1 skips the next instruction if the key in VX is not pressed.
+                let x = digit2 as usize;
+                let key = self.keys[(self.v_reg[x] & 0xF) as usize];
+                if !key {
+                    self.skip()?;
+                }
+            },
+            (0xF, 0, 0, 0) if self.quirks.xo_chip => { // F000 NNNN sets I to the 16-bit address in the next two bytes.
+                let high = self.read_ram(self.pc as usize)? as u16;
+                let low = self.read_ram(self.pc as usize + 1)? as u16;
+                self.i_reg = (high << 8) | low;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            (0xF, _, 0, 1) if self.quirks.xo_chip => { // FN01 selects which planes to draw to, as a bitmask.
+                self.planes = (digit2 & 0x3) as u8;
+            },
+            (0xF, 0, 0, 2) if self.quirks.xo_chip => { // F002 loads 16 bytes from RAM at I into the audio pattern.
+                let i = self.i_reg as usize;
+                for idx in 0..AUDIO_PATTERN_SIZE {
+                    self.audio_pattern[idx] = self.read_data(i + idx)?;
+                }
+            },
+            (0xF, _, 0, 7) => { // FX07 sets VX to the delay timer.
+                let x = digit2 as usize;
+                self.v_reg[x] = self.dt;
+            },
+            (0xF, _, 0, 0xA) => { // FX0A waits for a key press and stores it in VX.
+                let x = digit2 as usize;
+                let mut pressed = false;
+                for i in 0..self.keys.len() {
+                    if self.keys[i] {
+                        self.v_reg[x] = i as u8;
+                        pressed = true;
+                        break;
+                    }
+                }
+
+                if !pressed {
+                    // Nothing is pressed, so run this opcode again on the next tick.
+                    self.pc = self.pc.wrapping_sub(2);
+                }
+            },
+            (0xF, _, 1, 5) => { // FX15 sets the delay timer to VX.
+                let x = digit2 as usize;
+                self.dt = self.v_reg[x];
+            },
+            (0xF, _, 1, 8) => { // FX18 sets the sound timer to VX.
+                let x = digit2 as usize;
+                self.st = self.v_reg[x];
+            },
+            (0xF, _, 1, 0xE) => { // FX1E adds VX to I.
+                let x = digit2 as usize;
+                let vx = self.v_reg[x] as u16;
+                self.i_reg = self.i_reg.wrapping_add(vx);
+            },
+            (0xF, _, 2, 9) => { // FX29 sets I to the font character for the digit in VX.
+                // Every character in FONTSET is 5 bytes long, and the font starts at address 0.
+                let x = digit2 as usize;
+                let c = (self.v_reg[x] & 0xF) as u16;
+                self.i_reg = c * 5;
+            },
+            (0xF, _, 3, 0) if self.quirks.super_chip => { // FX30 sets I to the large font character for the digit in VX.
+                // Every character in BIG_FONTSET is 10 bytes long.
+                let x = digit2 as usize;
+                let c = (self.v_reg[x] & 0xF) as usize;
+                self.i_reg = (BIG_FONTSET_ADDR + c * 10) as u16;
+            },
+            (0xF, _, 3, 0xA) if self.quirks.xo_chip => { // FX3A sets the audio pattern pitch to VX.
+                let x = digit2 as usize;
+                self.pitch = self.v_reg[x];
+            },
+            (0xF, _, 3, 3) => { // FX33 stores the binary-coded decimal of VX at I, I+1 and I+2.
+                let x = digit2 as usize;
+                let vx = self.v_reg[x];
+
+                let hundreds = vx / 100;
+                let tens = (vx / 10) % 10;
+                let ones = vx % 10;
+
+                let i = self.i_reg as usize;
+                self.write_ram(i, hundreds)?;
+                self.write_ram(i + 1, tens)?;
+                self.write_ram(i + 2, ones)?;
+            },
+            (0xF, _, 5, 5) => { // FX55 stores V0 through VX in RAM, starting at I.
+                let x = digit2 as usize;
+                let i = self.i_reg as usize;
+                for idx in 0..=x {
+                    self.write_ram(i + idx, self.v_reg[idx])?;
+                }
+                self.i_reg = self.i_reg.wrapping_add(self.quirks.load_store_increment.step(x));
+            },
+            (0xF, _, 6, 5) => { // FX65 loads V0 through VX from RAM, starting at I.
+                let x = digit2 as usize;
+                let i = self.i_reg as usize;
+                for idx in 0..=x {
+                    self.v_reg[idx] = self.read_data(i + idx)?;
+                }
+                self.i_reg = self.i_reg.wrapping_add(self.quirks.load_store_increment.step(x));
+            },
+            (0xF, _, 7, 5) if self.quirks.super_chip => { // FX75 saves V0 through VX to the RPL user flags.
+                let x = digit2 as usize;
+                self.rpl[..=x].copy_from_slice(&self.v_reg[..=x]);
+            },
+            (0xF, _, 8, 5) if self.quirks.super_chip => { // FX85 loads V0 through VX from the RPL user flags.
+                let x = digit2 as usize;
+                self.v_reg[..=x].copy_from_slice(&self.rpl[..=x]);
+            },
+            (_, _, _, _) => { // If digits are not valid, report the opcode and where it was fetched from.
+                return Err(EmuError::InvalidOpcode { pc: self.pc.wrapping_sub(2), op });
+            },
+        }
+
+        Ok(())
+    }
+}
+
+// The registers from x to y inclusive, counting down if y comes before x.
+// Used by XO-CHIP's 5XY2 and 5XY3.
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An emulator that has the program loaded at START_ADDR.
+    fn emu_with(quirks: Quirks, program: &[u8]) -> Emu {
+        let mut emu = Emu::new(quirks);
+        emu.load_rom(program).unwrap();
+        emu
+    }
+
+    // Runs every instruction of a program once, in order. The program can't jump or skip.
+    fn run(quirks: Quirks, program: &[u8]) -> Emu {
+        let mut emu = emu_with(quirks, program);
+        for _ in 0..program.len() / 2 {
+            emu.tick().unwrap();
         }
+        emu
+    }
+
+    // The lit pixels of the screen, as (x, y) pairs.
+    fn lit(emu: &Emu) -> Vec<(usize, usize)> {
+        let (width, _) = emu.resolution();
+        emu.screen.iter().enumerate().filter(|(_, &planes)| planes != 0).map(|(idx, _)| (idx % width, idx / width)).collect()
+    }
+
+    // An XO-CHIP emulator about to run the instruction in the last two bytes of its 64 KiB of RAM.
+    fn emu_at_end_of_ram(quirks: Quirks, op: [u8; 2]) -> Emu {
+        let mut emu = emu_with(quirks, &[]);
+        emu.ram[0xFFFE..].copy_from_slice(&op);
+        emu.pc = 0xFFFE;
+        emu
+    }
+
+    #[test]
+    fn wait_for_key_at_end_of_ram() {
+        let mut emu = emu_at_end_of_ram(Quirks::XO_CHIP, [0xF0, 0x0A]);
+        emu.tick().unwrap();
+        assert_eq!(emu.pc, 0xFFFE);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn display_wait_at_end_of_ram() {
+        let quirks = Quirks { display_wait: true, ..Quirks::XO_CHIP };
+        let mut emu = emu_at_end_of_ram(quirks, [0xD0, 0x01]);
+        emu.tick().unwrap();
+        assert_eq!(emu.pc, 0xFFFE);
+    }
+
+    #[test]
+    fn invalid_opcode_at_end_of_ram() {
+        let mut emu = emu_at_end_of_ram(Quirks::XO_CHIP, [0xE0, 0x00]);
+        assert_eq!(emu.tick(), Err(EmuError::InvalidOpcode { pc: 0xFFFE, op: 0xE000 }));
+    }
+
+    #[test]
+    fn write_memory_stays_inside_ram() {
+        let mut emu = emu_with(Quirks::CHIP_48, &[]);
+        emu.write_memory(0xFFE, &[1, 2]).unwrap();
+        assert_eq!(&emu.ram[0xFFE..], [1, 2]);
+
+        // Nothing is written when any of it doesn't fit, even if the end would wrap around.
+        assert_eq!(emu.write_memory(0xFFF, &[3, 4]), Err(EmuError::MemoryOutOfBounds { addr: 0x1000 }));
+        assert_eq!(emu.write_memory(usize::MAX, &[3, 4]), Err(EmuError::MemoryOutOfBounds { addr: usize::MAX }));
+        assert_eq!(&emu.ram[0xFFE..], [1, 2]);
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        let emu = run(Quirks::CHIP_48, &[0x60, 0xFF, 0x61, 0x02, 0x80, 0x14]);
+        assert_eq!(emu.v_reg[0], 0x01);
+        assert_eq!(emu.v_reg[0xF], 1);
+
+        let emu = run(Quirks::CHIP_48, &[0x60, 0x10, 0x61, 0x02, 0x80, 0x14]);
+        assert_eq!(emu.v_reg[0], 0x12);
+        assert_eq!(emu.v_reg[0xF], 0);
+    }
+
+    #[test]
+    fn subtract_clears_vf_on_borrow() {
+        let emu = run(Quirks::CHIP_48, &[0x60, 0x05, 0x61, 0x03, 0x80, 0x15]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x02, 1));
+
+        let emu = run(Quirks::CHIP_48, &[0x60, 0x03, 0x61, 0x05, 0x80, 0x15]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0xFE, 0));
+
+        // 8XY7 subtracts the other way around.
+        let emu = run(Quirks::CHIP_48, &[0x60, 0x03, 0x61, 0x05, 0x80, 0x17]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x02, 1));
+
+        let emu = run(Quirks::CHIP_48, &[0x60, 0x05, 0x61, 0x03, 0x80, 0x17]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0xFE, 0));
+    }
+
+    #[test]
+    fn shifts_set_vf_to_the_bit_shifted_out() {
+        let emu = run(Quirks::CHIP_48, &[0x60, 0x81, 0x80, 0x06]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x40, 1));
+
+        let emu = run(Quirks::CHIP_48, &[0x60, 0x81, 0x80, 0x0E]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x02, 1));
+
+        // The original interpreter shifts VY into VX.
+        let emu = run(Quirks::COSMAC_VIP, &[0x60, 0xFF, 0x61, 0x02, 0x80, 0x16]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x01, 0));
+
+        let emu = run(Quirks::COSMAC_VIP, &[0x60, 0xFF, 0x61, 0x40, 0x80, 0x1E]);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x80, 0));
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        // Each result would be something other than the flag if VX were written after VF.
+        let cases: [(&[u8], u8); 5] = [
+            (&[0x6F, 0x10, 0x61, 0x20, 0x8F, 0x14], 0), // 0x30 with no carry.
+            (&[0x6F, 0x05, 0x61, 0x03, 0x8F, 0x15], 1), // 0x02 with no borrow.
+            (&[0x6F, 0x02, 0x8F, 0xF6], 0), // 0x01 with a 0 shifted out.
+            (&[0x6F, 0x03, 0x61, 0x05, 0x8F, 0x17], 1), // 0x02 with no borrow.
+            (&[0x6F, 0x40, 0x8F, 0xFE], 0), // 0x80 with a 0 shifted out.
+        ];
+        for (program, flag) in cases {
+            let emu = run(Quirks::CHIP_48, program);
+            assert_eq!(emu.v_reg[0xF], flag, "{:02X?}", program);
+        }
+    }
+
+    #[test]
+    fn logic_resets_vf_on_the_original_interpreter() {
+        let program = [0x6F, 0x05, 0x60, 0x0C, 0x61, 0x0A, 0x80, 0x11];
+        let emu = run(Quirks::COSMAC_VIP, &program);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x0E, 0));
+
+        let emu = run(Quirks::CHIP_48, &program);
+        assert_eq!((emu.v_reg[0], emu.v_reg[0xF]), (0x0E, 5));
+    }
+
+    #[test]
+    fn draw_sets_vf_on_collision() {
+        // Draws the 1 from the font at (2, 3), then draws it again on top.
+        let mut emu = emu_with(Quirks::CHIP_48, &[0x60, 0x02, 0x61, 0x03, 0xA0, 0x05, 0xD0, 0x15, 0xD0, 0x15]);
+        for _ in 0..4 {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.v_reg[0xF], 0);
+        assert_eq!(lit(&emu), [(4, 3), (3, 4), (4, 4), (4, 5), (4, 6), (3, 7), (4, 7), (5, 7)]);
+
+        emu.tick().unwrap();
+        assert_eq!(emu.v_reg[0xF], 1);
+        assert_eq!(lit(&emu), []);
+    }
+
+    #[test]
+    fn draw_wraps_or_clips_at_the_edges() {
+        // The top row of the 0 from the font, drawn at (66, 63), which wraps to (2, 31).
+        let program = [0x60, 0x42, 0x61, 0x3F, 0xA0, 0x00, 0xD0, 0x11];
+        let emu = run(Quirks::CHIP_48, &program);
+        assert_eq!(lit(&emu), [(2, 31), (3, 31), (4, 31), (5, 31)]);
+
+        // Drawn at (62, 31), the sprite runs off the edge, where CHIP-48 cuts it off and XO-CHIP wraps it.
+        let program = [0x60, 0x3E, 0x61, 0x1F, 0xA0, 0x00, 0xD0, 0x12];
+        let emu = run(Quirks::CHIP_48, &program);
+        assert_eq!(lit(&emu), [(62, 31), (63, 31)]);
+
+        let emu = run(Quirks::XO_CHIP, &program);
+        assert_eq!(lit(&emu), [(1, 0), (62, 0), (0, 31), (1, 31), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        // B300 with V0 = 4 and V3 = 8.
+        let program = [0x60, 0x04, 0x63, 0x08, 0xB3, 0x00];
+        assert_eq!(run(Quirks::COSMAC_VIP, &program).pc, 0x304);
+        assert_eq!(run(Quirks::CHIP_48, &program).pc, 0x308);
+    }
+
+    #[test]
+    fn loads_and_stores_move_index_by_preset() {
+        // FX55 and FX65 with X = 2, from I = 0x300.
+        let presets = [(Quirks::COSMAC_VIP, 3), (Quirks::CHIP_48, 2), (Quirks::SUPER_CHIP, 0), (Quirks::XO_CHIP, 3)];
+        for (quirks, step) in presets {
+            assert_eq!(run(quirks, &[0xA3, 0x00, 0xF2, 0x55]).i_reg, 0x300 + step, "{:?}", quirks);
+            assert_eq!(run(quirks, &[0xA3, 0x00, 0xF2, 0x65]).i_reg, 0x300 + step, "{:?}", quirks);
+        }
+    }
+
+    #[test]
+    fn draw_waits_for_the_next_frame_on_the_original_interpreter() {
+        let program = [0xA0, 0x00, 0xD0, 0x01];
+        let mut emu = emu_with(Quirks::COSMAC_VIP, &program);
+        for _ in 0..3 {
+            emu.tick().unwrap();
+        }
+        assert_eq!((emu.pc, lit(&emu).len()), (0x202, 0));
+
+        emu.tick_timers();
+        emu.tick().unwrap();
+        assert_eq!((emu.pc, lit(&emu).len()), (0x204, 4));
+
+        let emu = run(Quirks::CHIP_48, &program);
+        assert_eq!((emu.pc, lit(&emu).len()), (0x204, 4));
+    }
+
+    #[test]
+    fn super_chip_opcodes_need_the_quirk() {
+        for op in [0x00C1, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0xF030, 0xF075, 0xF085] {
+            for quirks in [Quirks::COSMAC_VIP, Quirks::CHIP_48] {
+                let mut emu = emu_with(quirks, &u16::to_be_bytes(op));
+                assert_eq!(emu.tick(), Err(EmuError::InvalidOpcode { pc: 0x200, op }));
+            }
+            for quirks in [Quirks::SUPER_CHIP, Quirks::XO_CHIP] {
+                emu_with(quirks, &u16::to_be_bytes(op)).tick().unwrap();
+            }
+        }
+
+        // DXY0 draws a 16x16 sprite, or nothing at all on the interpreters before SUPER-CHIP.
+        let program = [0xA0, 0x00, 0xD0, 0x00];
+        assert_eq!(lit(&run(Quirks::CHIP_48, &program)), []);
+        assert!(lit(&run(Quirks::SUPER_CHIP, &program)).iter().any(|&(x, _)| x >= 8));
+    }
+
+    #[test]
+    fn binary_coded_decimal() {
+        for (vx, digits) in [(0, [0, 0, 0]), (7, [0, 0, 7]), (42, [0, 4, 2]), (255, [2, 5, 5])] {
+            let emu = run(Quirks::CHIP_48, &[0x65, vx, 0xA3, 0x00, 0xF5, 0x33]);
+            assert_eq!(emu.ram[0x300..0x303], digits);
+            assert_eq!(emu.i_reg, 0x300);
+        }
+    }
+
+    #[test]
+    fn add_to_index() {
+        let emu = run(Quirks::CHIP_48, &[0x6F, 0xFF, 0x62, 0x10, 0xAF, 0xF8, 0xF2, 0x1E]);
+        assert_eq!(emu.i_reg, 0x1008);
+        // VF isn't touched, unlike on the Amiga interpreter.
+        assert_eq!(emu.v_reg[0xF], 0xFF);
+    }
+
+    #[test]
+    fn seventeen_nested_calls_overflow_the_stack() {
+        // Every subroutine calls the next one, from 0x200 up.
+        let program: Vec<u8> = (0..=STACK_SIZE as u16).flat_map(|n| (0x2202 + 2 * n).to_be_bytes()).collect();
+        let mut emu = emu_with(Quirks::CHIP_48, &program);
+        for _ in 0..STACK_SIZE {
+            emu.tick().unwrap();
+        }
+        assert_eq!(emu.tick(), Err(EmuError::StackOverflow));
+    }
+
+    #[test]
+    fn returning_from_nothing_underflows_the_stack() {
+        let mut emu = emu_with(Quirks::CHIP_48, &[0x00, 0xEE]);
+        assert_eq!(emu.tick(), Err(EmuError::StackUnderflow));
+    }
+
+    #[test]
+    fn running_off_the_end_of_ram() {
+        let mut emu = emu_with(Quirks::CHIP_48, &[0x1F, 0xFF]);
+        emu.tick().unwrap();
+        assert_eq!(emu.tick(), Err(EmuError::MemoryOutOfBounds { addr: 0x1000 }));
+    }
+
+    #[test]
+    fn loads_and_stores_past_the_end_of_ram() {
+        // I is 0xFFE, so V2 is the first register that doesn't fit.
+        let mut emu = emu_with(Quirks::CHIP_48, &[0xAF, 0xFE, 0xF2, 0x55]);
+        emu.tick().unwrap();
+        assert_eq!(emu.tick(), Err(EmuError::MemoryOutOfBounds { addr: 0x1000 }));
+
+        let mut emu = emu_with(Quirks::CHIP_48, &[0xAF, 0xFE, 0xF2, 0x65]);
+        emu.tick().unwrap();
+        assert_eq!(emu.tick(), Err(EmuError::MemoryOutOfBounds { addr: 0x1000 }));
+
+        let mut emu = emu_with(Quirks::CHIP_48, &[0xAF, 0xFE, 0xD0, 0x03]);
+        emu.tick().unwrap();
+        assert_eq!(emu.tick(), Err(EmuError::MemoryOutOfBounds { addr: 0x1000 }));
+    }
+
+    #[test]
+    fn invalid_opcodes_report_where_they_were() {
+        let mut emu = emu_with(Quirks::CHIP_48, &[0x00, 0x00, 0x5A, 0xB1]);
+        emu.tick().unwrap();
+        assert_eq!(emu.tick(), Err(EmuError::InvalidOpcode { pc: 0x202, op: 0x5AB1 }));
+
+        // The XO-CHIP opcodes aren't there without the XO-CHIP quirk.
+        let mut emu = emu_with(Quirks::SUPER_CHIP, &[0xF0, 0x01]);
+        assert_eq!(emu.tick(), Err(EmuError::InvalidOpcode { pc: 0x200, op: 0xF001 }));
+    }
+}
@@ -0,0 +1,1170 @@
+// A compiler for Octo, the language most modern CHIP-8 programs are written in. The language is
+// described in full in the Octo manual (https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md).
+//
+//     :alias x v0
+//     :const SPEED 2
+//     : main
+//         x := 0
+//         loop
+//             i := ball
+//             sprite x x 4
+//             x += SPEED
+//             if x == 60 then x := 0
+//         again
+//     : ball 0x60 0xF0 0xF0 0x60
+//
+// Everything is separated by whitespace and `#` starts a comment. Besides the instructions this
+// covers labels, `:const`, `:alias`, `:calc`, `:macro`, `:unpack`, `:next`, `:org`, `:byte`,
+// `:pointer` and `:assert`, structured `if`/`loop`/`while`, and the `<`, `>`, `<=` and `>=`
+// comparisons, which are built out of subtraction and use vF.
+//
+// Like Octo, if the program has a `main` label that isn't the very first thing, a jump to it is put
+// at 0x200. Programs without one start at the top, which is also how our Octo disassembly comes out.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::asm::{AsmError, Assembly, SourceLine};
+use crate::opcode::Op;
+use crate::{MAX_ROM_SIZE, START_ADDR, XO_MAX_ROM_SIZE};
+
+// How many macros can be expanded before we assume one is expanding itself forever.
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+// The register the comparison operators use for their working.
+const COMPARE_TEMP: u8 = 0xF;
+
+const CALC_BINARY: [&str; 19] = [
+    "-", "+", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", "<=", "==", "!=", ">=", ">",
+];
+const CALC_UNARY: [&str; 14] =
+    ["-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil", "floor", "@"];
+
+// The instruction set a program is written for. Using an instruction the target doesn't have is an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Target {
+    fn name(self) -> &'static str {
+        match self {
+            Target::Chip8 => "CHIP-8",
+            Target::SuperChip => "SUPER-CHIP",
+            Target::XoChip => "XO-CHIP",
+        }
+    }
+
+    // The first instruction set that has this instruction.
+    fn of(op: &Op) -> Target {
+        match op {
+            Op::ScrollDown(_)
+            | Op::ScrollRight
+            | Op::ScrollLeft
+            | Op::Exit
+            | Op::Lores
+            | Op::Hires
+            | Op::BigFont(_)
+            | Op::SaveFlags(_)
+            | Op::LoadFlags(_) => Target::SuperChip,
+            Op::ScrollUp(_)
+            | Op::StoreRange(..)
+            | Op::LoadRange(..)
+            | Op::LongI(_)
+            | Op::Plane(_)
+            | Op::Audio
+            | Op::Pitch(_) => Target::XoChip,
+            _ => Target::Chip8,
+        }
+    }
+
+    fn max_rom_size(self) -> usize {
+        match self {
+            Target::XoChip => XO_MAX_ROM_SIZE,
+            _ => MAX_ROM_SIZE,
+        }
+    }
+}
+
+// Compiles Octo source text.
+pub fn compile(source: &str, target: Target) -> Result<Assembly, AsmError> {
+    Compiler::new("<input>", source, target).run()
+}
+
+// Compiles an Octo source file.
+pub fn compile_file(path: &Path, target: Target) -> Result<Assembly, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: name.clone(),
+        line: 0,
+        column: 0,
+        message: err.to_string(),
+    })?;
+    Compiler::new(&name, &source, target).run()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+// Splits source up into whitespace separated tokens, dropping comments. Quoted strings are kept whole.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+
+            let mut end = line.len();
+            let quoted = c == '"';
+            chars.next();
+            while let Some(&(pos, c)) = chars.peek() {
+                if (quoted && c == '"') || (!quoted && c.is_whitespace()) {
+                    end = if quoted { pos + 1 } else { pos };
+                    chars.next();
+                    break;
+                }
+                chars.next();
+            }
+            tokens.push(Token { text: line[start..end].to_string(), line: index + 1, column: start + 1 });
+        }
+    }
+    tokens
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// How to write a label's address into the program once it is known.
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    Nnn,                          // The low 12 bits of an opcode.
+    Word,                         // Two bytes, big-endian.
+    Byte { shift: u32, high: u8 }, // One byte of the address, ORed with `high`.
+}
+
+// A reference to a label that hadn't been defined yet when it was used.
+#[derive(Debug, Clone)]
+struct Fixup {
+    addr: u32,
+    kind: FixupKind,
+    token: Token,
+}
+
+// A condition for `if` or `while`.
+struct Condition {
+    x: u8,
+    op: Token,
+    rhs: Rhs,
+}
+
+// What a register is compared with.
+#[derive(Debug, Clone, Copy)]
+enum Rhs {
+    None, // `key` and `-key` don't compare with anything.
+    Reg(u8),
+    Byte(u8),
+}
+
+// An `if ... begin` or `loop` that hasn't been closed yet.
+#[derive(Debug, Clone)]
+enum Block {
+    // The jump to patch when the block ends, and whether `else` has been seen.
+    If { jump: u32, has_else: bool, token: Token },
+    Loop { start: u32, breaks: Vec<u32>, token: Token },
+}
+
+struct Compiler {
+    file: String,
+    target: Target,
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>, // The program, with its first byte at START_ADDR.
+    here: u32,    // Where the next byte goes.
+    labels: HashMap<String, u32>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    source_map: Vec<SourceLine>,
+    expansions: usize,
+    statement: Token, // The token that started the statement being compiled, for errors.
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str, target: Target) -> Self {
+        let tokens: VecDeque<Token> = tokenize(source).into();
+        let statement = Token { text: String::new(), line: 1, column: 1 };
+        Self {
+            file: file.to_string(),
+            target,
+            tokens,
+            rom: Vec::new(),
+            here: START_ADDR as u32,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            source_map: Vec::new(),
+            expansions: 0,
+            statement,
+        }
+    }
+
+    fn error(&self, token: &Token, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn run(mut self) -> Result<Assembly, AsmError> {
+        // Octo jumps to `main` first, unless main is where the program starts anyway.
+        let has_main = self
+            .tokens
+            .iter()
+            .zip(self.tokens.iter().skip(1))
+            .any(|(colon, name)| colon.text == ":" && name.text == "main");
+        let main_first = self.tokens.front().is_some_and(|token| token.text == ":")
+            && self.tokens.get(1).is_some_and(|token| token.text == "main");
+        if has_main && !main_first {
+            let token = Token { text: "main".to_string(), ..self.tokens[0].clone() };
+            self.fixups.push(Fixup { addr: self.here, kind: FixupKind::Nnn, token });
+            self.emit(Op::Jump(0))?;
+        }
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement = token.clone();
+            self.compile_statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let (token, message) = match block {
+                Block::If { token, .. } => (token, "this 'if' has no 'end'"),
+                Block::Loop { token, .. } => (token, "this 'loop' has no 'again'"),
+            };
+            return Err(self.error(token, message));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let value = match (self.labels.get(&fixup.token.text), self.constants.get(&fixup.token.text)) {
+                (Some(&addr), _) => addr as i64,
+                (None, Some(&value)) => value.floor() as i64,
+                (None, None) => return Err(self.error(&fixup.token, format!("'{}' is not defined", fixup.token.text))),
+            };
+            self.statement = fixup.token.clone();
+            self.apply_fixup(fixup.addr, fixup.kind, value)?;
+        }
+
+        self.source_map.sort_by_key(|line| line.addr);
+        Ok(Assembly {
+            bytes: self.rom,
+            labels: self.labels.into_iter().map(|(name, addr)| (name, addr as u16)).collect::<BTreeMap<_, _>>(),
+            source_map: self.source_map,
+        })
+    }
+
+    fn apply_fixup(&mut self, addr: u32, kind: FixupKind, value: i64) -> Result<(), AsmError> {
+        let token = self.statement.clone();
+        match kind {
+            FixupKind::Nnn => {
+                let value = self.check(&token, value, 0, 0xFFF, "address")?;
+                let index = (addr - START_ADDR as u32) as usize;
+                self.rom[index] = (self.rom[index] & 0xF0) | (value >> 8) as u8;
+                self.rom[index + 1] = value as u8;
+            },
+            FixupKind::Word => {
+                let value = self.check(&token, value, 0, 0xFFFF, "address")?;
+                let index = (addr - START_ADDR as u32) as usize;
+                self.rom[index..index + 2].copy_from_slice(&(value as u16).to_be_bytes());
+            },
+            FixupKind::Byte { shift, high } => {
+                // A nibble in the high bits leaves room for a 12-bit address.
+                let max = if high == 0 { 0xFFFF } else { 0xFFF };
+                let value = self.check(&token, value, 0, max, "address")?;
+                let index = (addr - START_ADDR as u32) as usize;
+                self.rom[index] = high | (value >> shift) as u8;
+            },
+        }
+        Ok(())
+    }
+
+    // Writes bytes at the current address.
+    fn emit_bytes(&mut self, bytes: &[u8]) -> Result<(), AsmError> {
+        let start = (self.here - START_ADDR as u32) as usize;
+        let end = start + bytes.len();
+        if end > self.target.max_rom_size() {
+            let message = format!("the program does not fit in {} memory", self.target.name());
+            return Err(self.error(&self.statement, message));
+        }
+        if self.rom.len() < end {
+            self.rom.resize(end, 0);
+        }
+        self.rom[start..end].copy_from_slice(bytes);
+        self.here += bytes.len() as u32;
+        Ok(())
+    }
+
+    fn emit(&mut self, op: Op) -> Result<(), AsmError> {
+        let needs = Target::of(&op);
+        if needs > self.target {
+            let message = format!(
+                "'{}' needs {}, but the target is {}",
+                self.statement.text,
+                needs.name(),
+                self.target.name()
+            );
+            return Err(self.error(&self.statement, message));
+        }
+
+        self.source_map.push(SourceLine { addr: self.here as u16, file: self.file.clone(), line: self.statement.line });
+        self.emit_bytes(&op.encode())
+    }
+
+    // Writes a jump at `at` that goes to `target`. Used to fill in jumps out of blocks.
+    fn patch_jump(&mut self, at: u32, target: u32) -> Result<(), AsmError> {
+        self.apply_fixup(at, FixupKind::Nnn, target as i64)
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => Ok(token),
+            None => Err(self.error(&self.statement, format!("'{}' is missing something after it", self.statement.text))),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(&token, format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn check(&self, token: &Token, value: i64, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        if value < min || value > max {
+            return Err(self.error(token, format!("{} {} is out of range ({} to {})", what, value, min, max)));
+        }
+        Ok(value)
+    }
+
+    fn register_of(&self, text: &str) -> Option<u8> {
+        if let Some(&reg) = self.aliases.get(text) {
+            return Some(reg);
+        }
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.register_of(&token.text)
+            .ok_or_else(|| self.error(&token, format!("expected a register, found '{}'", token.text)))
+    }
+
+    // Checks that a name can be given to a label, constant, alias or macro.
+    fn check_name(&self, token: &Token) -> Result<(), AsmError> {
+        let name = token.text.as_str();
+        let valid = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && parse_number(name).is_none()
+            && self.register_of(name).is_none()
+            && !KEYWORDS.contains(&name);
+        if !valid {
+            return Err(self.error(token, format!("'{}' cannot be used as a name", name)));
+        }
+        let taken = self.labels.contains_key(name)
+            || self.constants.contains_key(name)
+            || self.aliases.contains_key(name)
+            || self.macros.contains_key(name);
+        if taken {
+            return Err(self.error(token, format!("'{}' is defined more than once", name)));
+        }
+        Ok(())
+    }
+
+    // Reads a number, constant, label that has been defined, or a `{ ... }` calculation.
+    fn number(&mut self, token: &Token) -> Result<f64, AsmError> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Ok(value);
+        }
+        if let Some(&addr) = self.labels.get(&token.text) {
+            return Ok(addr as f64);
+        }
+        if token.text == "{" {
+            let value = self.calc_expr()?;
+            self.expect("}")?;
+            return Ok(value);
+        }
+        Err(self.error(token, format!("expected a number, found '{}'", token.text)))
+    }
+
+    // Reads a value and checks that it fits in the given range.
+    fn value(&mut self, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let token = self.next()?;
+        let value = self.number(&token)?.floor() as i64;
+        self.check(&token, value, min, max, what)
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        self.value(-128, 255, "byte").map(|value| value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        self.value(0, 15, "value").map(|value| value as u8)
+    }
+
+    // Reads an address. Labels that haven't been defined yet are filled in at `at` once everything is compiled.
+    fn addr(&mut self, at: u32, kind: FixupKind, max: i64) -> Result<i64, AsmError> {
+        let token = self.next()?;
+        let is_name = parse_number(&token.text).is_none() && token.text != "{";
+        let known = self.labels.contains_key(&token.text) || self.constants.contains_key(&token.text);
+        if is_name && !known {
+            if self.register_of(&token.text).is_some() {
+                return Err(self.error(&token, format!("expected an address, found '{}'", token.text)));
+            }
+            self.fixups.push(Fixup { addr: at, kind, token });
+            return Ok(0);
+        }
+
+        let value = self.number(&token)?.floor() as i64;
+        self.check(&token, value, 0, max, "address")
+    }
+
+    fn calc_expr(&mut self) -> Result<f64, AsmError> {
+        // Octo evaluates calculations from right to left, and all operators have the same precedence.
+        let lhs = self.calc_term()?;
+        if matches!(self.peek(), Some("}") | Some(")") | None) {
+            return Ok(lhs);
+        }
+
+        let op = self.next()?;
+        if !CALC_BINARY.contains(&op.text.as_str()) {
+            return Err(self.error(&op, format!("'{}' is not an operator", op.text)));
+        }
+        let rhs = self.calc_expr()?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+        Ok(match op.text.as_str() {
+            "-" => lhs - rhs,
+            "+" => lhs + rhs,
+            "*" => lhs * rhs,
+            "/" | "%" if rhs == 0.0 => return Err(self.error(&op, "division by zero")),
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.wrapping_shl(b as u32) as f64,
+            ">>" => a.wrapping_shr(b as u32) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => truth(lhs < rhs),
+            "<=" => truth(lhs <= rhs),
+            "==" => truth(lhs == rhs),
+            "!=" => truth(lhs != rhs),
+            ">=" => truth(lhs >= rhs),
+            _ => truth(lhs > rhs),
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, AsmError> {
+        let token = self.next()?;
+        let text = token.text.as_str();
+        if text == "(" {
+            let value = self.calc_expr()?;
+            self.expect(")")?;
+            return Ok(value);
+        }
+        if parse_number(text).is_none() && CALC_UNARY.contains(&text) {
+            let value = self.calc_term()?;
+            return Ok(match text {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => if value == 0.0 { 1.0 } else { 0.0 },
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => if value == 0.0 { 0.0 } else { value.signum() },
+                "ceil" => value.ceil(),
+                "floor" => value.floor(),
+                _ => {
+                    // `@` reads back a byte that has already been compiled.
+                    let index = value as i64 - START_ADDR as i64;
+                    let byte = usize::try_from(index).ok().and_then(|index| self.rom.get(index));
+                    match byte {
+                        Some(&byte) => byte as f64,
+                        None => return Err(self.error(&token, format!("nothing has been compiled at {}", value))),
+                    }
+                },
+            });
+        }
+        match text {
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            "HERE" => Ok(self.here as f64),
+            _ => self.number(&token),
+        }
+    }
+
+    // Reads a condition such as `v0 == 5`, `v1 != v2` or `v3 key`.
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        if !matches!(op.text.as_str(), "key" | "-key" | "==" | "!=" | "<" | ">" | "<=" | ">=") {
+            return Err(self.error(&op, format!("'{}' is not a comparison", op.text)));
+        }
+        if op.text.ends_with("key") {
+            return Ok(Condition { x, op, rhs: Rhs::None });
+        }
+
+        let rhs = match self.tokens.front().and_then(|token| self.register_of(&token.text)) {
+            Some(y) => {
+                self.tokens.pop_front();
+                Rhs::Reg(y)
+            },
+            None => Rhs::Byte(self.byte()?),
+        };
+        Ok(Condition { x, op, rhs })
+    }
+
+    // Compiles a condition into a skip. With `skip_when` true, the next instruction is skipped when the
+    // condition holds, otherwise it is skipped when the condition doesn't hold.
+    fn emit_condition(&mut self, cond: Condition, skip_when: bool) -> Result<(), AsmError> {
+        let Condition { x, op, rhs } = cond;
+        let (x, rhs) = match op.text.as_str() {
+            "key" => return self.emit(if skip_when { Op::SkipKey(x) } else { Op::SkipNotKey(x) }),
+            "-key" => return self.emit(if skip_when { Op::SkipNotKey(x) } else { Op::SkipKey(x) }),
+            // Skipping when "not equal" holds is the same as skipping when "equal" doesn't.
+            "==" => return self.emit_skip(x, rhs, skip_when),
+            "!=" => return self.emit_skip(x, rhs, !skip_when),
+            _ => (x, rhs),
+        };
+
+        // The rest are worked out with a subtraction in vF, whose flag is 1 when there is no borrow.
+        let t = COMPARE_TEMP;
+        if x == t || matches!(rhs, Rhs::Reg(COMPARE_TEMP)) {
+            return Err(self.error(&op, "'<', '>', '<=' and '>=' use vf, so it can't be compared with them"));
+        }
+        // For each comparison: whether the left side is subtracted from the right, and the flag when it holds.
+        let (reversed, flag) = match op.text.as_str() {
+            "<" => (false, 0),
+            ">" => (true, 0),
+            "<=" => (true, 1),
+            _ => (false, 1),
+        };
+        match rhs {
+            Rhs::Reg(y) => {
+                let (a, b) = if reversed { (y, x) } else { (x, y) };
+                self.emit(Op::SetReg(t, a))?;
+                self.emit(Op::Sub(t, b))?;
+            },
+            Rhs::Byte(n) => {
+                // vf = n - x for the reversed comparisons, vf = x - n otherwise.
+                self.emit(Op::SetByte(t, n))?;
+                self.emit(if reversed { Op::Sub(t, x) } else { Op::SubN(t, x) })?;
+            },
+            Rhs::None => {},
+        }
+        self.emit_skip(t, Rhs::Byte(flag), skip_when)
+    }
+
+    // Emits a skip that compares a register for equality.
+    fn emit_skip(&mut self, x: u8, rhs: Rhs, skip_if_equal: bool) -> Result<(), AsmError> {
+        let op = match rhs {
+            Rhs::Reg(y) if skip_if_equal => Op::SkipEqReg(x, y),
+            Rhs::Reg(y) => Op::SkipNeReg(x, y),
+            Rhs::Byte(n) if skip_if_equal => Op::SkipEqByte(x, n),
+            Rhs::Byte(n) => Op::SkipNeByte(x, n),
+            Rhs::None => unreachable!("only key conditions have nothing to compare with"),
+        };
+        self.emit(op)
+    }
+
+    fn compile_statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.labels.insert(name.text, self.here);
+            },
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let reg = self.register()?;
+                self.aliases.insert(name.text, reg);
+            },
+            ":const" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let value_token = self.next()?;
+                let value = self.number(&value_token)?;
+                self.constants.insert(name.text, value);
+            },
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.expect("{")?;
+                let value = self.calc_expr()?;
+                self.expect("}")?;
+                self.constants.insert(name.text, value);
+            },
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_bytes(&[byte])?;
+            },
+            ":pointer" => {
+                let addr = self.addr(self.here, FixupKind::Word, 0xFFFF)?;
+                self.emit_bytes(&(addr as u16).to_be_bytes())?;
+            },
+            ":org" => {
+                let max = (START_ADDR as usize + self.target.max_rom_size()) as i64;
+                self.here = self.value(START_ADDR as i64, max, "address")? as u32;
+            },
+            ":call" => {
+                let addr = self.addr(self.here, FixupKind::Nnn, 0xFFF)?;
+                self.emit(Op::Call(addr as u16))?;
+            },
+            ":unpack" => {
+                // Loads an address into v0 and v1. With a nibble, it is put in the top of v0.
+                let high = match self.peek() {
+                    Some("long") => {
+                        self.tokens.pop_front();
+                        0
+                    },
+                    _ => self.nibble()? << 4,
+                };
+                let (hi_at, lo_at) = (self.here + 1, self.here + 3);
+                let max = if high == 0 { 0xFFFF } else { 0xFFF };
+                let fixups = self.fixups.len();
+                let addr = self.addr(hi_at, FixupKind::Byte { shift: 8, high }, max)?;
+                if self.fixups.len() > fixups {
+                    // The low byte needs filling in too.
+                    let token = self.fixups[fixups].token.clone();
+                    self.fixups.push(Fixup { addr: lo_at, kind: FixupKind::Byte { shift: 0, high: 0 }, token });
+                }
+                self.emit(Op::SetByte(0, high | (addr >> 8) as u8))?;
+                self.emit(Op::SetByte(1, addr as u8))?;
+            },
+            ":next" => {
+                // Names the second byte of the next instruction, for code that modifies itself.
+                let name = self.next()?;
+                self.check_name(&name)?;
+                self.labels.insert(name.text, self.here + 1);
+            },
+            ":macro" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let mut params = Vec::new();
+                loop {
+                    let param = self.next()?;
+                    if param.text == "{" {
+                        break;
+                    }
+                    params.push(param.text);
+                }
+                let mut body = Vec::new();
+                let mut depth = 1;
+                loop {
+                    let token = self.next()?;
+                    match token.text.as_str() {
+                        "{" => depth += 1,
+                        "}" => depth -= 1,
+                        _ => {},
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    body.push(token);
+                }
+                self.macros.insert(name.text, Macro { params, body });
+            },
+            ":breakpoint" => {
+                self.next()?;
+            },
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            },
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(text) if text.starts_with('"') => Some(self.next()?.text.trim_matches('"').to_string()),
+                    _ => None,
+                };
+                self.expect("{")?;
+                let value = self.calc_expr()?;
+                self.expect("}")?;
+                if value == 0.0 {
+                    let message = message.unwrap_or_else(|| "assertion failed".to_string());
+                    return Err(self.error(&token, message));
+                }
+            },
+            "loop" => self.blocks.push(Block::Loop { start: self.here, breaks: Vec::new(), token }),
+            "again" => {
+                let Some(Block::Loop { start, breaks, .. }) = self.blocks.pop() else {
+                    return Err(self.error(&token, "'again' without a 'loop'"));
+                };
+                self.emit(Op::Jump(0))?;
+                self.patch_jump(self.here - 2, start)?;
+                for at in breaks {
+                    self.patch_jump(at, self.here)?;
+                }
+            },
+            "while" => {
+                let Some(index) = self.blocks.iter().rposition(|block| matches!(block, Block::Loop { .. })) else {
+                    return Err(self.error(&token, "'while' is only allowed inside a loop"));
+                };
+                let cond = self.condition()?;
+                self.emit_condition(cond, true)?;
+                if let Block::Loop { breaks, .. } = &mut self.blocks[index] {
+                    breaks.push(self.here);
+                }
+                self.emit(Op::Jump(0))?;
+            },
+            "if" => {
+                let cond = self.condition()?;
+                let kind = self.next()?;
+                match kind.text.as_str() {
+                    "then" => self.emit_condition(cond, false)?,
+                    "begin" => {
+                        // Jump over the block when the condition doesn't hold.
+                        self.emit_condition(cond, true)?;
+                        self.blocks.push(Block::If { jump: self.here, has_else: false, token });
+                        self.emit(Op::Jump(0))?;
+                    },
+                    _ => return Err(self.error(&kind, format!("expected 'then' or 'begin', found '{}'", kind.text))),
+                }
+            },
+            "else" => {
+                let Some(Block::If { jump, has_else: false, .. }) = self.blocks.pop() else {
+                    return Err(self.error(&token, "'else' without an 'if ... begin'"));
+                };
+                let end_jump = self.here;
+                self.emit(Op::Jump(0))?;
+                self.patch_jump(jump, self.here)?;
+                self.blocks.push(Block::If { jump: end_jump, has_else: true, token });
+            },
+            "end" => {
+                let Some(Block::If { jump, .. }) = self.blocks.pop() else {
+                    return Err(self.error(&token, "'end' without an 'if ... begin'"));
+                };
+                self.patch_jump(jump, self.here)?;
+            },
+            "clear" => self.emit(Op::Cls)?,
+            "return" | ";" => self.emit(Op::Ret)?,
+            "exit" => self.emit(Op::Exit)?,
+            "hires" => self.emit(Op::Hires)?,
+            "lores" => self.emit(Op::Lores)?,
+            "scroll-left" => self.emit(Op::ScrollLeft)?,
+            "scroll-right" => self.emit(Op::ScrollRight)?,
+            "audio" => self.emit(Op::Audio)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Op::ScrollDown(n))?;
+            },
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Op::ScrollUp(n))?;
+            },
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(Op::Plane(n))?;
+            },
+            "jump" => {
+                let addr = self.addr(self.here, FixupKind::Nnn, 0xFFF)?;
+                self.emit(Op::Jump(addr as u16))?;
+            },
+            "jump0" => {
+                let addr = self.addr(self.here, FixupKind::Nnn, 0xFFF)?;
+                self.emit(Op::JumpV0(addr as u16))?;
+            },
+            "native" => {
+                let addr = self.addr(self.here, FixupKind::Nnn, 0xFFF)?;
+                self.emit(Op::Sys(addr as u16))?;
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Op::Draw(x, y, n))?;
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                let op = if self.peek() == Some("-") {
+                    self.tokens.pop_front();
+                    let y = self.register()?;
+                    if token.text == "save" { Op::StoreRange(x, y) } else { Op::LoadRange(x, y) }
+                } else if token.text == "save" {
+                    Op::Store(x)
+                } else {
+                    Op::Load(x)
+                };
+                self.emit(op)?;
+            },
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Op::SaveFlags(x))?;
+            },
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Op::LoadFlags(x))?;
+            },
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Op::Bcd(x))?;
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let op = match token.text.as_str() {
+                    "delay" => Op::SetDelay(x),
+                    "buzzer" => Op::SetSound(x),
+                    _ => Op::Pitch(x),
+                };
+                self.emit(op)?;
+            },
+            "i" => self.compile_i()?,
+            text if self.register_of(text).is_some() => self.compile_register(&token)?,
+            text if self.macros.contains_key(text) => self.expand_macro(&token)?,
+            text if parse_number(text).is_some() || self.constants.contains_key(text) || text == "{" => {
+                // Numbers on their own are data.
+                let value = self.number(&token)?.floor() as i64;
+                let byte = self.check(&token, value, -128, 255, "byte")?;
+                self.emit_bytes(&[byte as u8])?;
+            },
+            text if KEYWORDS.contains(&text) || text.starts_with(':') || text.starts_with('"') => {
+                return Err(self.error(&token, format!("'{}' is not allowed here", text)));
+            },
+            _ => {
+                // Any other name is a subroutine call.
+                let at = self.here;
+                match self.labels.get(&token.text) {
+                    Some(&addr) => {
+                        let addr = self.check(&token, addr as i64, 0, 0xFFF, "address")?;
+                        self.emit(Op::Call(addr as u16))?;
+                    },
+                    None => {
+                        self.fixups.push(Fixup { addr: at, kind: FixupKind::Nnn, token });
+                        self.emit(Op::Call(0))?;
+                    },
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn compile_i(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.tokens.pop_front();
+                    let x = self.register()?;
+                    self.emit(Op::Font(x))
+                },
+                Some("bighex") => {
+                    self.tokens.pop_front();
+                    let x = self.register()?;
+                    self.emit(Op::BigFont(x))
+                },
+                Some("long") => {
+                    self.tokens.pop_front();
+                    let addr = self.addr(self.here + 2, FixupKind::Word, 0xFFFF)?;
+                    self.emit(Op::LongI(addr as u16))
+                },
+                _ => {
+                    let addr = self.addr(self.here, FixupKind::Nnn, 0xFFF)?;
+                    self.emit(Op::SetI(addr as u16))
+                },
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Op::AddI(x))
+            },
+            _ => Err(self.error(&op, format!("'{}' can't be used with i", op.text))),
+        }
+    }
+
+    fn compile_register(&mut self, token: &Token) -> Result<(), AsmError> {
+        let x = self.register_of(&token.text).unwrap();
+        let op = self.next()?;
+        let rhs = self.tokens.front().and_then(|token| self.register_of(&token.text));
+        if let Some(y) = rhs {
+            let op = match op.text.as_str() {
+                ":=" => Op::SetReg(x, y),
+                "|=" => Op::Or(x, y),
+                "&=" => Op::And(x, y),
+                "^=" => Op::Xor(x, y),
+                "+=" => Op::Add(x, y),
+                "-=" => Op::Sub(x, y),
+                ">>=" => Op::Shr(x, y),
+                "=-" => Op::SubN(x, y),
+                "<<=" => Op::Shl(x, y),
+                _ => return Err(self.error(&op, format!("'{}' can't be used with two registers", op.text))),
+            };
+            self.tokens.pop_front();
+            return self.emit(op);
+        }
+
+        let op = match (op.text.as_str(), self.peek()) {
+            (":=", Some("random")) => {
+                self.tokens.pop_front();
+                Op::Rand(x, self.byte()?)
+            },
+            (":=", Some("delay")) => {
+                self.tokens.pop_front();
+                Op::GetDelay(x)
+            },
+            (":=", Some("key")) => {
+                self.tokens.pop_front();
+                Op::WaitKey(x)
+            },
+            (":=", _) => Op::SetByte(x, self.byte()?),
+            ("+=", _) => Op::AddByte(x, self.byte()?),
+            // There's no instruction for subtracting a constant, so add its negative instead.
+            ("-=", _) => Op::AddByte(x, self.byte()?.wrapping_neg()),
+            _ => return Err(self.error(&op, format!("'{}' needs a register after it", op.text))),
+        };
+        self.emit(op)
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error(token, "too many macros were expanded, does a macro use itself?"));
+        }
+
+        let mac = self.macros[&token.text].clone();
+        let mut args = HashMap::new();
+        for param in &mac.params {
+            args.insert(param.clone(), self.next()?.text);
+        }
+        for body_token in mac.body.iter().rev() {
+            let mut expanded = body_token.clone();
+            if let Some(arg) = args.get(&body_token.text) {
+                expanded.text = arg.clone();
+            }
+            self.tokens.push_front(expanded);
+        }
+        Ok(())
+    }
+}
+
+// Words that mean something to the compiler, and so can't be used as names.
+const KEYWORDS: [&str; 43] = [
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=", "key", "-key", "hex",
+    "bighex", "random", "delay", "buzzer", "pitch", "if", "then", "begin", "else", "end", "loop", "again", "while",
+    "jump", "jump0", "native", "sprite", "save", "load", "saveflags", "loadflags", "bcd", "long", "i", "clear",
+];
+
+// Parses a number written in decimal, hex (0xFF) or binary (0b1010), possibly negative.
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value as f64 } else { value as f64 })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::disasm::Syntax;
+    use crate::{flow, Emu, Quirks};
+
+    fn bytes(source: &str) -> Vec<u8> {
+        compile(source, Target::XoChip).unwrap_or_else(|err| panic!("{}", err)).bytes
+    }
+
+    fn error(source: &str, target: Target) -> String {
+        compile(source, target).unwrap_err().to_string()
+    }
+
+    // Compiles a program and runs it until it settles into `halt`, returning the registers it finishes with.
+    fn registers(source: &str) -> [u8; 16] {
+        let source = format!("{}\n: halt jump halt", source);
+        let mut emu = Emu::with_seed(Quirks::CHIP_48, 0);
+        emu.load_rom(&bytes(&source)).unwrap();
+        for _ in 0..1000 {
+            emu.tick().unwrap();
+        }
+        emu.cpu_state().v
+    }
+
+    #[test]
+    fn comparisons() {
+        let values = [0u8, 1, 127, 128, 254, 255];
+        let holds = |op: &str, a: u8, b: u8| match op {
+            "==" => a == b,
+            "!=" => a != b,
+            "<" => a < b,
+            ">" => a > b,
+            "<=" => a <= b,
+            _ => a >= b,
+        };
+        for op in ["==", "!=", "<", ">", "<=", ">="] {
+            for a in values {
+                for b in values {
+                    let source = format!(
+                        "v0 := {a} v1 := {b}
+                         if v0 {op} v1 then v2 := 1
+                         if v0 {op} {b} then v3 := 1
+                         if v0 {op} v1 begin v4 := 1 else v4 := 2 end
+                         if v0 {op} {b} begin v5 := 1 end"
+                    );
+                    let v = registers(&source);
+                    let expected = if holds(op, a, b) { [1, 1, 1, 1] } else { [0, 0, 2, 0] };
+                    assert_eq!(v[2..6], expected, "{} {} {}", a, op, b);
+                    assert_eq!((v[0], v[1]), (a, b), "{} {} {} changed its operands", a, op, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn comparisons_cant_use_vf() {
+        assert_eq!(
+            error("if vf < 3 then v0 := 1", Target::Chip8),
+            "<input>:1:7: '<', '>', '<=' and '>=' use vf, so it can't be compared with them"
+        );
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let source = "v0 := 5
+            if v0 > 2 begin
+                if v0 == 5 begin v1 := 1 else v1 := 2 end
+                v2 := 3
+            else
+                v2 := 4
+            end";
+        assert_eq!(registers(source)[1..3], [1, 3]);
+    }
+
+    #[test]
+    fn loops() {
+        // Counts v0 up to 10, adding 2 to v1 each time round.
+        let source = "loop
+                while v0 != 10
+                v0 += 1
+                v1 += 2
+            again
+            v2 := 1";
+        assert_eq!(registers(source)[..3], [10, 20, 1]);
+
+        let source = "loop v0 += 1 while v0 < 3 v1 += 1 while v1 < 100 again";
+        assert_eq!(registers(source)[..2], [3, 2]);
+
+        assert_eq!(error("while v0 == 1", Target::Chip8), "<input>:1:1: 'while' is only allowed inside a loop");
+        assert_eq!(error("loop v0 += 1", Target::Chip8), "<input>:1:1: this 'loop' has no 'again'");
+        assert_eq!(error("if v0 == 1 begin", Target::Chip8), "<input>:1:1: this 'if' has no 'end'");
+    }
+
+    #[test]
+    fn calc() {
+        let source = ":const WIDTH 64
+            :calc HALF { WIDTH / 2 }
+            :calc RIGHT { HALF - 4 * 2 + -1 }
+            :calc LEFT { ( HALF - 4 ) * 2 }
+            :calc THIRD { 10 / 3 }
+            v0 := HALF v1 := RIGHT v2 := LEFT v3 := THIRD";
+        // Like Octo, calculations are worked out from right to left: 32 - (4 * (2 + -1)).
+        assert_eq!(registers(source)[..4], [32, 28, 56, 3]);
+
+        assert_eq!(error(":calc BIG { 16 * 16 } v0 := BIG", Target::Chip8), "<input>:1:29: byte 256 is out of range (-128 to 255)");
+        assert_eq!(error(":assert \"too big\" { 1 > 2 }", Target::Chip8), "<input>:1:1: too big");
+    }
+
+    #[test]
+    fn unpack() {
+        // data ends up at 0x206, after the two loads and the jump.
+        assert_eq!(bytes(":unpack 0xA data jump 0x200 : data 1"), [0x60, 0xA2, 0x61, 0x06, 0x12, 0x00, 0x01]);
+        assert_eq!(bytes(":unpack long data jump 0x200 : data 1"), [0x60, 0x02, 0x61, 0x06, 0x12, 0x00, 0x01]);
+        assert_eq!(bytes(": data 1 :unpack 0x1 data"), [0x01, 0x60, 0x12, 0x61, 0x00]);
+    }
+
+    #[test]
+    fn macros() {
+        let source = ":macro add-to reg amount { reg += amount }
+            :macro twice what { what what }
+            add-to v0 3
+            add-to v1 5
+            twice clear";
+        assert_eq!(bytes(source), [0x70, 0x03, 0x71, 0x05, 0x00, 0xE0, 0x00, 0xE0]);
+
+        assert_eq!(
+            error(":macro forever { forever } forever", Target::Chip8),
+            "<input>:1:18: too many macros were expanded, does a macro use itself?"
+        );
+    }
+
+    #[test]
+    fn instructions_need_the_right_target() {
+        assert_eq!(error("hires", Target::Chip8), "<input>:1:1: 'hires' needs SUPER-CHIP, but the target is CHIP-8");
+        assert_eq!(error("  saveflags v3", Target::Chip8), "<input>:1:3: 'saveflags' needs SUPER-CHIP, but the target is CHIP-8");
+        assert_eq!(error("plane 1", Target::SuperChip), "<input>:1:1: 'plane' needs XO-CHIP, but the target is SUPER-CHIP");
+        assert_eq!(error("i := long 0x1234", Target::SuperChip), "<input>:1:1: 'i' needs XO-CHIP, but the target is SUPER-CHIP");
+        assert_eq!(error("save v1 - v3", Target::Chip8), "<input>:1:1: 'save' needs XO-CHIP, but the target is CHIP-8");
+        assert!(compile("hires scroll-left exit", Target::SuperChip).is_ok());
+        assert!(compile("plane 3 audio save v1 - v3", Target::XoChip).is_ok());
+    }
+
+    // The control flow disassembly of every ROM compiles back into the same bytes.
+    #[test]
+    fn roms_survive_a_round_trip() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("rom_files");
+        let mut roms: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        roms.sort();
+        assert!(!roms.is_empty());
+        for path in roms {
+            let rom = fs::read(&path).unwrap();
+            let listing = flow::listing(&rom, START_ADDR, Syntax::Octo, true).unwrap();
+            let assembly = compile(&listing, Target::XoChip)
+                .unwrap_or_else(|err| panic!("{} doesn't compile: {}\n{}", path.display(), err, listing));
+            assert!(assembly.bytes == rom, "{} compiles into different bytes:\n{}", path.display(), listing);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+// Every instruction the emulator understands, decoded into a form that is easier to work with than raw
+// opcodes. Registers are stored as their index (0 to 15), addresses and bytes as plain numbers.
+// Used by the disassembler and anything else that needs to look at a program without running it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    // CHIP-8.
+    Sys(u16), // 0NNN, a machine code routine on the original hardware. The emulator skips 0000 and rejects the rest.
+    Cls, // 00E0
+    Ret, // 00EE
+    Jump(u16), // 1NNN
+    Call(u16), // 2NNN
+    SkipEqByte(u8, u8), // 3XNN
+    SkipNeByte(u8, u8), // 4XNN
+    SkipEqReg(u8, u8), // 5XY0
+    SetByte(u8, u8), // 6XNN
+    AddByte(u8, u8), // 7XNN
+    SetReg(u8, u8), // 8XY0
+    Or(u8, u8), // 8XY1
+    And(u8, u8), // 8XY2
+    Xor(u8, u8), // 8XY3
+    Add(u8, u8), // 8XY4
+    Sub(u8, u8), // 8XY5
+    Shr(u8, u8), // 8XY6
+    SubN(u8, u8), // 8XY7
+    Shl(u8, u8), // 8XYE
+    SkipNeReg(u8, u8), // 9XY0
+    SetI(u16), // ANNN
+    JumpV0(u16), // BNNN
+    Rand(u8, u8), // CXNN
+    Draw(u8, u8, u8), // DXYN
+    SkipKey(u8), // EX9E
+    SkipNotKey(u8), // EXA1
+    GetDelay(u8), // FX07
+    WaitKey(u8), // FX0A
+    SetDelay(u8), // FX15
+    SetSound(u8), // FX18
+    AddI(u8), // FX1E
+    Font(u8), // FX29
+    Bcd(u8), // FX33
+    Store(u8), // FX55
+    Load(u8), // FX65
+
+    // SUPER-CHIP.
+    ScrollDown(u8), // 00CN
+    ScrollRight, // 00FB
+    ScrollLeft, // 00FC
+    Exit, // 00FD
+    Lores, // 00FE
+    Hires, // 00FF
+    BigFont(u8), // FX30
+    SaveFlags(u8), // FX75
+    LoadFlags(u8), // FX85
+
+    // XO-CHIP.
+    ScrollUp(u8), // 00DN
+    StoreRange(u8, u8), // 5XY2
+    LoadRange(u8, u8), // 5XY3
+    LongI(u16), // F000 NNNN
+    Plane(u8), // FN01
+    Audio, // F002
+    Pitch(u8), // FX3A
+}
+
+impl Op {
+    // Decodes an opcode. `next` is the opcode after it, which is only needed for XO-CHIP's 4 byte F000 NNNN.
+    // F000 is only an instruction when xo_chip is set. Anywhere else it is 2 bytes of data, like any other
+    // opcode that is not part of any of the instruction sets, and None is returned.
+    pub fn decode(op: u16, next: Option<u16>, xo_chip: bool) -> Option<Op> {
+        let digit1 = (op & 0xF000) >> 12;
+        let x = ((op & 0x0F00) >> 8) as u8;
+        let y = ((op & 0x00F0) >> 4) as u8;
+        let n = (op & 0x000F) as u8;
+        let nn = (op & 0x00FF) as u8;
+        let nnn = op & 0x0FFF;
+
+        let decoded = match (digit1, x, y, n) {
+            (0, 0, 0xC, _) => Op::ScrollDown(n),
+            (0, 0, 0xD, _) => Op::ScrollUp(n),
+            (0, 0, 0xE, 0) => Op::Cls,
+            (0, 0, 0xE, 0xE) => Op::Ret,
+            (0, 0, 0xF, 0xB) => Op::ScrollRight,
+            (0, 0, 0xF, 0xC) => Op::ScrollLeft,
+            (0, 0, 0xF, 0xD) => Op::Exit,
+            (0, 0, 0xF, 0xE) => Op::Lores,
+            (0, 0, 0xF, 0xF) => Op::Hires,
+            (0, _, _, _) => Op::Sys(nnn),
+            (1, _, _, _) => Op::Jump(nnn),
+            (2, _, _, _) => Op::Call(nnn),
+            (3, _, _, _) => Op::SkipEqByte(x, nn),
+            (4, _, _, _) => Op::SkipNeByte(x, nn),
+            (5, _, _, 0) => Op::SkipEqReg(x, y),
+            (5, _, _, 2) => Op::StoreRange(x, y),
+            (5, _, _, 3) => Op::LoadRange(x, y),
+            (6, _, _, _) => Op::SetByte(x, nn),
+            (7, _, _, _) => Op::AddByte(x, nn),
+            (8, _, _, 0) => Op::SetReg(x, y),
+            (8, _, _, 1) => Op::Or(x, y),
+            (8, _, _, 2) => Op::And(x, y),
+            (8, _, _, 3) => Op::Xor(x, y),
+            (8, _, _, 4) => Op::Add(x, y),
+            (8, _, _, 5) => Op::Sub(x, y),
+            (8, _, _, 6) => Op::Shr(x, y),
+            (8, _, _, 7) => Op::SubN(x, y),
+            (8, _, _, 0xE) => Op::Shl(x, y),
+            (9, _, _, 0) => Op::SkipNeReg(x, y),
+            (0xA, _, _, _) => Op::SetI(nnn),
+            (0xB, _, _, _) => Op::JumpV0(nnn),
+            (0xC, _, _, _) => Op::Rand(x, nn),
+            (0xD, _, _, _) => Op::Draw(x, y, n),
+            (0xE, _, 9, 0xE) => Op::SkipKey(x),
+            (0xE, _, 0xA, 1) => Op::SkipNotKey(x),
+            (0xF, 0, 0, 0) if xo_chip => Op::LongI(next?),
+            (0xF, _, 0, 1) => Op::Plane(x),
+            (0xF, 0, 0, 2) => Op::Audio,
+            (0xF, _, 0, 7) => Op::GetDelay(x),
+            (0xF, _, 0, 0xA) => Op::WaitKey(x),
+            (0xF, _, 1, 5) => Op::SetDelay(x),
+            (0xF, _, 1, 8) => Op::SetSound(x),
+            (0xF, _, 1, 0xE) => Op::AddI(x),
+            (0xF, _, 2, 9) => Op::Font(x),
+            (0xF, _, 3, 0) => Op::BigFont(x),
+            (0xF, _, 3, 3) => Op::Bcd(x),
+            (0xF, _, 3, 0xA) => Op::Pitch(x),
+            (0xF, _, 5, 5) => Op::Store(x),
+            (0xF, _, 6, 5) => Op::Load(x),
+            (0xF, _, 7, 5) => Op::SaveFlags(x),
+            (0xF, _, 8, 5) => Op::LoadFlags(x),
+            (_, _, _, _) => return None,
+        };
+        Some(decoded)
+    }
+
+    // Decodes the instruction at the start of `bytes`, if there is one.
+    pub fn decode_bytes(bytes: &[u8], xo_chip: bool) -> Option<Op> {
+        let op = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]);
+        let next = bytes.get(2..4).map(|next| u16::from_be_bytes([next[0], next[1]]));
+        Op::decode(op, next, xo_chip)
+    }
+
+    // Turns the instruction back into its bytes. Anything too big for its field is cut down to fit.
+    pub fn encode(&self) -> Vec<u8> {
+        // Builds an opcode from its four hex digits.
+        let op = |a: u16, b: u8, c: u8, d: u8| (a << 12) | ((b as u16 & 0xF) << 8) | ((c as u16 & 0xF) << 4) | (d as u16 & 0xF);
+        let nnn = |a: u16, nnn: u16| (a << 12) | (nnn & 0xFFF);
+        let xnn = |a: u16, x: u8, nn: u8| (a << 12) | ((x as u16 & 0xF) << 8) | nn as u16;
+
+        let opcode = match *self {
+            Op::Sys(addr) => nnn(0, addr),
+            Op::Cls => 0x00E0,
+            Op::Ret => 0x00EE,
+            Op::Jump(addr) => nnn(1, addr),
+            Op::Call(addr) => nnn(2, addr),
+            Op::SkipEqByte(x, nn) => xnn(3, x, nn),
+            Op::SkipNeByte(x, nn) => xnn(4, x, nn),
+            Op::SkipEqReg(x, y) => op(5, x, y, 0),
+            Op::SetByte(x, nn) => xnn(6, x, nn),
+            Op::AddByte(x, nn) => xnn(7, x, nn),
+            Op::SetReg(x, y) => op(8, x, y, 0),
+            Op::Or(x, y) => op(8, x, y, 1),
+            Op::And(x, y) => op(8, x, y, 2),
+            Op::Xor(x, y) => op(8, x, y, 3),
+            Op::Add(x, y) => op(8, x, y, 4),
+            Op::Sub(x, y) => op(8, x, y, 5),
+            Op::Shr(x, y) => op(8, x, y, 6),
+            Op::SubN(x, y) => op(8, x, y, 7),
+            Op::Shl(x, y) => op(8, x, y, 0xE),
+            Op::SkipNeReg(x, y) => op(9, x, y, 0),
+            Op::SetI(addr) => nnn(0xA, addr),
+            Op::JumpV0(addr) => nnn(0xB, addr),
+            Op::Rand(x, nn) => xnn(0xC, x, nn),
+            Op::Draw(x, y, n) => op(0xD, x, y, n),
+            Op::SkipKey(x) => op(0xE, x, 9, 0xE),
+            Op::SkipNotKey(x) => op(0xE, x, 0xA, 1),
+            Op::GetDelay(x) => op(0xF, x, 0, 7),
+            Op::WaitKey(x) => op(0xF, x, 0, 0xA),
+            Op::SetDelay(x) => op(0xF, x, 1, 5),
+            Op::SetSound(x) => op(0xF, x, 1, 8),
+            Op::AddI(x) => op(0xF, x, 1, 0xE),
+            Op::Font(x) => op(0xF, x, 2, 9),
+            Op::Bcd(x) => op(0xF, x, 3, 3),
+            Op::Store(x) => op(0xF, x, 5, 5),
+            Op::Load(x) => op(0xF, x, 6, 5),
+            Op::ScrollDown(n) => op(0, 0, 0xC, n),
+            Op::ScrollRight => 0x00FB,
+            Op::ScrollLeft => 0x00FC,
+            Op::Exit => 0x00FD,
+            Op::Lores => 0x00FE,
+            Op::Hires => 0x00FF,
+            Op::BigFont(x) => op(0xF, x, 3, 0),
+            Op::SaveFlags(x) => op(0xF, x, 7, 5),
+            Op::LoadFlags(x) => op(0xF, x, 8, 5),
+            Op::ScrollUp(n) => op(0, 0, 0xD, n),
+            Op::StoreRange(x, y) => op(5, x, y, 2),
+            Op::LoadRange(x, y) => op(5, x, y, 3),
+            Op::LongI(addr) => return [0xF0, 0x00, (addr >> 8) as u8, addr as u8].to_vec(),
+            Op::Plane(n) => op(0xF, n, 0, 1),
+            Op::Audio => 0xF002,
+            Op::Pitch(x) => op(0xF, x, 3, 0xA),
+        };
+        opcode.to_be_bytes().to_vec()
+    }
+
+    // How many bytes the instruction takes up. Everything is 2 bytes, apart from F000 NNNN.
+    pub fn size(&self) -> usize {
+        match self {
+            Op::LongI(_) => 4,
+            _ => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every opcode that decodes turns back into the same bytes, and takes up as many bytes as it says.
+    #[test]
+    fn decode_encode_round_trip() {
+        for xo_chip in [false, true] {
+            for word in 0..=0xFFFF_u16 {
+                let Some(op) = Op::decode(word, Some(0xABCD), xo_chip) else {
+                    continue;
+                };
+                let mut bytes = word.to_be_bytes().to_vec();
+                if op.size() == 4 {
+                    bytes.extend_from_slice(&[0xAB, 0xCD]);
+                }
+                assert_eq!(op.encode(), bytes, "{:04X} decoded as {:?}", word, op);
+                assert_eq!(Op::decode_bytes(&bytes, xo_chip), Some(op));
+            }
+        }
+    }
+
+    #[test]
+    fn long_i_is_only_an_instruction_for_xo_chip() {
+        assert_eq!(Op::decode(0xF000, Some(0x1234), true), Some(Op::LongI(0x1234)));
+        assert_eq!(Op::decode(0xF000, Some(0x1234), false), None);
+        // At the very end of a program there is no second word to read.
+        assert_eq!(Op::decode(0xF000, None, true), None);
+        assert_eq!(Op::decode_bytes(&[0xF0, 0x00], true), None);
+        // The rest of XO-CHIP decodes either way, since it doesn't change how big anything is.
+        assert_eq!(Op::decode(0xF002, None, false), Some(Op::Audio));
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        let known = |word| Op::decode(word, Some(0), true).is_some();
+        assert!(!known(0x5001));
+        assert!(!known(0x800F));
+        assert!(!known(0xE000));
+        assert!(!known(0xF0FF));
+        // Every other 0NNN is a machine code routine.
+        assert_eq!(Op::decode(0x0123, None, true), Some(Op::Sys(0x123)));
+    }
+}
@@ -0,0 +1,105 @@
+// The original CHIP-8 interpreter and the ones that came after it disagree on how a handful of
+// opcodes behave. Games were written against whichever interpreter their author had, so the
+// emulator needs to be told which behavior to use for each of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY and store the result in VX. Otherwise VX is shifted in place.
+    pub shift_uses_vy: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0 after the logic operation.
+    pub vf_reset: bool,
+    // How far FX55/FX65 move I along after storing or loading V0 through VX.
+    pub load_store_increment: IndexIncrement,
+    // BNNN is treated as BXNN, jumping to XNN plus VX instead of NNN plus V0.
+    pub jump_uses_vx: bool,
+    // DXYN cuts sprites off at the edge of the screen. Otherwise they wrap around to the other side.
+    pub clip_sprites: bool,
+    // DXYN waits for the next frame (the next call to tick_timers) before drawing.
+    pub display_wait: bool,
+    // Enables the XO-CHIP extensions: 64 KiB of RAM, two bitplanes, audio patterns and the extra opcodes.
+    pub xo_chip: bool,
+    // Enables the SUPER-CHIP opcodes: 00CN, 00FB and 00FC scrolling, 00FD exit, 00FE/00FF resolution
+    // switching, DXY0 16x16 sprites, the FX30 big font and the FX75/FX85 RPL flags.
+    // Without it they are invalid, and DXY0 draws nothing, like on the COSMAC VIP.
+    pub super_chip: bool,
+}
+
+// The interpreters disagree on where FX55/FX65 leave I.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    // I is left where it was.
+    Unchanged,
+    // I moves along by X, so it points at the last register that was stored or loaded.
+    ByX,
+    // I moves along by X + 1, so it points just past the last register that was stored or loaded.
+    ByXPlusOne,
+}
+
+impl IndexIncrement {
+    // How far I moves along after storing or loading V0 through VX.
+    pub fn step(self, x: usize) -> u16 {
+        match self {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => x as u16,
+            IndexIncrement::ByXPlusOne => x as u16 + 1,
+        }
+    }
+}
+
+impl Quirks {
+    // The original interpreter on the COSMAC VIP.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        vf_reset: true,
+        load_store_increment: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: true,
+        display_wait: true,
+        xo_chip: false,
+        super_chip: false,
+    };
+
+    // CHIP-48 on the HP-48 calculators, which most of the 90s game packs were written for.
+    // It came before SUPER-CHIP, so it has none of its opcodes, and its FX55/FX65 move I along by one
+    // less than the VIP's.
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        vf_reset: false,
+        load_store_increment: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+        xo_chip: false,
+        super_chip: false,
+    };
+
+    // SUPER-CHIP 1.1 as implemented by modern interpreters, where FX55/FX65 leave I alone.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        vf_reset: false,
+        load_store_increment: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+        xo_chip: false,
+        super_chip: true,
+    };
+
+    // XO-CHIP, as defined by the Octo reference interpreter. It builds on the SUPER-CHIP opcodes.
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        vf_reset: false,
+        load_store_increment: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        clip_sprites: false,
+        display_wait: false,
+        xo_chip: true,
+        super_chip: true,
+    };
+}
+
+impl Default for Quirks {
+    // Plain CHIP-8 programs expect the original interpreter.
+    fn default() -> Self {
+        Quirks::COSMAC_VIP
+    }
+}
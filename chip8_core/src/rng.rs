@@ -0,0 +1,99 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// A small xorshift random number generator for the CXNN opcode.
+// Given the same seed it always produces the same numbers, so test runs, replays
+// and save states are reproducible down to the last bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Xorshift gets stuck on zero forever, so a zero seed is swapped for a fixed non-zero one.
+        let state = if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed };
+        Self { state }
+    }
+
+    // Picks a seed that is different every time, for when reproducibility does not matter.
+    // The core has no dependencies, so we borrow the randomly seeded hasher from the standard library.
+    pub(crate) fn random_seed() -> u64 {
+        RandomState::new().build_hasher().finish()
+    }
+
+    // The generator's internal state, so that it can be saved and restored exactly.
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn next_u8(&mut self) -> u8 {
+        // xorshift64*, see Marsaglia's "Xorshift RNGs" and Vigna's scrambled variant.
+        // The top bits of the multiplied output are the best mixed, so that is where we take our byte from.
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emu, Quirks};
+
+    // An emulator running a program that rolls V0 over and over.
+    fn roller(seed: u64) -> Emu {
+        let mut emu = Emu::with_seed(Quirks::CHIP_48, seed);
+        emu.load_rom(&[0xC0, 0xFF, 0x12, 0x00]).unwrap();
+        emu
+    }
+
+    // The next count numbers the program rolls.
+    fn rolls(emu: &mut Emu, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                emu.tick().unwrap();
+                emu.tick().unwrap();
+                emu.cpu_state().v[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let numbers = rolls(&mut roller(42), 100);
+        assert_eq!(rolls(&mut roller(42), 100), numbers);
+        assert_ne!(rolls(&mut roller(43), 100), numbers);
+        // They shouldn't all be the same number either.
+        assert!(numbers.iter().any(|&n| n != numbers[0]));
+    }
+
+    #[test]
+    fn zero_seed_still_rolls() {
+        let mut rng = Rng::new(0);
+        assert_ne!(rng.state(), 0);
+        let first = rng.next_u8();
+        assert!((0..100).any(|_| rng.next_u8() != first));
+    }
+
+    #[test]
+    fn reset_starts_the_numbers_over() {
+        let mut emu = roller(42);
+        let numbers = rolls(&mut emu, 20);
+        emu.reset();
+        assert_eq!(rolls(&mut emu, 20), numbers);
+    }
+
+    #[test]
+    fn save_states_carry_on_with_the_same_numbers() {
+        let mut emu = roller(42);
+        rolls(&mut emu, 10);
+        let state = emu.save_state();
+        let expected = rolls(&mut emu, 50);
+
+        let mut restored = roller(1);
+        restored.load_state(&state).unwrap();
+        assert_eq!(rolls(&mut restored, 50), expected);
+    }
+}
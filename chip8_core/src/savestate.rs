@@ -0,0 +1,310 @@
+// Save states are a snapshot of everything inside Emu, written out as a flat list of bytes.
+//
+// Layout (all multi-byte numbers are little endian):
+//   magic "C8SS", format version (u8), quirk flags (u8), load/store increment (u8),
+//   pc, i, sp (u16 each), V0-VF, stack (16 x u16), keys (16 bytes, 0 or 1), dt, st,
+//   hires, planes, vblank, exited, RPL flags (16 bytes), audio pattern (16 bytes), pitch,
+//   RNG seed and RNG state (u64 each),
+//   RAM (u32 length, then the bytes), screen (one byte per pixel), ROM (u32 length, then the bytes).
+use crate::rng::Rng;
+use crate::{
+    Emu, EmuError, IndexIncrement, Quirks, AUDIO_PATTERN_SIZE, NUM_REGS, NUM_RPL_FLAGS, SCREEN_SIZE, START_ADDR, STACK_SIZE,
+};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+const VERSION: u8 = 2; // Bump this whenever the layout changes, so that old states are rejected.
+
+impl Emu {
+    // Takes a snapshot of the whole machine that can be restored later with load_state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.ram.len() + SCREEN_SIZE + self.rom.len() + 256);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(quirks_to_bits(&self.quirks));
+        out.push(self.quirks.load_store_increment as u8);
+
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.i_reg.to_le_bytes());
+        out.extend_from_slice(&self.sp.to_le_bytes());
+        out.extend_from_slice(&self.v_reg);
+        for addr in self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.extend(self.keys.iter().map(|&key| key as u8));
+        out.push(self.dt);
+        out.push(self.st);
+
+        out.push(self.hires as u8);
+        out.push(self.planes);
+        out.push(self.vblank as u8);
+        out.push(self.exited as u8);
+        out.extend_from_slice(&self.rpl);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+
+        out.extend_from_slice(&(self.ram.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.ram);
+        out.extend_from_slice(&self.screen);
+        out.extend_from_slice(&(self.rom.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.rom);
+
+        out
+    }
+
+    // Restores a snapshot made by save_state. The state is checked completely before anything is changed,
+    // so if it is rejected the emulator carries on exactly as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), EmuError> {
+        let mut reader = Reader { data, pos: 0 };
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a save state"));
+        }
+        if reader.u8()? != VERSION {
+            return Err(invalid("unsupported save state version"));
+        }
+
+        // The quirks decide how big RAM is, so they have to come first.
+        // The seed is a placeholder until we reach the real one further on.
+        let bits = reader.u8()?;
+        let load_store_increment = match reader.u8()? {
+            0 => IndexIncrement::Unchanged,
+            1 => IndexIncrement::ByX,
+            2 => IndexIncrement::ByXPlusOne,
+            _ => return Err(invalid("load/store increment out of range")),
+        };
+        let mut restored = Emu::with_seed(Quirks { load_store_increment, ..quirks_from_bits(bits) }, 0);
+
+        restored.pc = reader.u16()?;
+        restored.i_reg = reader.u16()?;
+        restored.sp = reader.u16()?;
+        if restored.sp as usize > STACK_SIZE {
+            return Err(invalid("stack pointer out of range"));
+        }
+        restored.v_reg.copy_from_slice(reader.bytes(NUM_REGS)?);
+        for addr in restored.stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        for key in restored.keys.iter_mut() {
+            *key = reader.bool()?;
+        }
+        restored.dt = reader.u8()?;
+        restored.st = reader.u8()?;
+
+        restored.hires = reader.bool()?;
+        restored.planes = reader.u8()?;
+        if restored.planes > 0x3 {
+            return Err(invalid("plane mask out of range"));
+        }
+        restored.vblank = reader.bool()?;
+        restored.exited = reader.bool()?;
+        restored.rpl.copy_from_slice(reader.bytes(NUM_RPL_FLAGS)?);
+        restored.audio_pattern.copy_from_slice(reader.bytes(AUDIO_PATTERN_SIZE)?);
+        restored.pitch = reader.u8()?;
+        restored.seed = reader.u64()?;
+        let rng_state = reader.u64()?;
+        if rng_state == 0 {
+            return Err(invalid("random number generator state is zero"));
+        }
+        restored.rng = Rng::new(rng_state);
+
+        let ram_len = reader.u32()? as usize;
+        if ram_len != restored.ram.len() {
+            return Err(invalid("RAM size does not match the quirks"));
+        }
+        restored.ram.copy_from_slice(reader.bytes(ram_len)?);
+
+        restored.screen.copy_from_slice(reader.bytes(SCREEN_SIZE)?);
+        if restored.screen.iter().any(|&pixel| pixel > 0x3) {
+            return Err(invalid("pixel value out of range"));
+        }
+        restored.refresh_display();
+
+        let rom_len = reader.u32()? as usize;
+        if rom_len > ram_len - START_ADDR as usize {
+            return Err(invalid("ROM does not fit in RAM"));
+        }
+        restored.rom = reader.bytes(rom_len)?.to_vec();
+
+        if reader.pos != data.len() {
+            return Err(invalid("unexpected data after the end of the save state"));
+        }
+
+        // The audio sink belongs to the frontend, not the snapshot, so it carries over.
+        // The buzzer is left as it was, and will catch up with the sound timer on the next frame.
+        restored.audio = self.audio.take();
+        restored.buzzer_on = self.buzzer_on;
+        // So do breakpoints, watchpoints and the tracer, which belong to whoever is debugging.
+        restored.watches = std::mem::take(&mut self.watches);
+        restored.tracer = self.tracer.take();
+        *self = restored;
+        Ok(())
+    }
+}
+
+fn invalid(reason: &'static str) -> EmuError {
+    EmuError::InvalidSaveState { reason }
+}
+
+// Packs the quirks that are on or off into a single byte, one bit each.
+// The load/store increment has three settings, so it gets a byte of its own.
+fn quirks_to_bits(quirks: &Quirks) -> u8 {
+    (quirks.shift_uses_vy as u8)
+        | (quirks.vf_reset as u8) << 1
+        | (quirks.super_chip as u8) << 2
+        | (quirks.jump_uses_vx as u8) << 3
+        | (quirks.clip_sprites as u8) << 4
+        | (quirks.display_wait as u8) << 5
+        | (quirks.xo_chip as u8) << 6
+}
+
+// Unpacks the bits from quirks_to_bits. The load/store increment is left for the caller to fill in.
+fn quirks_from_bits(bits: u8) -> Quirks {
+    Quirks {
+        shift_uses_vy: bits & 1 != 0,
+        vf_reset: bits & (1 << 1) != 0,
+        load_store_increment: IndexIncrement::Unchanged,
+        super_chip: bits & (1 << 2) != 0,
+        jump_uses_vx: bits & (1 << 3) != 0,
+        clip_sprites: bits & (1 << 4) != 0,
+        display_wait: bits & (1 << 5) != 0,
+        xo_chip: bits & (1 << 6) != 0,
+    }
+}
+
+// Walks through a save state, failing cleanly if it ends too soon.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], EmuError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len());
+        match end {
+            Some(end) => {
+                let bytes = &self.data[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            },
+            None => Err(invalid("save state is truncated")),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, EmuError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, EmuError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("flag is not 0 or 1")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, EmuError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, EmuError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, EmuError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Where the load/store increment, the stack pointer and the hires flag are in a save state.
+    const INCREMENT_OFFSET: usize = 6;
+    const SP_OFFSET: usize = 11;
+    const HIRES_OFFSET: usize = 79;
+
+    // An emulator partway through a program that has drawn something, rolled a random number
+    // and called a subroutine that it hasn't returned from.
+    fn playing() -> Emu {
+        let mut emu = Emu::with_seed(Quirks::CHIP_48, 7);
+        emu.load_rom(&[0x60, 0x05, 0xC1, 0xFF, 0xA0, 0x00, 0xD0, 0x15, 0x22, 0x0A, 0x12, 0x0A]).unwrap();
+        for _ in 0..6 {
+            emu.tick().unwrap();
+        }
+        emu.tick_timers();
+        emu
+    }
+
+    #[test]
+    fn round_trip() {
+        let emu = playing();
+        let mut restored = Emu::with_seed(Quirks::XO_CHIP, 1);
+        restored.load_state(&emu.save_state()).unwrap();
+
+        assert_eq!(restored.quirks, emu.quirks);
+        assert_eq!(restored.cpu_state(), emu.cpu_state());
+        assert_eq!(restored.cpu_state().sp, 1);
+        assert_eq!(restored.planes(), emu.planes());
+        assert_eq!(restored.display(), emu.display());
+        assert_eq!(restored.ram, emu.ram);
+        assert_eq!(restored.seed(), emu.seed());
+        assert_eq!(restored.rng, emu.rng);
+        assert_eq!(restored.save_state(), emu.save_state());
+    }
+
+    // Loads a damaged state into an emulator, checking that it is rejected and the emulator is left alone.
+    fn rejected(state: &[u8]) -> &'static str {
+        let mut emu = playing();
+        let before = emu.save_state();
+        let Err(EmuError::InvalidSaveState { reason }) = emu.load_state(state) else {
+            panic!("a damaged save state was loaded");
+        };
+        assert_eq!(emu.save_state(), before);
+        reason
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut state = playing().save_state();
+        state[..4].copy_from_slice(b"PK\x03\x04");
+        assert_eq!(rejected(&state), "not a save state");
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut state = playing().save_state();
+        state[4] = VERSION + 1;
+        assert_eq!(rejected(&state), "unsupported save state version");
+    }
+
+    #[test]
+    fn rejects_truncated_states() {
+        let state = playing().save_state();
+        for len in 0..state.len() {
+            assert_eq!(rejected(&state[..len]), "save state is truncated", "{} bytes", len);
+        }
+        assert_eq!(rejected(&[state.as_slice(), &[0]].concat()), "unexpected data after the end of the save state");
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let mut state = playing().save_state();
+        state[SP_OFFSET..SP_OFFSET + 2].copy_from_slice(&(STACK_SIZE as u16 + 1).to_le_bytes());
+        assert_eq!(rejected(&state), "stack pointer out of range");
+
+        let mut state = playing().save_state();
+        state[INCREMENT_OFFSET] = 3;
+        assert_eq!(rejected(&state), "load/store increment out of range");
+
+        let mut state = playing().save_state();
+        state[HIRES_OFFSET] = 2;
+        assert_eq!(rejected(&state), "flag is not 0 or 1");
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+
+use crate::{Emu, NUM_KEYS};
+
+// A list of key presses and releases to feed the emulator, for running ROMs without anyone at the keyboard.
+//
+// Script files have one frame per line: the frame number, then any number of key changes,
+// where +K presses the hex key K and -K lets go of it. Keys stay down until they are released.
+// Everything after a '#' is a comment.
+//
+//   # Start the game, then hold 5 for half a second.
+//   60 +1
+//   62 -1
+//   120 +5
+//   150 -5
+#[derive(Debug, Default)]
+pub struct InputScript {
+    events: Vec<KeyEvent>, // Sorted by frame.
+}
+
+#[derive(Debug, Clone, Copy)]
+struct KeyEvent {
+    frame: u32,
+    key: usize,
+    pressed: bool,
+}
+
+// A line of a script that could not be understood.
+#[derive(Debug)]
+pub struct ScriptError {
+    pub line: usize, // Line numbers start at 1, like in an editor.
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input script line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut events = Vec::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            let error = |message: String| ScriptError { line: idx + 1, message };
+
+            // Drop comments, then skip lines with nothing left on them.
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let frame = match words.next() {
+                Some(word) => word.parse().map_err(|_| error(format!("'{}' is not a frame number", word)))?,
+                None => continue,
+            };
+
+            for word in words {
+                let pressed = match word.chars().next() {
+                    Some('+') => true,
+                    Some('-') => false,
+                    _ => return Err(error(format!("'{}' should start with + or -", word))),
+                };
+                let key = usize::from_str_radix(&word[1..], 16)
+                    .ok()
+                    .filter(|&key| key < NUM_KEYS)
+                    .ok_or_else(|| error(format!("'{}' is not a hex key from 0 to F", &word[1..])))?;
+                events.push(KeyEvent { frame, key, pressed });
+            }
+        }
+
+        // A stable sort keeps changes within the same frame in the order they were written.
+        events.sort_by_key(|event| event.frame);
+        Ok(Self { events })
+    }
+
+    // Presses and releases the keys that change at the start of this frame.
+    pub fn apply(&self, emu: &mut Emu, frame: u32) {
+        let start = self.events.partition_point(|event| event.frame < frame);
+        for event in self.events[start..].iter().take_while(|event| event.frame == frame) {
+            emu.keypress(event.key, event.pressed);
+        }
+    }
+}
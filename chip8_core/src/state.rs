@@ -0,0 +1,15 @@
+use crate::{NUM_REGS, STACK_SIZE};
+
+// A copy of the CPU's registers, taken with Emu::cpu_state.
+// Debuggers and tests can look at this freely without changing the running machine.
+// Changes only take effect when the copy is handed back with Emu::set_cpu_state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    pub pc: u16, // Program Counter.
+    pub i: u16, // The I register.
+    pub sp: u16, // Stack Pointer. The number of return addresses currently on the stack.
+    pub v: [u8; NUM_REGS], // V0 through VF.
+    pub stack: [u16; STACK_SIZE], // Only the first sp entries are in use.
+    pub dt: u8, // Delay Timer.
+    pub st: u8, // Sound Timer.
+}
@@ -0,0 +1,321 @@
+// Instruction tracing, for comparing what we do with what other emulators do.
+// Tracing is off until a Tracer is handed to Emu::set_tracer. After that, every instruction
+// that tick runs is recorded, either into a ring buffer that keeps the last few, or straight out to a writer.
+//
+// The text format has one line per instruction, made to be diffed:
+//          0 0200  6A02       LD VA, 0x02           V=0000...0000 I=0000 -> V=0000...0200 I=0000
+// That is the cycle, pc, opcode, mnemonic, then V0-VF and I before and after the instruction.
+// The V registers are written as 32 hex digits, V0 first. F000 NNNN shows both of its words.
+//
+// The binary format is a header of magic "C8TR" and a format version (u8), followed by one
+// fixed size record per instruction (all multi-byte numbers are little endian, like save states):
+//   cycle (u64), pc (u16), opcode (u16), second word of F000 NNNN (u16, 0 otherwise),
+//   flags (u8, bit 0 set if there is a second word), V0-VF before, I before (u16), V0-VF after, I after (u16).
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::disasm::{self, Syntax};
+use crate::opcode::Op;
+use crate::NUM_REGS;
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1; // Bump this whenever the record layout changes.
+pub const RECORD_SIZE: usize = 8 + 2 + 2 + 2 + 1 + 2 * (NUM_REGS + 2);
+
+// Which way a Tracer writes what it records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+// The registers that an instruction can change, as the tracer sees them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TraceRegisters {
+    pub v: [u8; NUM_REGS],
+    pub i: u16,
+}
+
+// One instruction that ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub cycle: u64, // How many instructions the tracer had seen before this one.
+    pub pc: u16,
+    pub opcode: u16,
+    pub operand: Option<u16>, // The second word of XO-CHIP's F000 NNNN.
+    pub before: TraceRegisters,
+    pub after: TraceRegisters,
+}
+
+impl TraceEntry {
+    // The instruction, in the disassembler's Cowgod syntax.
+    pub fn mnemonic(&self) -> String {
+        // Only XO-CHIP records a second word, so that is the only time F000 is an instruction.
+        match Op::decode(self.opcode, self.operand, self.operand.is_some()) {
+            Some(op) => disasm::format_op(&op, Syntax::Cowgod),
+            None => "???".to_string(),
+        }
+    }
+
+    // Writes the entry as a record of the binary format.
+    pub fn write_binary(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut record = Vec::with_capacity(RECORD_SIZE);
+        record.extend_from_slice(&self.cycle.to_le_bytes());
+        record.extend_from_slice(&self.pc.to_le_bytes());
+        record.extend_from_slice(&self.opcode.to_le_bytes());
+        record.extend_from_slice(&self.operand.unwrap_or(0).to_le_bytes());
+        record.push(self.operand.is_some() as u8);
+        for registers in [&self.before, &self.after] {
+            record.extend_from_slice(&registers.v);
+            record.extend_from_slice(&registers.i.to_le_bytes());
+        }
+        out.write_all(&record)
+    }
+
+    fn from_record(record: &[u8]) -> TraceEntry {
+        let word = |at: usize| u16::from_le_bytes([record[at], record[at + 1]]);
+        let registers = |at: usize| {
+            let mut v = [0; NUM_REGS];
+            v.copy_from_slice(&record[at..at + NUM_REGS]);
+            TraceRegisters { v, i: word(at + NUM_REGS) }
+        };
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&record[..8]);
+        TraceEntry {
+            cycle: u64::from_le_bytes(cycle),
+            pc: word(8),
+            opcode: word(10),
+            operand: (record[14] & 1 != 0).then(|| word(12)),
+            before: registers(15),
+            after: registers(15 + NUM_REGS + 2),
+        }
+    }
+}
+
+// One line of the text format, without the newline.
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let words = match self.operand {
+            Some(operand) => format!("{:04X} {:04X}", self.opcode, operand),
+            None => format!("{:04X}", self.opcode),
+        };
+        write!(f, "{:>8} {:04X}  {:<9}  {:<20}  ", self.cycle, self.pc, words, self.mnemonic())?;
+        write!(f, "{} -> {}", self.before, self.after)
+    }
+}
+
+impl fmt::Display for TraceRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V=")?;
+        for value in self.v {
+            write!(f, "{:02X}", value)?;
+        }
+        write!(f, " I={:04X}", self.i)
+    }
+}
+
+// Writes the header that every binary trace starts with.
+pub fn write_binary_header(out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])
+}
+
+// Reads a whole binary trace back in.
+pub fn read_binary(input: &mut dyn Read) -> io::Result<Vec<TraceEntry>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    let header = MAGIC.len() + 1;
+    if data.len() < header || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a binary trace"));
+    }
+    if data[MAGIC.len()] != VERSION {
+        return Err(invalid("unsupported trace version"));
+    }
+    let records = &data[header..];
+    if records.len() % RECORD_SIZE != 0 {
+        return Err(invalid("the trace ends part of the way through a record"));
+    }
+    Ok(records.chunks(RECORD_SIZE).map(TraceEntry::from_record).collect())
+}
+
+// Where traced instructions go.
+enum Output {
+    Buffer { entries: VecDeque<TraceEntry>, capacity: usize },
+    Writer { out: Box<dyn Write>, format: TraceFormat },
+}
+
+// Records instructions for Emu::tick. Kept across resets and save state loads, like the debugging watches.
+pub struct Tracer {
+    cycle: u64,
+    output: Output,
+    error: Option<io::Error>, // The first write that failed. Nothing more is written after it.
+}
+
+impl Tracer {
+    // Keeps the last capacity instructions in memory.
+    pub fn to_buffer(capacity: usize) -> Self {
+        let output = Output::Buffer { entries: VecDeque::with_capacity(capacity), capacity };
+        Self { cycle: 0, output, error: None }
+    }
+
+    // Writes every instruction out as it runs. Binary traces get their header straight away.
+    pub fn to_writer(mut out: Box<dyn Write>, format: TraceFormat) -> io::Result<Self> {
+        if format == TraceFormat::Binary {
+            write_binary_header(&mut out)?;
+        }
+        Ok(Self { cycle: 0, output: Output::Writer { out, format }, error: None })
+    }
+
+    // The instructions in the ring buffer, oldest first. Tracers that write straight out don't keep any.
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        let entries = match &self.output {
+            Output::Buffer { entries, .. } => Some(entries.iter()),
+            Output::Writer { .. } => None,
+        };
+        entries.into_iter().flatten()
+    }
+
+    // How many instructions have been traced so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycle
+    }
+
+    // Flushes anything still waiting to be written, and reports the first write that failed, if any did.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        match &mut self.output {
+            Output::Writer { out, .. } => out.flush(),
+            Output::Buffer { .. } => Ok(()),
+        }
+    }
+
+    pub(crate) fn record(&mut self, pc: u16, opcode: u16, operand: Option<u16>, before: TraceRegisters, after: TraceRegisters) {
+        let entry = TraceEntry { cycle: self.cycle, pc, opcode, operand, before, after };
+        self.cycle += 1;
+        match &mut self.output {
+            Output::Buffer { entries, capacity } => {
+                if *capacity == 0 {
+                    return;
+                }
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                }
+                entries.push_back(entry);
+            },
+            Output::Writer { out, format } => {
+                if self.error.is_some() {
+                    return;
+                }
+                let result = match format {
+                    TraceFormat::Text => writeln!(out, "{}", entry),
+                    TraceFormat::Binary => entry.write_binary(out),
+                };
+                self.error = result.err();
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emu, Quirks};
+
+    // Runs a program with a tracer keeping the last capacity instructions, and returns them.
+    fn trace(quirks: Quirks, program: &[u8], ticks: usize, capacity: usize) -> (Vec<TraceEntry>, u64) {
+        let mut emu = Emu::new(quirks);
+        emu.load_rom(program).unwrap();
+        emu.set_tracer(Tracer::to_buffer(capacity));
+        for _ in 0..ticks {
+            emu.tick().unwrap();
+        }
+        let tracer = emu.take_tracer().unwrap();
+        (tracer.entries().copied().collect(), tracer.cycles())
+    }
+
+    #[test]
+    fn text_columns() {
+        let (entries, _) = trace(Quirks::CHIP_48, &[0x6A, 0x02, 0xA2, 0x34], 2, 2);
+        let zeros = "0".repeat(32);
+        let va = format!("{}02{}", "0".repeat(20), "0".repeat(10));
+        assert_eq!(
+            entries[0].to_string(),
+            format!("       0 0200  6A02       LD VA, 0x02           V={} I=0000 -> V={} I=0000", zeros, va)
+        );
+        assert_eq!(
+            entries[1].to_string(),
+            format!("       1 0202  A234       LD I, 0x234           V={} I=0000 -> V={} I=0234", va, va)
+        );
+    }
+
+    #[test]
+    fn long_i_shows_both_words() {
+        let (entries, _) = trace(Quirks::XO_CHIP, &[0xF0, 0x00, 0x12, 0x34], 1, 1);
+        assert_eq!(entries[0].operand, Some(0x1234));
+        assert!(entries[0].to_string().starts_with("       0 0200  F000 1234  LD I, LONG 0x1234     V="), "{}", entries[0]);
+
+        // Without XO-CHIP, F000 is not an instruction, so there is no second word to show.
+        let entry = TraceEntry { operand: None, ..entries[0] };
+        assert!(entry.to_string().starts_with("       0 0200  F000       ???                   V="), "{}", entry);
+    }
+
+    #[test]
+    fn ring_buffer_keeps_the_newest() {
+        // Adds 1 to V0 forever.
+        let program = [0x70, 0x01, 0x12, 0x00];
+        let (entries, cycles) = trace(Quirks::CHIP_48, &program, 7, 3);
+        assert_eq!(cycles, 7);
+        let kept: Vec<(u64, u16)> = entries.iter().map(|entry| (entry.cycle, entry.pc)).collect();
+        assert_eq!(kept, [(4, 0x200), (5, 0x202), (6, 0x200)]);
+
+        // With no room at all, nothing is kept but the cycles are still counted.
+        let (entries, cycles) = trace(Quirks::CHIP_48, &program, 7, 0);
+        assert!(entries.is_empty());
+        assert_eq!(cycles, 7);
+    }
+
+    // A binary trace of a few instructions, including a long one.
+    fn binary_trace() -> (Vec<TraceEntry>, Vec<u8>) {
+        let (entries, _) = trace(Quirks::XO_CHIP, &[0x6A, 0x02, 0xF0, 0x00, 0x12, 0x34, 0x7A, 0xFF], 3, 3);
+        let mut out = Vec::new();
+        write_binary_header(&mut out).unwrap();
+        for entry in &entries {
+            entry.write_binary(&mut out).unwrap();
+        }
+        (entries, out)
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let (entries, out) = binary_trace();
+        assert_eq!(out.len(), MAGIC.len() + 1 + 3 * RECORD_SIZE);
+        assert_eq!(RECORD_SIZE, 51);
+        assert_eq!(read_binary(&mut out.as_slice()).unwrap(), entries);
+        assert_eq!(entries[1].operand, Some(0x1234));
+        assert_eq!(entries[2].after.v[0xA], 0x01);
+    }
+
+    #[test]
+    fn binary_rejects_other_files() {
+        let error = |data: &[u8]| read_binary(&mut &data[..]).unwrap_err().to_string();
+        let (_, out) = binary_trace();
+
+        let mut bad_magic = out.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(error(&bad_magic), "not a binary trace");
+        assert_eq!(error(&out[..3]), "not a binary trace");
+
+        let mut bad_version = out.clone();
+        bad_version[MAGIC.len()] = VERSION + 1;
+        assert_eq!(error(&bad_version), "unsupported trace version");
+
+        assert_eq!(error(&out[..out.len() - 1]), "the trace ends part of the way through a record");
+        // A header on its own is an empty trace.
+        assert_eq!(read_binary(&mut &out[..MAGIC.len() + 1]).unwrap(), []);
+    }
+}
@@ -0,0 +1,68 @@
+// What the ROM tests share: running a ROM frame by frame, drawing the screen as text, and
+// comparing with goldens, which are rewritten instead when the tests are run with BLESS=1 set.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use chip8_core::script::InputScript;
+use chip8_core::Emu;
+
+pub fn blessing() -> bool {
+    env::var_os("BLESS").is_some()
+}
+
+pub fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|err| panic!("can't read {}: {}", path.display(), err))
+}
+
+// Only the game tests have script files, so the other tests don't use this.
+#[allow(dead_code)]
+pub fn read_script(path: &Path) -> InputScript {
+    let text = fs::read_to_string(path).unwrap_or_else(|err| panic!("can't read {}: {}", path.display(), err));
+    InputScript::parse(&text).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+// Runs frames the way `chip8 run` does: the keys for the frame, then the instructions, then the
+// timers. on_frame sees the emulator at the end of each frame, numbered from 1.
+pub fn run_frames(
+    emu: &mut Emu,
+    name: &str,
+    ticks_per_frame: u32,
+    frames: u32,
+    script: &InputScript,
+    mut on_frame: impl FnMut(u32, &Emu),
+) {
+    for frame in 0..frames {
+        script.apply(emu, frame);
+        for _ in 0..ticks_per_frame {
+            if let Err(err) = emu.tick() {
+                panic!("{} stopped on frame {}: {}", name, frame, err);
+            }
+        }
+        emu.tick_timers();
+        on_frame(frame + 1, emu);
+    }
+}
+
+// The screen as text, with . for pixels that are off and #, + and @ for the XO-CHIP bitplanes.
+pub fn screen_to_ascii(emu: &Emu) -> String {
+    let (width, _) = emu.resolution();
+    let mut text = String::new();
+    for row in emu.planes().chunks(width) {
+        text.extend(row.iter().map(|&pixel| ['.', '#', '+', '@'][pixel as usize & 3]));
+        text.push('\n');
+    }
+    text
+}
+
+// Writes a golden and returns None with BLESS set, and otherwise returns what the golden has.
+pub fn bless_or_read(path: &Path, actual: &str) -> Option<String> {
+    if blessing() {
+        fs::write(path, actual).unwrap_or_else(|err| panic!("can't write {}: {}", path.display(), err));
+        return None;
+    }
+    match fs::read_to_string(path) {
+        Ok(expected) => Some(expected),
+        Err(_) => panic!("there is no golden at {}, run the tests with BLESS=1 to make one", path.display()),
+    }
+}
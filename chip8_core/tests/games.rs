@@ -0,0 +1,150 @@
+// Regression tests for the games in rom_files. Each game is booted with a fixed seed and played for
+// FRAMES frames with the input script in tests/games, and a hash of the screen is taken every
+// CHECKPOINT frames. The hashes have to match the ones in tests/games/golden.
+//
+// The scripts use the same format as `chip8 run --input`, so a run can be watched with
+//   chip8 run --seed 0 --quirks <quirks> --ipf <ipf> --frames <frame> --input tests/games/<ROM>.keys <ROM>
+// When a change to the emulator is meant to change what the games draw, run the tests with BLESS=1
+// set to write new hashes out, and look at the games to be sure they still play properly.
+mod common;
+
+use std::path::PathBuf;
+
+use chip8_core::{Emu, Quirks};
+
+use common::{bless_or_read, read, read_script, run_frames, screen_to_ascii};
+
+const FRAMES: u32 = 600; // Ten seconds.
+const CHECKPOINT: u32 = 60; // Once a second.
+const SEED: u64 = 0;
+
+fn dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("games")
+}
+
+fn rom_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("rom_files").join(name)
+}
+
+// The quirks for each name that --quirks takes.
+fn quirks(profile: &str) -> Quirks {
+    match profile {
+        "vip" => Quirks::COSMAC_VIP,
+        "chip48" => Quirks::CHIP_48,
+        "schip" => Quirks::SUPER_CHIP,
+        "xochip" => Quirks::XO_CHIP,
+        _ => panic!("there are no {} quirks", profile),
+    }
+}
+
+// FNV-1a, which is plenty to tell screens apart and needs nothing outside std.
+fn hash_screen(emu: &Emu) -> u64 {
+    let (width, height) = emu.resolution();
+    let mut hash = 0xCBF2_9CE4_8422_2325u64;
+    for &byte in [width as u8, height as u8].iter().chain(emu.planes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01B3);
+    }
+    hash
+}
+
+// Plays a game, returning the frame and screen hash at every checkpoint, and each screen as text.
+fn play(name: &str, quirks: Quirks, ticks_per_frame: u32) -> Vec<(u32, u64, String)> {
+    let script_path = dir().join(format!("{}.keys", name));
+    let script = read_script(&script_path);
+
+    let mut emu = Emu::with_seed(quirks, SEED);
+    emu.load_rom(&read(&rom_path(name))).unwrap();
+    let mut checkpoints = Vec::new();
+    run_frames(&mut emu, name, ticks_per_frame, FRAMES, &script, |frame, emu| {
+        if frame % CHECKPOINT == 0 {
+            checkpoints.push((frame, hash_screen(emu), screen_to_ascii(emu)));
+        }
+    });
+    checkpoints
+}
+
+// Compares a game's checkpoints with its golden, or writes them as the new golden with BLESS set.
+fn check(name: &str, profile: &str, ticks_per_frame: u32) {
+    let checkpoints = play(name, quirks(profile), ticks_per_frame);
+    let path = dir().join("golden").join(format!("{}.txt", name));
+    let mut text = format!("# Screen hashes for {}: the frame, then the hash of the screen at the end of it.\n", name);
+    for (frame, hash, _) in &checkpoints {
+        text.push_str(&format!("{} {:016x}\n", frame, hash));
+    }
+    let Some(golden) = bless_or_read(&path, &text) else {
+        return;
+    };
+
+    let expected: Vec<(u32, u64)> = golden
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|line| {
+            let parsed = line.split_once(' ').and_then(|(frame, hash)| {
+                Some((frame.parse().ok()?, u64::from_str_radix(hash.trim(), 16).ok()?))
+            });
+            parsed.unwrap_or_else(|| panic!("{} has a line that isn't a checkpoint: {}", path.display(), line))
+        })
+        .collect();
+
+    let actual: Vec<(u32, u64)> = checkpoints.iter().map(|&(frame, hash, _)| (frame, hash)).collect();
+    if actual == expected {
+        return;
+    }
+    let Some((frame, _, screen)) = checkpoints.iter().find(|(frame, hash, _)| !expected.contains(&(*frame, *hash))) else {
+        panic!("{} doesn't have a checkpoint every {} frames, run the tests with BLESS=1 to remake it", path.display(), CHECKPOINT);
+    };
+    panic!(
+        "{} drew something else by frame {}:\n{}\nTo watch it, run\n  \
+         chip8 run --seed {} --quirks {} --ipf {} --frames {} --input {} {}\n\
+         If the change is intended, run the tests with BLESS=1 to update {}.",
+        name,
+        frame,
+        screen,
+        SEED,
+        profile,
+        ticks_per_frame,
+        frame,
+        dir().join(format!("{}.keys", name)).display(),
+        rom_path(name).display(),
+        path.display()
+    );
+}
+
+// One test per game: the ROM in rom_files, the quirks to run it with, and how
+// many instructions to run each frame. Most games are fine with the defaults that `chip8 play` uses,
+// the rest need to run faster to get going within the ten seconds.
+macro_rules! games {
+    ($($test:ident: $rom:literal, $profile:literal, $ipf:literal;)*) => {
+        $(#[test]
+        fn $test() {
+            check($rom, $profile, $ipf);
+        })*
+    };
+}
+
+games! {
+    puzzle15: "15PUZZLE", "vip", 10;
+    blinky: "BLINKY", "chip48", 30;
+    blitz: "BLITZ", "vip", 10;
+    brix: "BRIX", "vip", 10;
+    connect4: "CONNECT4", "vip", 10;
+    guess: "GUESS", "vip", 10;
+    hidden: "HIDDEN", "vip", 10;
+    invaders: "INVADERS", "vip", 10;
+    kaleid: "KALEID", "vip", 10;
+    maze: "MAZE", "vip", 10;
+    merlin: "MERLIN", "vip", 10;
+    missile: "MISSILE", "vip", 10;
+    pong: "PONG", "vip", 10;
+    pong2: "PONG2", "vip", 10;
+    puzzle: "PUZZLE", "chip48", 30;
+    syzygy: "SYZYGY", "vip", 10;
+    tank: "TANK", "vip", 10;
+    tetris: "TETRIS", "vip", 10;
+    tictac: "TICTAC", "vip", 10;
+    ufo: "UFO", "vip", 10;
+    vbrix: "VBRIX", "vip", 10;
+    vers: "VERS", "vip", 10;
+    wipeoff: "WIPEOFF", "vip", 10;
+}
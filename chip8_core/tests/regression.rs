@@ -0,0 +1,136 @@
+// Regression tests: runs the test programs in tests/regression through Emu under every quirk profile,
+// and compares the screen each one finishes on with a golden copy in tests/regression/golden.
+//
+// The programs are our own, written in the style of the community test ROMs (the IBM logo, corax+,
+// and Timendus' flags, quirks and keypad tests), which can't be bundled here. Each check draws a cell
+// with a tick or a cross. The goldens were made by this emulator and checked by eye, so passing says
+// the emulator still behaves as it did when they were blessed, not that it matches other interpreters.
+//
+// The programs are written in Octo, with results.8o added to the end of each one for the drawing code
+// they share, and the assembled ROMs are checked in next to the sources. The tests run the checked in
+// ROMs, so that a change to the compiler can't change what the emulator is tested with.
+// roms_match_their_sources catches the two drifting apart.
+//
+// The goldens are plain text, one line per row of pixels. When a change to the emulator is meant to
+// change what a program draws, run the tests with BLESS=1 set to write the new screens out, and
+// check the difference in the goldens before committing them. BLESS=1 also reassembles the ROMs.
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use chip8_core::octo::{self, Target};
+use chip8_core::script::InputScript;
+use chip8_core::{Emu, Quirks};
+
+use common::{bless_or_read, blessing, read, run_frames, screen_to_ascii};
+
+const INSTRUCTIONS_PER_FRAME: u32 = 200;
+const FRAMES: u32 = 180; // Three seconds, which is plenty for every program to finish.
+const SEED: u64 = 0;
+
+const NO_KEYS: &str = "";
+// keypad.8o wants 5 tapped, then 7 held for a while.
+const KEYPAD_KEYS: &str = "20 +5\n23 -5\n40 +7\n60 -7";
+
+fn dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("regression")
+}
+
+const PROGRAMS: [&str; 5] = ["ibm_logo", "opcodes", "flags", "quirks", "keypad"];
+
+// Assembles a program from its source, the way its ROM was made.
+fn compile(program: &str) -> Vec<u8> {
+    let source = |name: String| String::from_utf8(read(&dir().join(name))).unwrap();
+    let source = format!("{}\n{}", source(format!("{}.8o", program)), source("results.8o".to_string()));
+    octo::compile(&source, Target::Chip8).unwrap_or_else(|err| panic!("{}: {}", program, err)).bytes
+}
+
+// Runs a program's ROM for FRAMES frames, returning the screen it ends on.
+// While blessing, the ROM is assembled afresh, since roms_match_their_sources may be rewriting it.
+fn run(program: &str, quirks: Quirks, keys: &str) -> String {
+    let rom = match blessing() {
+        true => compile(program),
+        false => read(&dir().join(format!("{}.ch8", program))),
+    };
+    let keys = InputScript::parse(keys).unwrap();
+    let mut emu = Emu::with_seed(quirks, SEED);
+    emu.load_rom(&rom).unwrap();
+    run_frames(&mut emu, program, INSTRUCTIONS_PER_FRAME, FRAMES, &keys, |_, _| {});
+    screen_to_ascii(&emu)
+}
+
+// Compares a screen with its golden, or writes it as the new golden with BLESS set.
+fn check(program: &str, profile: &str, quirks: Quirks, keys: &str) {
+    let actual = run(program, quirks, keys);
+    let path = dir().join("golden").join(format!("{}.{}.txt", program, profile));
+    let Some(expected) = bless_or_read(&path, &actual) else {
+        return;
+    };
+    if actual != expected {
+        panic!(
+            "{} with the {} quirks didn't draw what {} has.\nExpected:\n{}\nActual:\n{}\n\
+             If the change is intended, run the tests with BLESS=1 to update it.",
+            program,
+            profile,
+            path.display(),
+            expected,
+            actual
+        );
+    }
+}
+
+#[test]
+fn roms_match_their_sources() {
+    for program in PROGRAMS {
+        let path = dir().join(format!("{}.ch8", program));
+        let compiled = compile(program);
+        if blessing() {
+            fs::write(&path, &compiled).unwrap_or_else(|err| panic!("can't write {}: {}", path.display(), err));
+            continue;
+        }
+        assert!(
+            read(&path) == compiled,
+            "{} no longer assembles to {}. If the source changed, run the tests with BLESS=1 to reassemble it.",
+            program,
+            path.display()
+        );
+    }
+}
+
+// One module per program, with a test for each quirk profile.
+macro_rules! regression {
+    ($($program:ident: $keys:expr;)*) => {
+        $(mod $program {
+            use super::*;
+
+            #[test]
+            fn vip() {
+                check(stringify!($program), "vip", Quirks::COSMAC_VIP, $keys);
+            }
+
+            #[test]
+            fn chip48() {
+                check(stringify!($program), "chip48", Quirks::CHIP_48, $keys);
+            }
+
+            #[test]
+            fn schip() {
+                check(stringify!($program), "schip", Quirks::SUPER_CHIP, $keys);
+            }
+
+            #[test]
+            fn xochip() {
+                check(stringify!($program), "xochip", Quirks::XO_CHIP, $keys);
+            }
+        })*
+    };
+}
+
+regression! {
+    ibm_logo: NO_KEYS;
+    opcodes: NO_KEYS;
+    flags: NO_KEYS;
+    quirks: NO_KEYS;
+    keypad: KEYPAD_KEYS;
+}
@@ -0,0 +1,78 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chip8_core::asm;
+use chip8_core::octo::{self, Target};
+
+// Everything that can be set on the command line.
+struct Options {
+    source: PathBuf,
+    out: PathBuf,
+    labels: bool, // Print every label and its address once assembled.
+    target: Option<Target>, // Only Octo source has a target.
+}
+
+pub fn usage(program: &str) -> String {
+    format!("{} asm [--labels] [--target chip8|schip|xochip] <SOURCE> <ROM>", program)
+}
+
+pub fn parse_target(name: &str) -> Option<Target> {
+    match name {
+        "chip8" => Some(Target::Chip8),
+        "schip" => Some(Target::SuperChip),
+        "xochip" => Some(Target::XoChip),
+        _ => None,
+    }
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut paths = Vec::new();
+    let mut labels = false;
+    let mut target = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--labels" => labels = true,
+            "--target" => target = Some(parse_target(iter.next()?)?),
+            _ if !arg.starts_with("--") => paths.push(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    let [source, out]: [PathBuf; 2] = paths.try_into().ok()?;
+    Some(Options { source, out, labels, target })
+}
+
+// Assembles a source file written in the disassembler's syntax into a ROM.
+// Files ending in .8o are compiled as Octo instead, checking that only the target's instructions are used.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let assembly = match (options.source.extension().and_then(|ext| ext.to_str()), options.target) {
+        (Some("8o"), target) => octo::compile_file(&options.source, target.unwrap_or(Target::XoChip))?,
+        (_, None) => asm::assemble_file(&options.source)?,
+        // The disassembler's syntax takes every instruction there is, so there would be nothing to check.
+        (_, Some(_)) => return Err("--target only applies to Octo source, in files ending in .8o".into()),
+    };
+    fs::write(&options.out, &assembly.bytes)?;
+
+    if options.labels {
+        for (name, addr) in &assembly.labels {
+            println!("{:04X}  {}", addr, name);
+        }
+    }
+    eprintln!("Wrote {} bytes to {}", assembly.bytes.len(), options.out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_is_only_for_octo() {
+        let args: Vec<String> = ["--target", "chip8", "game.s", "game.ch8"].iter().map(|arg| arg.to_string()).collect();
+        let err = main("chip8", &args).unwrap_err();
+        assert_eq!(err.to_string(), "--target only applies to Octo source, in files ending in .8o");
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip8_core::AudioSink;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+const CHUNK_SAMPLES: usize = 441; // 10 ms of audio per write.
+const MAX_LEAD: Duration = Duration::from_millis(50); // How far ahead of real time we let ourselves get.
+
+// Programs that can play raw 16-bit mono samples from stdin. We use the first one that starts.
+const PLAYERS: [(&str, &[&str]); 3] = [
+    ("aplay", &["-q", "-t", "raw", "-f", "S16_LE", "-r", "44100", "-c", "1", "-"]),
+    ("pacat", &["--raw", "--format=s16le", "--rate=44100", "--channels=1"]),
+    ("play", &["-q", "-t", "raw", "-e", "signed", "-b", "16", "-r", "44100", "-c", "1", "-"]),
+];
+
+// How the buzzer should sound.
+#[derive(Debug, Clone, Copy)]
+pub struct AudioSettings {
+    pub volume: f32, // From 0.0 (silent) to 1.0 (full volume).
+    pub frequency: f32, // Pitch of the square wave, in Hz.
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self { volume: 0.25, frequency: 440.0, muted: false }
+    }
+}
+
+// Generates the buzzer's square wave one sample at a time.
+pub struct Tone {
+    amplitude: i16,
+    step: f32, // How far through one cycle of the wave each sample moves us.
+    phase: f32,
+}
+
+impl Tone {
+    pub fn new(settings: &AudioSettings) -> Self {
+        Self {
+            amplitude: (settings.volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16,
+            step: settings.frequency / SAMPLE_RATE as f32,
+            phase: 0.0,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> i16 {
+        let sample = if self.phase < 0.5 { self.amplitude } else { -self.amplitude };
+        self.phase = (self.phase + self.step) % 1.0;
+        sample
+    }
+}
+
+// State shared between the emulator's sink, the frontend's controls and the synthesis thread.
+struct Shared {
+    on: AtomicBool,
+    muted: AtomicBool,
+}
+
+// The emulator's end. It only flips a flag, so calling it from tick_timers is cheap.
+pub struct SquareWave {
+    shared: Arc<Shared>,
+}
+
+impl AudioSink for SquareWave {
+    fn buzzer(&mut self, on: bool) {
+        self.shared.on.store(on, Ordering::Relaxed);
+    }
+}
+
+// The frontend's end, for muting and unmuting while the game runs.
+pub struct AudioControl {
+    shared: Arc<Shared>,
+}
+
+impl AudioControl {
+    // Flips mute on or off, returning whether audio is now muted.
+    pub fn toggle_mute(&self) -> bool {
+        !self.shared.muted.fetch_xor(true, Ordering::Relaxed)
+    }
+}
+
+// Starts a background thread that synthesizes the buzzer and streams it to an audio player.
+pub fn start(settings: AudioSettings) -> io::Result<(SquareWave, AudioControl)> {
+    let mut player = spawn_player()?;
+    let mut stdin = player.stdin.take().expect("player stdin is piped");
+
+    let shared = Arc::new(Shared {
+        on: AtomicBool::new(false),
+        muted: AtomicBool::new(settings.muted),
+    });
+    let thread_shared = Arc::clone(&shared);
+
+    thread::spawn(move || {
+        let mut tone = Tone::new(&settings);
+
+        let started = Instant::now();
+        let mut samples_written: u64 = 0;
+        let mut chunk = Vec::with_capacity(CHUNK_SAMPLES * 2);
+
+        loop {
+            let audible = thread_shared.on.load(Ordering::Relaxed) && !thread_shared.muted.load(Ordering::Relaxed);
+
+            chunk.clear();
+            for _ in 0..CHUNK_SAMPLES {
+                let sample = if audible { tone.next_sample() } else { 0 };
+                chunk.extend_from_slice(&sample.to_le_bytes());
+            }
+
+            if stdin.write_all(&chunk).is_err() {
+                break; // The player has gone away, so there is nobody left to listen.
+            }
+            samples_written += CHUNK_SAMPLES as u64;
+
+            // The player will happily buffer far more than we need, which makes the buzzer lag behind
+            // the game. So stay only a little ahead of real time.
+            let written_time = Duration::from_secs_f64(samples_written as f64 / SAMPLE_RATE as f64);
+            if let Some(ahead) = written_time.checked_sub(started.elapsed() + MAX_LEAD) {
+                thread::sleep(ahead);
+            }
+        }
+
+        let _ = player.kill();
+    });
+
+    Ok((SquareWave { shared: Arc::clone(&shared) }, AudioControl { shared }))
+}
+
+fn spawn_player() -> io::Result<Child> {
+    for (program, args) in PLAYERS {
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Ok(child) = child {
+            return Ok(child);
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound, "no audio player found (tried aplay, pacat and play)"))
+}
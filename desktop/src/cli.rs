@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::Path;
+
+use chip8_core::script::InputScript;
+use chip8_core::{Emu, Quirks};
+
+use crate::audio::AudioSettings;
+use crate::rom::{self, LoadError};
+
+const DEFAULT_TICKS_PER_FRAME: u32 = 10; // How many instructions to run for every 60 Hz frame.
+
+// Usage text for the options that every command running a ROM understands.
+pub const EMU_OPTIONS_USAGE: &str = "[--ipf <instructions per frame>] [--quirks vip|chip48|schip|xochip] [--seed <number>]";
+pub const AUDIO_OPTIONS_USAGE: &str = "[--volume <0-100>] [--frequency <Hz>]";
+
+// Options for setting up the emulator, shared by every command that runs a ROM.
+pub struct EmuOptions {
+    pub ticks_per_frame: u32,
+    pub quirks: Quirks,
+    pub seed: Option<u64>, // Fixes the random numbers, for reproducible runs.
+}
+
+impl Default for EmuOptions {
+    fn default() -> Self {
+        Self { ticks_per_frame: DEFAULT_TICKS_PER_FRAME, quirks: Quirks::default(), seed: None }
+    }
+}
+
+impl EmuOptions {
+    // Handles arg if it is one of the emulator options, taking its value from rest.
+    // Returns Some(true) if it was handled, Some(false) if it is not one of ours,
+    // and None if its value is missing or invalid.
+    pub fn parse_arg<'a>(&mut self, arg: &str, rest: &mut impl Iterator<Item = &'a String>) -> Option<bool> {
+        match arg {
+            "--ipf" => self.ticks_per_frame = rest.next()?.parse().ok()?,
+            "--quirks" => self.quirks = parse_quirks(rest.next()?)?,
+            "--seed" => self.seed = Some(rest.next()?.parse().ok()?),
+            _ => return Some(false),
+        }
+        Some(true)
+    }
+
+    // Creates an emulator with these options and loads the ROM into it.
+    pub fn create_emu(&self, rom: &Path) -> Result<Emu, LoadError> {
+        let mut emu = self.new_emu();
+        rom::load_rom_file(&mut emu, rom)?;
+        Ok(emu)
+    }
+
+    // Creates an emulator with these options, with nothing loaded into it yet.
+    pub fn new_emu(&self) -> Emu {
+        match self.seed {
+            Some(seed) => Emu::with_seed(self.quirks, seed),
+            None => Emu::new(self.quirks),
+        }
+    }
+}
+
+// Handles arg if it is one of the buzzer options, the same way as EmuOptions::parse_arg.
+pub fn parse_audio_arg<'a>(
+    settings: &mut AudioSettings,
+    arg: &str,
+    rest: &mut impl Iterator<Item = &'a String>,
+) -> Option<bool> {
+    match arg {
+        "--volume" => settings.volume = rest.next()?.parse::<u8>().ok().filter(|&v| v <= 100)? as f32 / 100.0,
+        "--frequency" => settings.frequency = rest.next()?.parse().ok().filter(|&f: &f32| f > 0.0)?,
+        _ => return Some(false),
+    }
+    Some(true)
+}
+
+pub fn parse_quirks(name: &str) -> Option<Quirks> {
+    match name {
+        "vip" => Some(Quirks::COSMAC_VIP),
+        "chip48" => Some(Quirks::CHIP_48),
+        "schip" => Some(Quirks::SUPER_CHIP),
+        "xochip" => Some(Quirks::XO_CHIP),
+        _ => None,
+    }
+}
+
+// Reads the --script file, or gives an empty script that never presses anything when there isn't one.
+pub fn load_script(path: Option<&Path>) -> Result<InputScript, Box<dyn std::error::Error>> {
+    match path {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+            Ok(InputScript::parse(&text)?)
+        },
+        None => Ok(InputScript::default()),
+    }
+}
@@ -0,0 +1,849 @@
+// A Debug Adapter Protocol server, so editors like VS Code can debug CHIP-8 programs.
+// It talks to the editor over stdin and stdout, with each message a JSON object after a
+// Content-Length header.
+//
+// The launch request takes these arguments:
+//   program      A ROM, or an Octo (.8o) or assembly (.asm) source file to build and run.
+//   stopOnEntry  Stop before the first instruction runs.
+//   quirks, ipf, seed  Like the command line options of the same names.
+//   target       chip8, schip or xochip: which instructions Octo source may use (xochip by default).
+//
+// Breakpoints go through the source map the assembler makes, so they need the program to be launched from source.
+// The debug console understands register names, labels, `key KEY down|up`, `screen` and `reset`.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip8_core::asm::{self, Assembly};
+use chip8_core::debug::StopReason;
+use chip8_core::disasm::{self, Syntax};
+use chip8_core::octo::{self, Target};
+use chip8_core::opcode::Op;
+use chip8_core::{CpuState, Emu, EmuError, NUM_KEYS};
+
+use crate::asm::parse_target;
+use crate::cli::{self, EmuOptions};
+use crate::debug::set_register;
+use crate::headless::FrameClock;
+use crate::json::Json;
+use crate::run;
+
+const FRAME_TIME: Duration = Duration::from_micros(16_667); // 60 frames per second, like play.
+const THREAD_ID: u64 = 1; // CHIP-8 only has the one thread, but the protocol wants to know its number.
+
+// The variablesReference of each scope.
+const REGISTERS_SCOPE: u64 = 1;
+const TIMERS_SCOPE: u64 = 2;
+
+pub fn usage(program: &str) -> String {
+    format!("{} dap", program)
+}
+
+// Serves one debugging session over stdin and stdout, until the editor disconnects.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if !args.is_empty() {
+        return Err(format!("Usage: {}", usage(program)).into());
+    }
+    Adapter::new().serve(read_messages())
+}
+
+// Reads messages from stdin on a thread of their own, so the program can keep running while we wait for them.
+// The channel closes when stdin does.
+fn read_messages() -> Receiver<Json> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(io::stdin());
+        loop {
+            match read_message(&mut input) {
+                Ok(Some(message)) => {
+                    if sender.send(message).is_err() {
+                        return;
+                    }
+                },
+                Ok(None) => return,
+                Err(err) => eprintln!("Ignoring a message: {}", err),
+            }
+        }
+    });
+    receiver
+}
+
+// Reads one message, or returns None at the end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, String> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|err| err.to_string())? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let len = len.ok_or("the message has no Content-Length")?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body).map_err(|err| err.to_string())?;
+    Json::parse(&String::from_utf8_lossy(&body)).map(Some)
+}
+
+// Why a resumed program stopped, which becomes events for the editor.
+#[derive(Debug)]
+enum Stop {
+    Entry,
+    Step,
+    Breakpoint,
+    Pause,
+    Exited,
+    Crashed(EmuError),
+}
+
+// What a resumed program is running until.
+#[derive(Debug, Clone)]
+enum Resume {
+    Continue,
+    // Until pc leaves the source line it started on, or after one instruction if there is no line.
+    // With over set, calls are stepped over by carrying on while the stack is deeper than depth.
+    Step { line: Option<(PathBuf, usize)>, depth: u16, over: bool },
+    // Until the stack is shallower than depth.
+    Out { depth: u16 },
+}
+
+// The address of an instruction and where it came from, with the path made absolute
+// so it can be compared with the ones the editor sends.
+struct Line {
+    addr: u16,
+    path: PathBuf,
+    line: usize,
+}
+
+// A launched program.
+struct Session {
+    emu: Emu,
+    clock: FrameClock,
+    keys: [bool; NUM_KEYS], // Which keys have been held down from the debug console.
+    lines: Vec<Line>, // Sorted by address. Empty when the program was launched as a ROM.
+    labels: BTreeMap<String, u16>,
+    breakpoints: BTreeMap<PathBuf, Vec<u16>>, // The addresses that each source file's breakpoints are on.
+    stop_on_entry: bool,
+    resume: Option<Resume>, // Set while the program is running.
+    pending: Option<Stop>, // A stop that happened while handling the current request.
+    frame_start: Instant,
+}
+
+struct Adapter {
+    seq: u64, // The number of the last message we sent.
+    session: Option<Session>,
+    events: Vec<(&'static str, Json)>, // Events to send once the response to the current request has gone.
+}
+
+impl Adapter {
+    fn new() -> Self {
+        Self { seq: 0, session: None, events: Vec::new() }
+    }
+
+    // Handles requests, running the program in between while it isn't stopped, until the editor disconnects.
+    fn serve(&mut self, messages: Receiver<Json>) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            let running = self.session.as_ref().is_some_and(|session| session.resume.is_some());
+            let message = if running {
+                match messages.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match messages.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => return Ok(()),
+                }
+            };
+
+            match message {
+                Some(message) => {
+                    if message.get("type").as_str() == Some("request") && !self.handle(&message)? {
+                        return Ok(());
+                    }
+                },
+                None => self.run_frame()?,
+            }
+        }
+    }
+
+    // Runs the program for a frame, keeping to 60 frames a second.
+    fn run_frame(&mut self) -> io::Result<()> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        let stop = session.run_frame();
+        if let Some(remaining) = FRAME_TIME.checked_sub(session.frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+        session.frame_start = Instant::now();
+        if let Some(stop) = stop {
+            session.resume = None;
+            self.stopped(stop);
+            self.send_events()?;
+        }
+        Ok(())
+    }
+
+    // Answers a request. Returns false once the editor has disconnected.
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request.get("command").as_str().unwrap_or("");
+        let args = request.get("arguments");
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "disconnect" => Ok(Json::Null),
+            "terminate" => {
+                self.events.push(("terminated", Json::Null));
+                Ok(Json::Null)
+            },
+            "threads" => Ok(Json::object([(
+                "threads",
+                Json::from(vec![Json::object([("id", Json::from(THREAD_ID)), ("name", Json::from("CHIP-8"))])]),
+            )])),
+            _ => match self.session.as_mut() {
+                Some(session) => session.handle(command, args),
+                None => Err("No program has been launched".to_string()),
+            },
+        };
+
+        // Requests that set the program going can stop it again straight away, like a single step.
+        if let Some(stop) = self.session.as_mut().and_then(|session| session.take_immediate_stop()) {
+            self.stopped(stop);
+        }
+
+        self.respond(request, command, result)?;
+        self.send_events()?;
+        Ok(command != "disconnect")
+    }
+
+    // Starts the program from a launch request.
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        self.session = Some(Session::launch(args)?);
+        // Only now is the editor told it can set breakpoints, so they always arrive after launch.
+        self.events.push(("initialized", Json::Null));
+        Ok(Json::Null)
+    }
+
+    // Queues the events that tell the editor the program stopped.
+    fn stopped(&mut self, stop: Stop) {
+        let (reason, description) = match stop {
+            Stop::Exited => {
+                self.events.push(("exited", Json::object([("exitCode", Json::from(0u8))])));
+                self.events.push(("terminated", Json::Null));
+                return;
+            },
+            Stop::Crashed(err) => {
+                let text = format!("The program crashed: {}", err);
+                self.events.push(("output", Json::object([("category", Json::from("stderr")), ("output", Json::from(format!("{}\n", text)))])));
+                ("exception", Some(text))
+            },
+            Stop::Entry => ("entry", None),
+            Stop::Step => ("step", None),
+            Stop::Breakpoint => ("breakpoint", None),
+            Stop::Pause => ("pause", None),
+        };
+        let mut body = Json::object([
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ]);
+        if let (Json::Object(fields), Some(text)) = (&mut body, description) {
+            fields.insert("description".to_string(), Json::from(text.clone()));
+            fields.insert("text".to_string(), Json::from(text));
+        }
+        self.events.push(("stopped", body));
+    }
+
+    fn respond(&mut self, request: &Json, command: &str, result: Result<Json, String>) -> io::Result<()> {
+        let mut response = Json::object([
+            ("type", Json::from("response")),
+            ("request_seq", request.get("seq").clone()),
+            ("command", Json::from(command)),
+            ("success", Json::from(result.is_ok())),
+        ]);
+        if let Json::Object(fields) = &mut response {
+            match result {
+                Ok(Json::Null) => {},
+                Ok(body) => {
+                    fields.insert("body".to_string(), body);
+                },
+                Err(message) => {
+                    fields.insert("message".to_string(), Json::from(message));
+                },
+            }
+        }
+        self.send(response)
+    }
+
+    fn send_events(&mut self) -> io::Result<()> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            let mut message = Json::object([("type", Json::from("event")), ("event", Json::from(event))]);
+            if let (Json::Object(fields), false) = (&mut message, body == Json::Null) {
+                fields.insert("body".to_string(), body);
+            }
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        if let Json::Object(fields) = &mut message {
+            fields.insert("seq".to_string(), Json::from(self.seq));
+        }
+        let body = message.to_string();
+        let mut out = io::stdout().lock();
+        write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        out.flush()
+    }
+}
+
+// What the editor can ask of us.
+fn capabilities() -> Json {
+    Json::object([
+        ("supportsConfigurationDoneRequest", Json::from(true)),
+        ("supportsEvaluateForHovers", Json::from(true)),
+        ("supportsSetVariable", Json::from(true)),
+        ("supportsSteppingGranularity", Json::from(true)),
+        ("supportsReadMemoryRequest", Json::from(true)),
+        ("supportsWriteMemoryRequest", Json::from(true)),
+        ("supportsTerminateRequest", Json::from(true)),
+    ])
+}
+
+impl Session {
+    fn launch(args: &Json) -> Result<Session, String> {
+        let program = PathBuf::from(args.get("program").as_str().ok_or("launch needs a program")?);
+        let mut options = EmuOptions::default();
+        if let Some(name) = args.get("quirks").as_str() {
+            options.quirks = cli::parse_quirks(name).ok_or_else(|| format!("Unknown quirks '{}'", name))?;
+        }
+        if let Some(ipf) = args.get("ipf").as_u64() {
+            options.ticks_per_frame = u32::try_from(ipf).map_err(|_| "ipf is too large")?;
+        }
+        options.seed = args.get("seed").as_u64();
+        let target = match args.get("target").as_str() {
+            Some(name) => parse_target(name).ok_or_else(|| format!("Unknown target '{}'", name))?,
+            None => Target::XoChip,
+        };
+
+        let assembly = match program.extension().and_then(|ext| ext.to_str()) {
+            Some("8o") => octo::compile_file(&program, target).map_err(|err| err.to_string())?,
+            Some("asm") => asm::assemble_file(&program).map_err(|err| err.to_string())?,
+            _ => Assembly {
+                bytes: fs::read(&program).map_err(|err| format!("Could not read {}: {}", program.display(), err))?,
+                ..Assembly::default()
+            },
+        };
+        let mut emu = options.new_emu();
+        emu.load_rom(&assembly.bytes).map_err(|err| err.to_string())?;
+
+        let mut paths = BTreeMap::new();
+        let lines = assembly
+            .source_map
+            .iter()
+            .map(|line| Line {
+                addr: line.addr,
+                path: paths.entry(line.file.clone()).or_insert_with(|| absolute(Path::new(&line.file))).clone(),
+                line: line.line,
+            })
+            .collect();
+
+        Ok(Session {
+            emu,
+            clock: FrameClock::new(options.ticks_per_frame),
+            keys: [false; NUM_KEYS],
+            lines,
+            labels: assembly.labels,
+            breakpoints: BTreeMap::new(),
+            stop_on_entry: args.get("stopOnEntry").as_bool().unwrap_or(false),
+            resume: None,
+            pending: None,
+            frame_start: Instant::now(),
+        })
+    }
+
+    fn handle(&mut self, command: &str, args: &Json) -> Result<Json, String> {
+        match command {
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.resume = None;
+                    self.immediate_stop(Stop::Entry);
+                } else {
+                    // run_until checks for a breakpoint on the very first instruction, so there's nothing to step off.
+                    self.resume = Some(Resume::Continue);
+                }
+                Ok(Json::Null)
+            },
+            "setBreakpoints" => self.set_breakpoints(args),
+            "continue" => {
+                self.start(Resume::Continue);
+                Ok(Json::object([("allThreadsContinued", Json::from(true))]))
+            },
+            "next" | "stepIn" => {
+                let state = self.emu.cpu_state();
+                let line = match args.get("granularity").as_str() {
+                    Some("instruction") => None,
+                    _ => self.line_at(state.pc).map(|line| (line.path.clone(), line.line)),
+                };
+                self.start(Resume::Step { line, depth: state.sp, over: command == "next" });
+                Ok(Json::Null)
+            },
+            "stepOut" => {
+                let depth = self.emu.cpu_state().sp;
+                if depth == 0 {
+                    return Err("The program is not in a subroutine".to_string());
+                }
+                self.start(Resume::Out { depth });
+                Ok(Json::Null)
+            },
+            "pause" => {
+                if self.resume.take().is_some() {
+                    self.immediate_stop(Stop::Pause);
+                }
+                Ok(Json::Null)
+            },
+            "stackTrace" => Ok(self.stack_trace(args)),
+            "scopes" => Ok(Json::object([(
+                "scopes",
+                Json::from(vec![scope("Registers", REGISTERS_SCOPE), scope("Timers", TIMERS_SCOPE)]),
+            )])),
+            "variables" => {
+                let names: &[&str] = match args.get("variablesReference").as_u64() {
+                    Some(REGISTERS_SCOPE) => &REGISTER_NAMES,
+                    Some(TIMERS_SCOPE) => &["DT", "ST"],
+                    _ => &[],
+                };
+                let state = self.emu.cpu_state();
+                let variables = names.iter().map(|&name| variable(&state, name)).collect::<Vec<_>>();
+                Ok(Json::object([("variables", Json::from(variables))]))
+            },
+            "setVariable" => {
+                let name = args.get("name").as_str().unwrap_or("");
+                let text = args.get("value").as_str().unwrap_or("");
+                let value = parse_number(text).ok_or_else(|| format!("'{}' is not a number", text))?;
+                set_register(&mut self.emu, name, value)?;
+                let state = self.emu.cpu_state();
+                Ok(Json::object([("value", Json::from(format_register(&state, name).unwrap_or_default()))]))
+            },
+            "evaluate" => self.evaluate(args.get("expression").as_str().unwrap_or("").trim(), args.get("context").as_str()),
+            "readMemory" => self.read_memory(args),
+            "writeMemory" => {
+                let addr = memory_address(args)?;
+                let data = args.get("data").as_str().and_then(from_base64).ok_or("The data is not valid base64")?;
+                self.emu.write_memory(addr, &data).map_err(|err| err.to_string())?;
+                Ok(Json::object([("bytesWritten", Json::from(data.len()))]))
+            },
+            _ => Err(format!("Unsupported request '{}'", command)),
+        }
+    }
+
+    // Sets the program going, after first running the instruction at pc so we don't stop on the breakpoint
+    // we are continuing from. A step that is already finished after that stops straight away.
+    fn start(&mut self, resume: Resume) {
+        self.frame_start = Instant::now();
+        match self.step(&resume) {
+            Some(stop) => self.immediate_stop(stop),
+            None => self.resume = Some(resume),
+        }
+    }
+
+    // Notes a stop that happened while handling a request, to be reported after the response.
+    fn immediate_stop(&mut self, stop: Stop) {
+        self.resume = None;
+        self.pending = Some(stop);
+    }
+
+    fn take_immediate_stop(&mut self) -> Option<Stop> {
+        self.pending.take()
+    }
+
+    // Runs the program for the rest of the current frame. Returns why it stopped, or None if it is still going.
+    fn run_frame(&mut self) -> Option<Stop> {
+        let resume = self.resume.clone()?;
+        if let Resume::Continue = resume {
+            return match self.emu.run_until(self.clock.remaining()) {
+                Ok((ticks, reason)) => {
+                    self.clock.advance(&mut self.emu, ticks);
+                    match reason {
+                        StopReason::TickLimit => None,
+                        StopReason::Exited => Some(Stop::Exited),
+                        _ => Some(Stop::Breakpoint),
+                    }
+                },
+                Err(err) => Some(Stop::Crashed(err)),
+            };
+        }
+        loop {
+            if let Some(stop) = self.step(&resume) {
+                return Some(stop);
+            }
+            if self.clock.at_frame_start() {
+                return None;
+            }
+        }
+    }
+
+    // Runs one instruction, ignoring any breakpoint on it, and says whether that finished what resume asked for.
+    fn step(&mut self, resume: &Resume) -> Option<Stop> {
+        match self.clock.step(&mut self.emu) {
+            Ok(_) if self.emu.has_exited() => return Some(Stop::Exited),
+            Ok(_) => {},
+            Err(err) => return Some(Stop::Crashed(err)),
+        }
+        let state = self.emu.cpu_state();
+        let done = match resume {
+            Resume::Continue => false,
+            Resume::Step { over: true, depth, .. } if state.sp > *depth => false,
+            Resume::Step { line: None, .. } => true,
+            Resume::Step { line: Some((path, line)), .. } => {
+                self.line_at(state.pc).is_none_or(|here| here.path != *path || here.line != *line)
+            },
+            Resume::Out { depth } => state.sp < *depth,
+        };
+        if done {
+            Some(Stop::Step)
+        } else if self.emu.breakpoints().contains(&state.pc) {
+            Some(Stop::Breakpoint)
+        } else {
+            None
+        }
+    }
+
+    // Replaces the breakpoints in a source file. Each one goes on the first instruction on or after its line.
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let path = absolute(Path::new(args.get("source").get("path").as_str().ok_or("The source has no path")?));
+        for addr in self.breakpoints.remove(&path).unwrap_or_default() {
+            self.emu.remove_breakpoint(addr);
+        }
+
+        let mut addrs = Vec::new();
+        let mut results = Vec::new();
+        for requested in args.get("breakpoints").as_array() {
+            let wanted = requested.get("line").as_u64().unwrap_or(0) as usize;
+            let found = self
+                .lines
+                .iter()
+                .filter(|line| line.path == path && line.line >= wanted)
+                .min_by_key(|line| (line.line, line.addr));
+            results.push(match found {
+                Some(line) => {
+                    addrs.push(line.addr);
+                    self.emu.add_breakpoint(line.addr);
+                    Json::object([
+                        ("verified", Json::from(true)),
+                        ("line", Json::from(line.line)),
+                        ("instructionReference", Json::from(format_addr(line.addr))),
+                    ])
+                },
+                None => {
+                    let message = if self.lines.is_empty() {
+                        "Breakpoints need the program to be launched from source"
+                    } else {
+                        "There is no code on or after this line"
+                    };
+                    Json::object([("verified", Json::from(false)), ("message", Json::from(message))])
+                },
+            });
+        }
+        self.breakpoints.insert(path, addrs);
+        Ok(Json::object([("breakpoints", Json::from(results))]))
+    }
+
+    // The current instruction, then the call of every subroutine we are in, most recent first.
+    fn stack_trace(&self, args: &Json) -> Json {
+        let state = self.emu.cpu_state();
+        let mut addrs = vec![state.pc];
+        // Each stack entry is where a call returns to, so the call itself is just before it.
+        addrs.extend(state.stack[..state.sp as usize].iter().rev().map(|addr| addr.wrapping_sub(2)));
+
+        let start = args.get("startFrame").as_u64().unwrap_or(0) as usize;
+        let levels = match args.get("levels").as_u64() {
+            Some(0) | None => addrs.len(),
+            Some(levels) => levels as usize,
+        };
+        let frames = addrs
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, &addr)| {
+                let mut frame = Json::object([
+                    ("id", Json::from(id)),
+                    ("name", Json::from(self.frame_name(addr))),
+                    ("line", Json::from(0u8)),
+                    ("column", Json::from(0u8)),
+                    ("instructionPointerReference", Json::from(format_addr(addr))),
+                ]);
+                if let (Json::Object(fields), Some(line)) = (&mut frame, self.line_at(addr)) {
+                    let name = line.path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                    let source = Json::object([("name", Json::from(name)), ("path", Json::from(line.path.display().to_string()))]);
+                    fields.insert("source".to_string(), source);
+                    fields.insert("line".to_string(), Json::from(line.line));
+                    fields.insert("column".to_string(), Json::from(1u8));
+                }
+                frame
+            })
+            .collect::<Vec<_>>();
+        Json::object([("stackFrames", Json::from(frames)), ("totalFrames", Json::from(addrs.len()))])
+    }
+
+    // Names a frame after the label it is in, or after its instruction when there are no labels.
+    fn frame_name(&self, addr: u16) -> String {
+        match self.labels.iter().filter(|(_, &label)| label <= addr).max_by_key(|(_, &label)| label) {
+            Some((name, &label)) if label == addr => name.clone(),
+            Some((name, &label)) => format!("{}+{}", name, addr - label),
+            None => match self.emu.memory().get(addr as usize..).and_then(|bytes| Op::decode_bytes(bytes, self.emu.quirks().xo_chip)) {
+                Some(op) => format!("{:04X}  {}", addr, disasm::format_op(&op, Syntax::Octo)),
+                None => format!("{:04X}", addr),
+            },
+        }
+    }
+
+    // Handles the debug console, and hovering over names in the source.
+    fn evaluate(&mut self, expression: &str, context: Option<&str>) -> Result<Json, String> {
+        let state = self.emu.cpu_state();
+        if let Some(value) = format_register(&state, expression) {
+            return Ok(Json::object([("result", Json::from(value)), ("variablesReference", Json::from(0u8))]));
+        }
+        if let Some(&addr) = self.labels.get(expression) {
+            return Ok(Json::object([
+                ("result", Json::from(format_addr(addr))),
+                ("variablesReference", Json::from(0u8)),
+                ("memoryReference", Json::from(format_addr(addr))),
+            ]));
+        }
+        if context != Some("repl") {
+            return Err(format!("'{}' is not a register or label", expression));
+        }
+
+        let words: Vec<&str> = expression.split_whitespace().collect();
+        let output = match words.as_slice() {
+            ["key", key, state] => {
+                let key = usize::from_str_radix(key, 16).ok().filter(|&key| key < NUM_KEYS).ok_or("There is no such key")?;
+                self.keys[key] = match *state {
+                    "down" => true,
+                    "up" => false,
+                    _ => return Err("Keys can be 'down' or 'up'".to_string()),
+                };
+                self.emu.keypress(key, self.keys[key]);
+                String::new()
+            },
+            ["screen"] => run::screen_to_ascii(&self.emu),
+            ["reset"] => {
+                self.emu.reset();
+                self.clock.reset();
+                for (key, &down) in self.keys.iter().enumerate() {
+                    self.emu.keypress(key, down);
+                }
+                if self.resume.is_none() {
+                    self.immediate_stop(Stop::Entry);
+                }
+                String::new()
+            },
+            _ => "Type a register or label to see its value, or one of:\n  key KEY down|up\n  screen\n  reset".to_string(),
+        };
+        Ok(Json::object([("result", Json::from(output)), ("variablesReference", Json::from(0u8))]))
+    }
+
+    // Reads count bytes from the memory reference plus offset. Anything past the end of RAM is unreadable,
+    // but the read has to start inside it.
+    fn read_memory(&self, args: &Json) -> Result<Json, String> {
+        let addr = memory_address(args)?;
+        let count = args.get("count").as_u64().unwrap_or(0) as usize;
+        let memory = self.emu.memory();
+        let start = u16::try_from(addr).ok().filter(|_| addr < memory.len());
+        let start = start.ok_or_else(|| format!("0x{:X} is past the end of memory", addr))?;
+        let end = addr.saturating_add(count).min(memory.len());
+        let data = &memory[addr..end];
+        Ok(Json::object([
+            ("address", Json::from(format_addr(start))),
+            ("data", Json::from(to_base64(data))),
+            ("unreadableBytes", Json::from(count - data.len())),
+        ]))
+    }
+
+    // The line of source that the instruction at addr was written on.
+    fn line_at(&self, addr: u16) -> Option<&Line> {
+        let index = self.lines.binary_search_by_key(&addr, |line| line.addr).ok()?;
+        Some(&self.lines[index])
+    }
+}
+
+const REGISTER_NAMES: [&str; 19] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF", "I", "PC", "SP",
+];
+
+fn scope(name: &str, reference: u64) -> Json {
+    Json::object([("name", Json::from(name)), ("variablesReference", Json::from(reference)), ("expensive", Json::from(false))])
+}
+
+fn variable(state: &CpuState, name: &str) -> Json {
+    let mut variable = Json::object([
+        ("name", Json::from(name)),
+        ("value", Json::from(format_register(state, name).unwrap_or_default())),
+        ("variablesReference", Json::from(0u8)),
+    ]);
+    // Addresses can be opened in the editor's memory view.
+    let addr = match name {
+        "I" => Some(state.i),
+        "PC" => Some(state.pc),
+        _ => None,
+    };
+    if let (Json::Object(fields), Some(addr)) = (&mut variable, addr) {
+        fields.insert("memoryReference".to_string(), Json::from(format_addr(addr)));
+    }
+    variable
+}
+
+// Shows a register the way CHIP-8 programmers write them, in hex. The stack pointer and timers are counts.
+fn format_register(state: &CpuState, name: &str) -> Option<String> {
+    let name = name.to_uppercase();
+    let value = match name.as_str() {
+        "I" => format_addr(state.i),
+        "PC" => format_addr(state.pc),
+        "SP" => state.sp.to_string(),
+        "DT" => state.dt.to_string(),
+        "ST" => state.st.to_string(),
+        _ => {
+            let digit = name.strip_prefix('V').filter(|digit| digit.len() == 1)?;
+            format!("0x{:02X}", state.v[usize::from_str_radix(digit, 16).ok()?])
+        },
+    };
+    Some(value)
+}
+
+fn format_addr(addr: u16) -> String {
+    format!("0x{:04X}", addr)
+}
+
+// Parses a number the way Octo writes them: decimal, 0x hex or 0b binary.
+fn parse_number(text: &str) -> Option<u32> {
+    if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u32::from_str_radix(digits, 16).ok()
+    } else if let Some(digits) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        u32::from_str_radix(digits, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+// The address that a readMemory or writeMemory request starts at.
+fn memory_address(args: &Json) -> Result<usize, String> {
+    let reference = args.get("memoryReference").as_str().unwrap_or("");
+    let base = parse_number(reference).ok_or_else(|| format!("'{}' is not an address", reference))?;
+    let offset = args.get("offset").as_i64().unwrap_or(0);
+    i64::from(base)
+        .checked_add(offset)
+        .and_then(|addr| usize::try_from(addr).ok())
+        .ok_or_else(|| format!("{} plus {} is not an address", reference, offset))
+}
+
+// Paths from the editor and the assembler are compared once both are absolute.
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+const BASE64_DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn to_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| group | (byte as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_DIGITS[(group >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn from_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut group, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|&c| c != b'=') {
+        let digit = BASE64_DIGITS.iter().position(|&d| d == c)? as u32;
+        group = group << 6 | digit;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((group >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_args(reference: &str, offset: i64) -> Json {
+        Json::object([("memoryReference", Json::from(reference)), ("offset", Json::from(offset))])
+    }
+
+    #[test]
+    fn memory_address_adds_the_offset() {
+        assert_eq!(memory_address(&memory_args("0x200", 2)), Ok(0x202));
+        assert_eq!(memory_address(&memory_args("0x200", -0x200)), Ok(0));
+        assert!(memory_address(&memory_args("0x200", -0x201)).is_err());
+        assert!(memory_address(&memory_args("0x200", i64::MAX)).is_err());
+        assert!(memory_address(&memory_args("pc", 0)).is_err());
+    }
+
+    #[test]
+    fn base64_pads_every_length() {
+        let cases: [(&[u8], &str); 5] =
+            [(b"", ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foob", "Zm9vYg==")];
+        for (bytes, text) in cases {
+            assert_eq!(to_base64(bytes), text);
+            assert_eq!(from_base64(text).as_deref(), Some(bytes));
+        }
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(from_base64(&to_base64(&all)), Some(all));
+        assert_eq!(from_base64("Zm9v!"), None);
+    }
+
+    fn read(input: &str) -> Result<Option<Json>, String> {
+        read_message(&mut io::Cursor::new(input.as_bytes()))
+    }
+
+    #[test]
+    fn reads_messages_by_content_length() {
+        let mut input = io::Cursor::new("Content-Length: 7\r\n\r\n{\"a\":1}Content-Length:2\r\n\r\n[]".as_bytes());
+        assert_eq!(read_message(&mut input), Ok(Some(Json::object([("a", Json::from(1u8))]))));
+        assert_eq!(read_message(&mut input), Ok(Some(Json::Array(Vec::new()))));
+        assert_eq!(read_message(&mut input), Ok(None));
+    }
+
+    #[test]
+    fn reads_headers_in_any_case_and_order() {
+        let input = "Content-Type: application/vscode-jsonrpc\r\ncontent-length:  4 \r\n\r\nnull";
+        assert_eq!(read(input), Ok(Some(Json::Null)));
+        assert_eq!(read("Content-Length: 4\n\ntrue"), Ok(Some(Json::Bool(true))));
+    }
+
+    #[test]
+    fn rejects_messages_without_a_length() {
+        assert_eq!(read("Content-Type: text\r\n\r\n{}"), Err("the message has no Content-Length".to_string()));
+        assert_eq!(read("Content-Length: many\r\n\r\n{}"), Err("the message has no Content-Length".to_string()));
+        assert!(read("Content-Length: 10\r\n\r\n{}").is_err());
+        assert!(read("Content-Length: 2\r\n\r\n{]").is_err());
+        assert_eq!(read(""), Ok(None));
+    }
+}
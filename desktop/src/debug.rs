@@ -0,0 +1,598 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use chip8_core::debug::{Access, Register, StopReason};
+use chip8_core::disasm::{self, Syntax};
+use chip8_core::opcode::Op;
+use chip8_core::{Emu, NUM_KEYS};
+
+use crate::cli::{self, EmuOptions};
+use crate::headless::FrameClock;
+use crate::run;
+
+// How long `continue` runs for when nothing stops it, so a game that never halts hands control back.
+const DEFAULT_CONTINUE_FRAMES: u32 = 3600; // One minute.
+const DEFAULT_DUMP_LEN: usize = 64;
+const DUMP_BYTES_PER_LINE: usize = 16;
+const LIST_BYTES_BEFORE: u16 = 6; // How far before the address `list` starts.
+const LIST_LINES: usize = 10;
+
+const HELP: &str = "\
+Counts and frames are decimal. Addresses and values are hex, with or without 0x.
+  step [N]              s   Run N instructions (1 by default)
+  continue [FRAMES]     c   Run until something stops the program, for at most FRAMES frames
+  break [ADDR]          b   Stop when pc reaches ADDR, or list the breakpoints and watches
+  break-op PATTERN      bo  Stop on opcodes matching PATTERN, like D..5 or 00E0. Any non-hex digit matches anything
+  watch ADDR [read|write|any]  w  Stop after an instruction reads or writes ADDR as data (write by default)
+  watch REG             w   Stop after an instruction changes V0-VF or I
+  delete ADDR|PATTERN|REG  d  Remove a breakpoint or watch, or every one of them with 'delete all'
+  regs                  r   Show the registers, I, timers and stack pointer
+  set REG VALUE             Change V0-VF, I, PC, DT or ST
+  mem ADDR [LEN]        m   Show LEN bytes of memory from ADDR
+  poke ADDR BYTE...         Write bytes into memory from ADDR
+  list [ADDR]           l   Disassemble around pc, or around ADDR
+  stack                 bt  Show the call stack
+  key KEY down|up           Hold or release one of the hex keys
+  screen                    Draw the screen
+  reset                     Start the ROM again
+  help                  h   Show this list
+  quit                  q   Leave the debugger
+An empty line repeats the last command.";
+
+// Everything that can be set on the command line.
+struct Options {
+    rom: PathBuf,
+    emu: EmuOptions,
+}
+
+pub fn usage(program: &str) -> String {
+    format!("{} debug {} <ROM>", program, cli::EMU_OPTIONS_USAGE)
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut rom = None;
+    let mut emu = EmuOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if emu.parse_arg(arg, &mut iter)? {
+            continue;
+        }
+        match arg.as_str() {
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    Some(Options { rom: rom?, emu })
+}
+
+// Runs a ROM under an interactive debugger that reads commands from stdin.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let emu = options.emu.create_emu(&options.rom)?;
+    let mut debugger = Debugger::new(emu, options.emu.ticks_per_frame);
+
+    println!("Debugging {}. Type 'help' for a list of commands.", options.rom.display());
+    println!("{}", debugger.current_line());
+
+    let stdin = io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break; // End of input.
+        }
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        last = line.clone();
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            None => continue,
+            Some(&"quit") | Some(&"q") => break,
+            _ => {},
+        }
+        if let Err(message) = debugger.command(&words) {
+            println!("{}", message);
+        }
+    }
+    Ok(())
+}
+
+// Parses an opcode pattern like D..5 into a mask and the value the masked opcode has to equal.
+fn parse_pattern(text: &str) -> Option<(u16, u16)> {
+    if text.chars().count() != 4 {
+        return None;
+    }
+    let (mut mask, mut value) = (0, 0);
+    for c in text.chars() {
+        mask <<= 4;
+        value <<= 4;
+        if let Some(digit) = c.to_digit(16) {
+            mask |= 0xF;
+            value |= digit as u16;
+        }
+    }
+    Some((mask, value))
+}
+
+// Writes an opcode pattern back out, with dots for the digits that can be anything.
+fn format_pattern(mask: u16, value: u16) -> String {
+    (0..4)
+        .rev()
+        .map(|digit| match (mask >> (digit * 4)) & 0xF {
+            0xF => format!("{:X}", (value >> (digit * 4)) & 0xF),
+            _ => ".".to_string(),
+        })
+        .collect()
+}
+
+// Parses V0-VF or I.
+fn parse_register(text: &str) -> Option<Register> {
+    let text = text.to_uppercase();
+    if text == "I" {
+        return Some(Register::I);
+    }
+    let digit = text.strip_prefix('V').filter(|digit| digit.len() == 1)?;
+    u8::from_str_radix(digit, 16).ok().map(Register::V)
+}
+
+fn register_name(register: Register) -> String {
+    match register {
+        Register::V(x) => format!("V{:X}", x),
+        Register::I => "I".to_string(),
+    }
+}
+
+struct Debugger {
+    emu: Emu,
+    clock: FrameClock,
+    keys: [bool; NUM_KEYS], // Which keys have been held down with the key command.
+}
+
+impl Debugger {
+    fn new(emu: Emu, ticks_per_frame: u32) -> Self {
+        Self {
+            emu,
+            clock: FrameClock::new(ticks_per_frame),
+            keys: [false; NUM_KEYS],
+        }
+    }
+
+    fn command(&mut self, words: &[&str]) -> Result<(), String> {
+        let args = &words[1..];
+        match words[0] {
+            "step" | "s" => {
+                let count = match args.first() {
+                    Some(count) => count.parse().map_err(|_| format!("'{}' is not a count", count))?,
+                    None => 1,
+                };
+                let result = self.step(count);
+                println!("{}", self.current_line());
+                result
+            },
+            "continue" | "c" => {
+                let frames = match args.first() {
+                    Some(frames) => frames.parse().map_err(|_| format!("'{}' is not a count", frames))?,
+                    None => DEFAULT_CONTINUE_FRAMES,
+                };
+                let result = self.cont(frames);
+                println!("{}", self.current_line());
+                result
+            },
+            "break" | "b" => match args.first() {
+                Some(addr) => {
+                    let addr = self.parse_addr(addr)?;
+                    self.emu.add_breakpoint(addr);
+                    println!("Breakpoint at {:04X}", addr);
+                    Ok(())
+                },
+                None => {
+                    self.list_breakpoints();
+                    Ok(())
+                },
+            },
+            "break-op" | "bo" => {
+                let text = args.first().ok_or("break-op needs a pattern, like D..5")?;
+                let (mask, value) = parse_pattern(text).ok_or_else(|| format!("'{}' is not 4 characters long", text))?;
+                self.emu.add_opcode_breakpoint(mask, value);
+                println!("Breakpoint on opcode {}", format_pattern(mask, value));
+                Ok(())
+            },
+            "watch" | "w" => {
+                let target = args.first().ok_or("watch needs an address or a register")?;
+                if let Some(register) = parse_register(target) {
+                    self.emu.watch_register(register);
+                    println!("Watching {}", register_name(register));
+                    return Ok(());
+                }
+                let addr = self.parse_addr(target)? as usize;
+                let access = match args.get(1).copied() {
+                    None | Some("write") => Access::Write,
+                    Some("read") => Access::Read,
+                    Some("any") => Access::Any,
+                    Some(other) => return Err(format!("'{}' should be read, write or any", other)),
+                };
+                self.emu.add_watchpoint(addr, access);
+                println!("Watching {:04X} for {}", addr, access_name(access));
+                Ok(())
+            },
+            "delete" | "d" => {
+                let target = args.first().ok_or("delete needs an address, a pattern, a register or 'all'")?;
+                if *target == "all" {
+                    self.emu.clear_watches();
+                    return Ok(());
+                }
+                let addr = parse_hex(target).ok().and_then(|addr| u16::try_from(addr).ok());
+                let removed = match (parse_register(target), parse_pattern(target), addr) {
+                    (Some(register), _, _) => self.emu.unwatch_register(register),
+                    // Addresses can be 4 digits long too, so try them as a breakpoint or watchpoint first.
+                    (_, pattern, Some(addr)) => {
+                        let removed = self.emu.remove_breakpoint(addr) | self.emu.remove_watchpoint(addr as usize);
+                        removed || pattern.is_some_and(|(mask, value)| self.emu.remove_opcode_breakpoint(mask, value))
+                    },
+                    (_, Some((mask, value)), _) => self.emu.remove_opcode_breakpoint(mask, value),
+                    _ => false,
+                };
+                if !removed {
+                    return Err(format!("There is no breakpoint or watch on {}", target));
+                }
+                Ok(())
+            },
+            "regs" | "r" => {
+                print!("{}", run::registers_to_string(&self.emu));
+                Ok(())
+            },
+            "set" => {
+                let [reg, value] = args else {
+                    return Err("Usage: set REG VALUE".to_string());
+                };
+                set_register(&mut self.emu, reg, parse_hex(value)?)
+            },
+            "mem" | "m" => {
+                let addr = self.parse_addr(args.first().ok_or("mem needs an address")?)? as usize;
+                let len = match args.get(1) {
+                    Some(len) => parse_hex(len)? as usize,
+                    None => DEFAULT_DUMP_LEN,
+                };
+                self.dump(addr, len);
+                Ok(())
+            },
+            "poke" => {
+                let addr = self.parse_addr(args.first().ok_or("poke needs an address")?)? as usize;
+                let bytes = args[1..]
+                    .iter()
+                    .map(|byte| parse_hex(byte).and_then(|value| u8::try_from(value).map_err(|_| format!("{:X} is not a byte", value))))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                self.emu.write_memory(addr, &bytes).map_err(|err| err.to_string())
+            },
+            "list" | "l" => {
+                let addr = match args.first() {
+                    Some(addr) => self.parse_addr(addr)?,
+                    None => self.emu.cpu_state().pc,
+                };
+                self.list(addr);
+                Ok(())
+            },
+            "stack" | "bt" => {
+                let state = self.emu.cpu_state();
+                println!("#0  {:04X}", state.pc);
+                // The most recent call is at the top. Each entry is where that call returns to.
+                for (depth, &addr) in state.stack[..state.sp as usize].iter().rev().enumerate() {
+                    println!("#{}  {:04X}  (called from {:04X})", depth + 1, addr, addr.wrapping_sub(2));
+                }
+                Ok(())
+            },
+            "key" => {
+                let [key, state] = args else {
+                    return Err("Usage: key KEY down|up".to_string());
+                };
+                let key = parse_hex(key)? as usize;
+                if key >= NUM_KEYS {
+                    return Err(format!("There is no key {:X}", key));
+                }
+                self.keys[key] = match *state {
+                    "down" => true,
+                    "up" => false,
+                    _ => return Err("Keys can be 'down' or 'up'".to_string()),
+                };
+                self.emu.keypress(key, self.keys[key]);
+                Ok(())
+            },
+            "screen" => {
+                print!("{}", run::screen_to_ascii(&self.emu));
+                Ok(())
+            },
+            "reset" => {
+                self.emu.reset();
+                self.clock.reset();
+                for (key, &down) in self.keys.iter().enumerate() {
+                    self.emu.keypress(key, down);
+                }
+                println!("{}", self.current_line());
+                Ok(())
+            },
+            "help" | "h" => {
+                println!("{}", HELP);
+                Ok(())
+            },
+            other => Err(format!("Unknown command '{}'. Type 'help' for a list of commands.", other)),
+        }
+    }
+
+    fn step(&mut self, count: u32) -> Result<(), String> {
+        for _ in 0..count {
+            if self.emu.has_exited() {
+                return Err("The program has exited".to_string());
+            }
+            if self.is_waiting_for_key() {
+                return Err("The program is waiting for a key. Press one with 'key KEY down'.".to_string());
+            }
+            match self.clock.step(&mut self.emu).map_err(crashed)? {
+                StopReason::TickLimit => {},
+                reason => return Err(describe_stop(reason)),
+            }
+        }
+        Ok(())
+    }
+
+    // Runs until the core stops at a breakpoint or watch, the program stops itself or the frames run out.
+    fn cont(&mut self, frames: u32) -> Result<(), String> {
+        // Run the current instruction first, so we don't stop on the breakpoint we are continuing from.
+        self.step(1)?;
+
+        let mut frames_run = 0;
+        while frames_run < frames {
+            let (ticks, reason) = self.emu.run_until(self.clock.remaining()).map_err(crashed)?;
+            if self.clock.advance(&mut self.emu, ticks) {
+                frames_run += 1;
+            }
+            match reason {
+                StopReason::TickLimit => {},
+                StopReason::Exited => return Err("The program has exited".to_string()),
+                reason => {
+                    println!("{}", describe_stop(reason));
+                    return Ok(());
+                },
+            }
+
+            if self.is_waiting_for_key() {
+                return Err("The program is waiting for a key. Press one with 'key KEY down'.".to_string());
+            }
+            if self.is_spinning() {
+                return Err("The program is stuck on a jump to itself".to_string());
+            }
+        }
+        println!("Ran for {} frames", frames);
+        Ok(())
+    }
+
+    // Parses an address, which has to be somewhere in RAM.
+    fn parse_addr(&self, text: &str) -> Result<u16, String> {
+        let addr = parse_hex(text)?;
+        let ram_len = self.emu.memory().len();
+        match u16::try_from(addr) {
+            Ok(addr) if (addr as usize) < ram_len => Ok(addr),
+            _ => Err(format!("{:X} is outside of memory, which ends at {:X}", addr, ram_len - 1)),
+        }
+    }
+
+    fn list_breakpoints(&self) {
+        for addr in self.emu.breakpoints() {
+            println!("  break  {:04X}", addr);
+        }
+        for &(mask, value) in self.emu.opcode_breakpoints() {
+            println!("  break  opcode {}", format_pattern(mask, value));
+        }
+        for (addr, &access) in self.emu.watchpoints() {
+            println!("  watch  {:04X} for {}", addr, access_name(access));
+        }
+        for &register in self.emu.watched_registers() {
+            println!("  watch  {}", register_name(register));
+        }
+    }
+
+    fn is_waiting_for_key(&self) -> bool {
+        !self.keys.contains(&true) && matches!(self.emu.next_op(), Some(Op::WaitKey(_)))
+    }
+
+    fn is_spinning(&self) -> bool {
+        let pc = self.emu.cpu_state().pc;
+        matches!(self.emu.next_op(), Some(Op::Jump(target)) if target == pc)
+    }
+
+    fn dump(&self, addr: usize, len: usize) {
+        let memory = self.emu.memory();
+        let end = (addr + len).min(memory.len());
+        for start in (addr..end).step_by(DUMP_BYTES_PER_LINE) {
+            let line_end = (start + DUMP_BYTES_PER_LINE).min(end);
+            let bytes: Vec<String> = memory[start..line_end].iter().map(|byte| format!("{:02X}", byte)).collect();
+            println!("{:04X}  {}", start, bytes.join(" "));
+        }
+    }
+
+    fn list(&self, addr: u16) {
+        let pc = self.emu.cpu_state().pc;
+        let memory = self.emu.memory();
+        // pc can be anywhere, so keep to the end of RAM.
+        let start = (addr.saturating_sub(LIST_BYTES_BEFORE) as usize).min(memory.len());
+        let end = (start + LIST_LINES * 4).min(memory.len());
+        let lines = disasm::disassemble(&memory[start..end], start as u16, Syntax::Cowgod, self.emu.quirks().xo_chip);
+        for line in lines.iter().take(LIST_LINES) {
+            let marker = if line.addr == pc { "=>" } else { "  " };
+            let breakpoint = if self.emu.breakpoints().contains(&line.addr) { "*" } else { " " };
+            println!("{}{} {:04X}  {}", marker, breakpoint, line.addr, line.text);
+        }
+    }
+
+    // The instruction at pc, which is the next one to run.
+    fn current_line(&self) -> String {
+        let pc = self.emu.cpu_state().pc;
+        match self.emu.next_op() {
+            Some(op) => format!("=> {:04X}  {}", pc, disasm::format_op(&op, Syntax::Cowgod)),
+            None => format!("=> {:04X}  (not an instruction)", pc),
+        }
+    }
+}
+
+// Changes V0-VF, I, PC, DT or ST.
+pub fn set_register(emu: &mut Emu, reg: &str, value: u32) -> Result<(), String> {
+    let mut state = emu.cpu_state();
+    let reg = reg.to_uppercase();
+    let byte = || u8::try_from(value).map_err(|_| format!("{:X} does not fit in {}", value, reg));
+    let word = || u16::try_from(value).map_err(|_| format!("{:X} does not fit in {}", value, reg));
+    match reg.as_str() {
+        "I" => state.i = word()?,
+        "PC" => state.pc = word()?,
+        "DT" => state.dt = byte()?,
+        "ST" => state.st = byte()?,
+        _ => {
+            let index = reg
+                .strip_prefix('V')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+                .ok_or_else(|| format!("There is no register {}", reg))?;
+            state.v[index] = byte()?;
+        },
+    }
+    emu.set_cpu_state(state);
+    Ok(())
+}
+
+fn crashed(err: chip8_core::EmuError) -> String {
+    format!("The program crashed: {}", err)
+}
+
+fn access_name(access: Access) -> &'static str {
+    match access {
+        Access::Read => "reads",
+        Access::Write => "writes",
+        Access::Any => "reads and writes",
+    }
+}
+
+// Explains why run_until stopped.
+fn describe_stop(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint { pc } => format!("Breakpoint at {:04X}", pc),
+        StopReason::Opcode { pc, op } => format!("Opcode {:04X} at {:04X} matches a breakpoint", op, pc),
+        StopReason::Watchpoint { pc, addr, access: Access::Read, value } => {
+            format!("{:04X} read {:02X} from {:04X}", pc, value, addr)
+        },
+        StopReason::Watchpoint { pc, addr, value, .. } => format!("{:04X} wrote {:02X} to {:04X}", pc, value, addr),
+        StopReason::RegisterChanged { pc, register, old, new } => {
+            format!("{:04X} changed {} from {:X} to {:X}", pc, register_name(register), old, new)
+        },
+        StopReason::Exited => "The program has exited".to_string(),
+        StopReason::TickLimit => "Ran out of instructions".to_string(),
+    }
+}
+
+// Parses a hex number, with or without 0x in front.
+fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex number", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::Quirks;
+
+    // A debugger on a 4 KiB CHIP-8 machine.
+    fn debugger() -> Debugger {
+        let mut emu = Emu::with_seed(Quirks::COSMAC_VIP, 0);
+        emu.load_rom(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+        Debugger::new(emu, 10)
+    }
+
+    fn run(debugger: &mut Debugger, line: &str) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        debugger.command(&words)
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = debugger();
+        assert_eq!(run(&mut debugger, "break 0x204"), Ok(()));
+        assert_eq!(run(&mut debugger, "b 2a0"), Ok(()));
+        assert_eq!(debugger.emu.breakpoints().iter().copied().collect::<Vec<_>>(), [0x204, 0x2A0]);
+
+        // Addresses past the end of RAM are refused, rather than cut down to fit.
+        assert_eq!(run(&mut debugger, "break F000"), Err("F000 is outside of memory, which ends at FFF".to_string()));
+        assert_eq!(run(&mut debugger, "break 10204"), Err("10204 is outside of memory, which ends at FFF".to_string()));
+        assert_eq!(run(&mut debugger, "break 2g0"), Err("'2g0' is not a hex number".to_string()));
+        assert_eq!(debugger.emu.breakpoints().len(), 2);
+
+        assert_eq!(run(&mut debugger, "break-op D..5"), Ok(()));
+        assert_eq!(debugger.emu.opcode_breakpoints(), [(0xF00F, 0xD005)]);
+        assert!(run(&mut debugger, "break-op D.5").is_err());
+
+        assert_eq!(run(&mut debugger, "delete 204"), Ok(()));
+        assert_eq!(run(&mut debugger, "delete D..5"), Ok(()));
+        assert!(run(&mut debugger, "delete 10204").is_err());
+        assert_eq!(debugger.emu.breakpoints().iter().copied().collect::<Vec<_>>(), [0x2A0]);
+    }
+
+    #[test]
+    fn watches() {
+        let mut debugger = debugger();
+        assert_eq!(run(&mut debugger, "watch 300"), Ok(()));
+        assert_eq!(run(&mut debugger, "watch 0x301 read"), Ok(()));
+        assert_eq!(run(&mut debugger, "w vA"), Ok(()));
+        assert_eq!(run(&mut debugger, "watch I"), Ok(()));
+        let watchpoints: Vec<(usize, Access)> = debugger.emu.watchpoints().iter().map(|(&addr, &access)| (addr, access)).collect();
+        assert_eq!(watchpoints, [(0x300, Access::Write), (0x301, Access::Read)]);
+        assert_eq!(debugger.emu.watched_registers().iter().copied().collect::<Vec<_>>(), [Register::V(0xA), Register::I]);
+
+        assert_eq!(run(&mut debugger, "watch 300 sideways"), Err("'sideways' should be read, write or any".to_string()));
+        assert_eq!(run(&mut debugger, "watch 1000"), Err("1000 is outside of memory, which ends at FFF".to_string()));
+        assert_eq!(run(&mut debugger, "watch VG"), Err("'VG' is not a hex number".to_string()));
+        assert!(run(&mut debugger, "watch").is_err());
+    }
+
+    #[test]
+    fn memory_commands() {
+        let mut debugger = debugger();
+        // Listing near the end of RAM stops there instead of running off it.
+        assert_eq!(run(&mut debugger, "list FFE"), Ok(()));
+        assert_eq!(run(&mut debugger, "list F000"), Err("F000 is outside of memory, which ends at FFF".to_string()));
+        assert_eq!(run(&mut debugger, "mem FF0 100"), Ok(()));
+
+        assert_eq!(run(&mut debugger, "poke 300 12 AB"), Ok(()));
+        assert_eq!(&debugger.emu.memory()[0x300..0x302], [0x12, 0xAB]);
+        assert_eq!(run(&mut debugger, "poke 300 100"), Err("100 is not a byte".to_string()));
+        assert!(run(&mut debugger, "poke FFF 1 2").is_err());
+        assert_eq!(debugger.emu.memory()[0xFFF], 0);
+    }
+
+    #[test]
+    fn setting_registers() {
+        let mut debugger = debugger();
+        assert_eq!(run(&mut debugger, "set v3 FF"), Ok(()));
+        assert_eq!(run(&mut debugger, "set I 0xFFFF"), Ok(()));
+        assert_eq!(run(&mut debugger, "set PC 300"), Ok(()));
+        let state = debugger.emu.cpu_state();
+        assert_eq!((state.v[3], state.i, state.pc), (0xFF, 0xFFFF, 0x300));
+
+        assert_eq!(run(&mut debugger, "set V3 100"), Err("100 does not fit in V3".to_string()));
+        assert_eq!(run(&mut debugger, "set PC 10000"), Err("10000 does not fit in PC".to_string()));
+        assert_eq!(run(&mut debugger, "set VG 1"), Err("There is no register VG".to_string()));
+        assert_eq!(run(&mut debugger, "set V3 x"), Err("'x' is not a hex number".to_string()));
+        assert_eq!(run(&mut debugger, "set V3"), Err("Usage: set REG VALUE".to_string()));
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(parse_pattern("00E0"), Some((0xFFFF, 0x00E0)));
+        assert_eq!(parse_pattern("d..5"), Some((0xF00F, 0xD005)));
+        assert_eq!(parse_pattern("D5"), None);
+        assert_eq!(format_pattern(0xF00F, 0xD005), "D..5");
+    }
+}
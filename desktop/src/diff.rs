@@ -0,0 +1,535 @@
+// Runs a ROM alongside a trace recorded by another emulator, and reports the first instruction where
+// the two disagree. Quirk bugs show up as a register that one emulator sets and the other doesn't.
+//
+// A reference trace has one entry per instruction, in the order they ran, describing the machine
+// just after the instruction ran. Every field is optional, and only the ones that are there are checked:
+//   pc       The address of the instruction (so where it ran from, not where pc went next).
+//   opcode   The first two bytes of the instruction.
+//   v0-vf    The V registers.
+//   i        The I register.
+//   sp       How many return addresses are on the stack.
+//   dt, st   The timers. They tick after the last instruction of each frame, so these are from before that.
+// Field names are case-insensitive, and anything else is ignored.
+//
+// Traces can be CSV, JSON or one of our own binary traces (from `run --trace-format binary`):
+//   CSV   A header line naming the fields, then one line per instruction. Values are hex, with or
+//         without 0x. Blank lines and lines starting with # are skipped.
+//             pc,opcode,v0,v1,i
+//             0200,6005,05,00,0000
+//   JSON  An array of objects, or one object per line (JSON Lines). Values are numbers, or hex strings
+//         like the CSV ones.
+//             {"pc": 512, "opcode": "6005", "v0": 5}
+//
+// Our side runs with the same emulator options and input script, a frame being --ipf instructions.
+// Instructions that wait, FX0A for a key and DXYN for the next frame with the display wait quirk,
+// are one entry in the trace however many ticks they wait for. Each of those ticks still counts
+// towards the frame, as it does when the ROM is played.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chip8_core::disasm::{self, Syntax};
+use chip8_core::opcode::Op;
+use chip8_core::script::InputScript;
+use chip8_core::trace::{self, Tracer};
+use chip8_core::{CpuState, Emu, NUM_REGS};
+
+use crate::cli::{self, EmuOptions};
+use crate::headless::FrameClock;
+use crate::json::Json;
+
+const HISTORY: usize = 8; // How many of the instructions that led up to a difference are shown.
+const LIST_BYTES_BEFORE: usize = 8; // How far before the instruction the disassembly starts.
+const LIST_LINES: usize = 9;
+const MAX_WAIT_FRAMES: u32 = 60 * 60; // How long an instruction can wait before we give up on it.
+
+// Everything that can be set on the command line.
+struct Options {
+    rom: PathBuf,
+    trace: PathBuf,
+    script: Option<PathBuf>,
+    emu: EmuOptions,
+}
+
+pub fn usage(program: &str) -> String {
+    format!("{} diff [--input <script>] {} <ROM> <TRACE>", program, cli::EMU_OPTIONS_USAGE)
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut paths = Vec::new();
+    let mut script = None;
+    let mut emu = EmuOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if emu.parse_arg(arg, &mut iter)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--input" => script = Some(PathBuf::from(iter.next()?)),
+            _ if !arg.starts_with("--") => paths.push(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    // Random numbers have to come out the same as the reference's for CXNN to match, which a
+    // fixed seed can't promise, but it does at least make our side repeatable.
+    emu.seed = Some(emu.seed.unwrap_or(0));
+
+    let [rom, trace]: [PathBuf; 2] = paths.try_into().ok()?;
+    Some(Options { rom, trace, script, emu })
+}
+
+// Compares a ROM's run with a reference trace. Fails if they differ anywhere.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let expected = load_trace(&options.trace)?;
+    let mut emu = options.emu.create_emu(&options.rom)?;
+    let script = cli::load_script(options.script.as_deref())?;
+
+    match compare(&mut emu, options.emu.ticks_per_frame, &script, &expected) {
+        Ok(()) => {
+            println!("All {} instructions match", expected.len());
+            Ok(())
+        },
+        Err(divergence) => {
+            print!("{}", divergence);
+            Err("The traces differ".into())
+        },
+    }
+}
+
+// Where an instruction is in the trace file, counting from 1. JSON arrays and binary traces
+// are counted by entry, since they don't have one instruction per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Place {
+    Line(usize),
+    Entry(usize),
+}
+
+impl Default for Place {
+    fn default() -> Self {
+        Place::Line(0)
+    }
+}
+
+impl std::fmt::Display for Place {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Place::Line(line) => write!(f, "line {}", line),
+            Place::Entry(entry) => write!(f, "entry {}", entry),
+        }
+    }
+}
+
+// What a reference trace says about one instruction. None is for fields the trace doesn't have.
+#[derive(Debug, Default)]
+struct Expected {
+    place: Place,
+    pc: Option<u16>,
+    opcode: Option<u16>,
+    v: [Option<u8>; NUM_REGS],
+    i: Option<u16>,
+    sp: Option<u16>,
+    dt: Option<u8>,
+    st: Option<u8>,
+}
+
+impl Expected {
+    // Whether name is one of the fields above. Anything else in a trace is skipped.
+    fn is_field(name: &str) -> bool {
+        matches!(name.to_lowercase().as_str(), "pc" | "opcode" | "i" | "sp" | "dt" | "st") || v_index(name).is_some()
+    }
+
+    fn set(&mut self, name: &str, value: u32) -> Result<(), String> {
+        let name = name.to_lowercase();
+        let too_big = || format!("{:#X} is too big for {}", value, name);
+        let byte = || u8::try_from(value).map_err(|_| too_big());
+        let word = || u16::try_from(value).map_err(|_| too_big());
+        match name.as_str() {
+            "pc" => self.pc = Some(word()?),
+            "opcode" => self.opcode = Some(word()?),
+            "i" => self.i = Some(word()?),
+            "sp" => self.sp = Some(word()?),
+            "dt" => self.dt = Some(byte()?),
+            "st" => self.st = Some(byte()?),
+            _ => {
+                if let Some(x) = v_index(&name) {
+                    self.v[x] = Some(byte()?);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // Lists every field that state disagrees with, as "name: expected X, got Y".
+    fn differences(&self, state: &CpuState) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut check = |name: String, expected: Option<u16>, actual: u16, digits: usize| {
+            if expected.is_some_and(|expected| expected != actual) {
+                differences.push(format!(
+                    "{}: expected {:0digits$X}, got {:0digits$X}",
+                    name,
+                    expected.unwrap_or(0),
+                    actual,
+                    digits = digits
+                ));
+            }
+        };
+        for (x, (&expected, &actual)) in self.v.iter().zip(&state.v).enumerate() {
+            check(format!("V{:X}", x), expected.map(u16::from), actual as u16, 2);
+        }
+        check("I".to_string(), self.i, state.i, 4);
+        check("SP".to_string(), self.sp, state.sp, 1);
+        check("DT".to_string(), self.dt.map(u16::from), state.dt as u16, 2);
+        check("ST".to_string(), self.st.map(u16::from), state.st as u16, 2);
+        differences
+    }
+}
+
+// Where the two emulators first disagreed, and what led up to it.
+struct Divergence {
+    index: usize, // Which instruction, counting from 0.
+    frame: u32,
+    pc: u16,
+    place: Place,
+    problems: Vec<String>,
+    history: Vec<String>, // The instructions we ran before it, as trace lines.
+    listing: Vec<String>, // Disassembly around pc.
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Instruction {} (frame {}, at {:04X}) differs from {} of the trace:",
+            self.index, self.frame, self.pc, self.place
+        )?;
+        for problem in &self.problems {
+            writeln!(f, "  {}", problem)?;
+        }
+        if !self.history.is_empty() {
+            writeln!(f, "Our last instructions:")?;
+            for line in &self.history {
+                writeln!(f, "  {}", line)?;
+            }
+        }
+        writeln!(f, "Code around {:04X}:", self.pc)?;
+        for line in &self.listing {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// Runs the emulator one instruction at a time, checking each against the trace.
+fn compare(emu: &mut Emu, ticks_per_frame: u32, script: &InputScript, expected: &[Expected]) -> Result<(), Divergence> {
+    let mut clock = FrameClock::new(ticks_per_frame);
+    let mut frame = 0;
+    // The tracer remembers the last few instructions, to show what led up to a difference.
+    emu.set_tracer(Tracer::to_buffer(HISTORY));
+
+    for (index, expected) in expected.iter().enumerate() {
+        if clock.at_frame_start() {
+            script.apply(emu, frame);
+        }
+        let pc = emu.cpu_state().pc;
+        let opcode = emu.memory().get(pc as usize..pc as usize + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+
+        let mut problems = Vec::new();
+        if expected.pc.is_some_and(|expected| expected != pc) {
+            problems.push(format!("pc: expected {:04X}, got {:04X}", expected.pc.unwrap_or(0), pc));
+        } else if expected.opcode.is_some_and(|expected| Some(expected) != opcode) {
+            problems.push(format!("opcode: expected {:04X}, got {:04X}", expected.opcode.unwrap_or(0), opcode.unwrap_or(0)));
+        } else if emu.has_exited() {
+            problems.push("the program has already exited".to_string());
+        } else if let Err(problem) = run_instruction(emu, &mut clock, &mut frame, script) {
+            problems.push(problem);
+        } else {
+            problems = expected.differences(&emu.cpu_state());
+        }
+
+        if !problems.is_empty() {
+            let history = emu.take_tracer().map(|tracer| tracer.entries().map(|entry| entry.to_string()).collect());
+            return Err(Divergence {
+                index,
+                frame,
+                pc,
+                place: expected.place,
+                problems,
+                history: history.unwrap_or_default(),
+                listing: list(emu, pc),
+            });
+        }
+        if clock.advance(emu, 1) {
+            frame += 1;
+        }
+    }
+
+    emu.take_tracer();
+    Ok(())
+}
+
+// Runs the instruction at pc. One that waits is tried again on the ticks after, with the frames
+// going by as they would, until it goes through.
+fn run_instruction(emu: &mut Emu, clock: &mut FrameClock, frame: &mut u32, script: &InputScript) -> Result<(), String> {
+    let pc = emu.cpu_state().pc;
+    let waits = match emu.next_op() {
+        Some(Op::WaitKey(_)) => true,
+        Some(Op::Draw(..)) => emu.quirks().display_wait,
+        _ => false,
+    };
+    let started = *frame;
+    loop {
+        emu.tick().map_err(|err| format!("the instruction crashed: {}", err))?;
+        if !waits || emu.cpu_state().pc != pc {
+            return Ok(());
+        }
+        if *frame - started >= MAX_WAIT_FRAMES {
+            return Err(format!("the instruction was still waiting after {} frames", MAX_WAIT_FRAMES));
+        }
+        if clock.advance(emu, 1) {
+            *frame += 1;
+            script.apply(emu, *frame);
+        }
+    }
+}
+
+// Disassembles the code around addr, marking the line at addr.
+fn list(emu: &Emu, addr: u16) -> Vec<String> {
+    let memory = emu.memory();
+    let start = (addr as usize).saturating_sub(LIST_BYTES_BEFORE).min(memory.len());
+    let end = (start + LIST_LINES * 4).min(memory.len());
+    disasm::disassemble(&memory[start..end], start as u16, Syntax::Cowgod, emu.quirks().xo_chip)
+        .iter()
+        .take(LIST_LINES)
+        .map(|line| format!("{} {:04X}  {}", if line.addr == addr { "=>" } else { "  " }, line.addr, line.text))
+        .collect()
+}
+
+// Reads a reference trace, working out its format from how it starts.
+fn load_trace(path: &Path) -> Result<Vec<Expected>, Box<dyn std::error::Error>> {
+    let data = fs::read(path)?;
+    if data.starts_with(b"C8TR") {
+        return Ok(trace::read_binary(&mut data.as_slice())?
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let mut expected = Expected {
+                    place: Place::Entry(index + 1),
+                    pc: Some(entry.pc),
+                    opcode: Some(entry.opcode),
+                    i: Some(entry.after.i),
+                    ..Expected::default()
+                };
+                for (x, &value) in entry.after.v.iter().enumerate() {
+                    expected.v[x] = Some(value);
+                }
+                expected
+            })
+            .collect());
+    }
+
+    let text = String::from_utf8(data).map_err(|_| format!("{} is not a text or binary trace", path.display()))?;
+    let result = match text.trim_start().chars().next() {
+        Some('[') | Some('{') => parse_json(&text),
+        _ => parse_csv(&text),
+    };
+    result.map_err(|message| format!("{}: {}", path.display(), message).into())
+}
+
+// Parses a CSV trace. Errors say which line they are on.
+fn parse_csv(text: &str) -> Result<Vec<Expected>, String> {
+    let mut columns: Option<Vec<String>> = None;
+    let mut entries = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split(',').map(str::trim);
+        let Some(columns) = &columns else {
+            columns = Some(fields.map(str::to_string).collect());
+            continue;
+        };
+
+        let place = Place::Line(idx + 1);
+        let mut expected = Expected { place, ..Expected::default() };
+        for (name, field) in columns.iter().zip(fields) {
+            if field.is_empty() || !Expected::is_field(name) {
+                continue;
+            }
+            let value = parse_hex(field).ok_or_else(|| format!("{}: '{}' is not a hex number", place, field))?;
+            expected.set(name, value).map_err(|message| format!("{}: {}", place, message))?;
+        }
+        entries.push(expected);
+    }
+    Ok(entries)
+}
+
+// Parses a JSON trace, which is either one array or one object per line.
+// Errors say which entry of the array, or which line, they are on.
+fn parse_json(text: &str) -> Result<Vec<Expected>, String> {
+    let objects: Vec<(Place, Json)> = if text.trim_start().starts_with('[') {
+        let array = Json::parse(text)?;
+        array.as_array().iter().cloned().enumerate().map(|(index, object)| (Place::Entry(index + 1), object)).collect()
+    } else {
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(idx, line)| {
+                let place = Place::Line(idx + 1);
+                Json::parse(line).map(|object| (place, object)).map_err(|message| format!("{}: {}", place, message))
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    objects
+        .into_iter()
+        .map(|(place, object)| {
+            let Json::Object(fields) = object else {
+                return Err(format!("{}: expected an object", place));
+            };
+            let mut expected = Expected { place, ..Expected::default() };
+            for (name, value) in fields.iter().filter(|(name, _)| Expected::is_field(name)) {
+                let number = match value {
+                    Json::Number(_) => value.as_u64().and_then(|n| u32::try_from(n).ok()),
+                    Json::String(text) => parse_hex(text),
+                    _ => None,
+                };
+                let number = number.ok_or_else(|| format!("{}: {} is not a number", place, name))?;
+                expected.set(name, number).map_err(|message| format!("{}: {}", place, message))?;
+            }
+            Ok(expected)
+        })
+        .collect()
+}
+
+// Which V register a field like v3 or VA is for.
+fn v_index(name: &str) -> Option<usize> {
+    let digit = name.strip_prefix(['v', 'V']).filter(|digit| digit.len() == 1)?;
+    usize::from_str_radix(digit, 16).ok()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8_core::Quirks;
+
+    use super::*;
+
+    fn emu_with(quirks: Quirks, program: &[u8]) -> Emu {
+        let mut emu = Emu::with_seed(quirks, 0);
+        emu.load_rom(program).unwrap();
+        emu
+    }
+
+    // The pc and V0 of each entry, which is all most of these tests look at.
+    fn pcs_and_v0(entries: &[Expected]) -> Vec<(Place, Option<u16>, Option<u8>)> {
+        entries.iter().map(|entry| (entry.place, entry.pc, entry.v[0])).collect()
+    }
+
+    #[test]
+    fn parses_csv() {
+        let text = "# From another emulator.\nPC, opcode, V0, cycles, vF\n0200,6005,05,1,00\n\n0x202,0x7001,,2,\n";
+        let entries = parse_csv(text).unwrap();
+        assert_eq!(pcs_and_v0(&entries), [(Place::Line(3), Some(0x200), Some(5)), (Place::Line(5), Some(0x202), None)]);
+        assert_eq!(entries[0].opcode, Some(0x6005));
+        assert_eq!(entries[0].v[0xF], Some(0));
+        assert_eq!(entries[1].v[0xF], None);
+    }
+
+    #[test]
+    fn csv_errors_say_which_line() {
+        assert_eq!(parse_csv("pc,v0\n200,05\n202,zz\n").unwrap_err(), "line 3: 'zz' is not a hex number");
+        assert_eq!(parse_csv("pc,v0\n\n200,100\n").unwrap_err(), "line 3: 0x100 is too big for v0");
+    }
+
+    #[test]
+    fn parses_json_arrays() {
+        let text = r#"[{"pc": 512, "opcode": "6005", "v0": 5, "note": "hi"},
+                       {"PC": "0x202", "I": 768}]"#;
+        let entries = parse_json(text).unwrap();
+        assert_eq!(pcs_and_v0(&entries), [(Place::Entry(1), Some(0x200), Some(5)), (Place::Entry(2), Some(0x202), None)]);
+        assert_eq!(entries[0].opcode, Some(0x6005));
+        assert_eq!(entries[1].i, Some(0x300));
+    }
+
+    #[test]
+    fn parses_json_lines() {
+        let text = "{\"pc\": 512, \"v0\": 5}\n\n{\"pc\": 514}\n";
+        let entries = parse_json(text).unwrap();
+        assert_eq!(pcs_and_v0(&entries), [(Place::Line(1), Some(0x200), Some(5)), (Place::Line(3), Some(0x202), None)]);
+    }
+
+    #[test]
+    fn json_errors_say_which_entry_or_line() {
+        // The second entry is on the first line, so it has to be counted by entry.
+        assert_eq!(parse_json(r#"[{"pc": 512}, 7]"#).unwrap_err(), "entry 2: expected an object");
+        assert_eq!(parse_json("[{\"pc\": 512},\n {\"v0\": 256}]").unwrap_err(), "entry 2: 0x100 is too big for v0");
+        assert_eq!(parse_json("{\"pc\": 512}\n\n{\"v0\": true}").unwrap_err(), "line 3: v0 is not a number");
+        assert_eq!(parse_json("{\"pc\": 512}\n{\"pc\"").unwrap_err(), "line 2: invalid JSON at byte 5: expected ':'");
+        assert!(parse_json("[{\"pc\": 512}").unwrap_err().starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn matching_traces() {
+        // v0 := 5, v1 := 7, v0 += v1, then spin.
+        let mut emu = emu_with(Quirks::CHIP_48, &[0x60, 0x05, 0x61, 0x07, 0x80, 0x14, 0x12, 0x06]);
+        let trace = parse_csv("pc,opcode,v0,v1,vf\n200,6005,05,00,00\n202,6107,05,07,00\n204,8014,0C,07,00\n206,1206,0C,07,00\n206,,0C,,\n");
+        assert!(compare(&mut emu, 10, &InputScript::default(), &trace.unwrap()).is_ok());
+    }
+
+    #[test]
+    fn reports_the_first_difference() {
+        // The reference thinks 8014 leaves vF alone, and then goes somewhere else.
+        let mut emu = emu_with(Quirks::CHIP_48, &[0x60, 0xFF, 0x61, 0x07, 0x80, 0x14, 0x12, 0x06]);
+        let trace = parse_json(
+            r#"[{"pc": 512, "v0": 255}, {"pc": 514, "v1": 7}, {"pc": 516, "v0": 6, "vf": 0}, {"pc": 600}]"#,
+        );
+        let divergence = compare(&mut emu, 10, &InputScript::default(), &trace.unwrap()).unwrap_err();
+        assert_eq!((divergence.index, divergence.frame, divergence.pc), (2, 0, 0x204));
+        assert_eq!(divergence.place, Place::Entry(3));
+        assert_eq!(divergence.problems, ["VF: expected 00, got 01"]);
+        assert_eq!(divergence.history.len(), 3);
+
+        let report = divergence.to_string();
+        assert!(report.starts_with("Instruction 2 (frame 0, at 0204) differs from entry 3 of the trace:\n  VF: expected 00, got 01\n"));
+        assert!(report.contains("=> 0204  ADD V0, V1\n"), "{}", report);
+    }
+
+    #[test]
+    fn reports_where_the_program_went_instead() {
+        let mut emu = emu_with(Quirks::CHIP_48, &[0x12, 0x04, 0x00, 0x00, 0x12, 0x04]);
+        let trace = parse_csv("pc\n200\n202\n").unwrap();
+        let divergence = compare(&mut emu, 10, &InputScript::default(), &trace).unwrap_err();
+        assert_eq!(divergence.problems, ["pc: expected 0202, got 0204"]);
+        assert_eq!(divergence.place, Place::Line(3));
+    }
+
+    #[test]
+    fn waiting_for_a_key_is_one_instruction() {
+        // v0 := key, then v1 := 1. The key goes down in frame 3.
+        let mut emu = emu_with(Quirks::CHIP_48, &[0xF0, 0x0A, 0x61, 0x01]);
+        let script = InputScript::parse("3 +7").unwrap();
+        let trace = parse_csv("pc,v0,v1\n200,07,00\n202,07,01\n").unwrap();
+        assert!(compare(&mut emu, 10, &script, &trace).is_ok());
+
+        // Without the key, it gives up in the end.
+        let mut emu = emu_with(Quirks::CHIP_48, &[0xF0, 0x0A, 0x61, 0x01]);
+        let divergence = compare(&mut emu, 10, &InputScript::default(), &trace).unwrap_err();
+        assert_eq!(divergence.problems, ["the instruction was still waiting after 3600 frames"]);
+    }
+
+    #[test]
+    fn waiting_for_the_display_is_one_instruction() {
+        // Two draws in a row, where the second has to wait for the next frame.
+        let program = [0xA0, 0x00, 0xD0, 0x15, 0xD0, 0x15, 0x60, 0x01];
+        let trace = parse_csv("pc,vf\n200,\n202,00\n204,01\n206,01\n").unwrap();
+        let mut emu = emu_with(Quirks::COSMAC_VIP, &program);
+        assert!(compare(&mut emu, 10, &InputScript::default(), &trace).is_ok());
+    }
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chip8_core::disasm::{self, Syntax};
+use chip8_core::flow;
+use chip8_core::octo::Target;
+use chip8_core::START_ADDR;
+
+use crate::asm;
+
+// Everything that can be set on the command line.
+struct Options {
+    rom: PathBuf,
+    syntax: Syntax,
+    flow: bool, // Follow the program's control flow, to separate code from data.
+    target: Target, // Only XO-CHIP has the 4 byte F000 NNNN, everywhere else F000 is data.
+}
+
+pub fn usage(program: &str) -> String {
+    format!("{} disasm [--syntax cowgod|octo] [--flow] [--target chip8|schip|xochip] <ROM>", program)
+}
+
+pub fn parse_syntax(name: &str) -> Option<Syntax> {
+    match name {
+        "cowgod" => Some(Syntax::Cowgod),
+        "octo" => Some(Syntax::Octo),
+        _ => None,
+    }
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut rom = None;
+    let mut syntax = Syntax::Cowgod;
+    let mut flow = false;
+    let mut target = Target::XoChip;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--syntax" => syntax = parse_syntax(iter.next()?)?,
+            "--flow" => flow = true,
+            "--target" => target = asm::parse_target(iter.next()?)?,
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    Some(Options { rom: rom?, syntax, flow, target })
+}
+
+// Prints every instruction in a ROM with its address and raw bytes.
+// With --flow, prints labelled source that can be assembled again instead.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let data = fs::read(&options.rom)?;
+    let xo_chip = options.target == Target::XoChip;
+
+    if options.flow {
+        print!("{}", flow::listing(&data, START_ADDR, options.syntax, xo_chip)?);
+        return Ok(());
+    }
+
+    for line in disasm::disassemble(&data, START_ADDR, options.syntax, xo_chip) {
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        println!("{:04X}  {:<11}  {}", line.addr, bytes.join(" "), line.text);
+    }
+    Ok(())
+}
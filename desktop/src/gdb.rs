@@ -0,0 +1,737 @@
+// A stub that lets GDB, or anything else that speaks its remote serial protocol, debug a ROM over TCP.
+//
+// GDB has no CHIP-8 architecture built in, so the register layout is described in target.xml,
+// which it asks for with qXfer. The registers are pc, i, sp, v0 to vf, dt and st, each one sent
+// big-endian like CHIP-8 stores words in memory. Memory is the emulator's RAM, starting at address 0.
+//
+// Only one connection is served. The stub exits when the debugger kills the program, detaches or goes away.
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip8_core::debug::{Access, StopReason};
+use chip8_core::{CpuState, Emu, EmuError, NUM_KEYS, NUM_REGS};
+
+use crate::cli::{self, EmuOptions};
+use crate::headless::FrameClock;
+use crate::run;
+
+const DEFAULT_PORT: u16 = 1234;
+const FRAME_TIME: Duration = Duration::from_micros(16_667); // 60 frames per second, like play.
+const PACKET_SIZE: usize = 0x1000; // The longest packet we tell the debugger it can send.
+const INTERRUPT: u8 = 0x03; // Sent on its own, outside of a packet, when the user presses Ctrl-C.
+
+// GDB's view of the registers, in the order it numbers them, with their sizes in bytes.
+const REGISTERS: [(&str, usize); 21] = [
+    ("pc", 2), ("i", 2), ("sp", 1),
+    ("v0", 1), ("v1", 1), ("v2", 1), ("v3", 1), ("v4", 1), ("v5", 1), ("v6", 1), ("v7", 1),
+    ("v8", 1), ("v9", 1), ("va", 1), ("vb", 1), ("vc", 1), ("vd", 1), ("ve", 1), ("vf", 1),
+    ("dt", 1), ("st", 1),
+];
+
+const MONITOR_HELP: &str = "\
+monitor key KEY down|up   Hold or release one of the hex keys
+monitor screen            Draw the screen
+monitor reset             Start the ROM again
+";
+
+// Signals that stop replies are made of.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// Everything that can be set on the command line.
+struct Options {
+    rom: PathBuf,
+    port: u16,
+    emu: EmuOptions,
+}
+
+pub fn usage(program: &str) -> String {
+    format!("{} gdb [--port <number>] {} <ROM>", program, cli::EMU_OPTIONS_USAGE)
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut rom = None;
+    let mut port = DEFAULT_PORT;
+    let mut emu = EmuOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if emu.parse_arg(arg, &mut iter)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--port" => port = iter.next()?.parse().ok()?,
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    Some(Options { rom: rom?, port, emu })
+}
+
+// Loads a ROM and waits on a local port for a debugger to connect to it.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let emu = options.emu.create_emu(&options.rom)?;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, options.port))?;
+    eprintln!("Waiting for a debugger on {}", listener.local_addr()?);
+    let (stream, addr) = listener.accept()?;
+    eprintln!("Debugger connected from {}", addr);
+
+    stream.set_nodelay(true)?;
+    let mut connection = Connection::new(Box::new(stream));
+    let mut stub = Stub::new(emu, options.emu.ticks_per_frame);
+    match stub.serve(&mut connection) {
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => eprintln!("The debugger went away"),
+        result => result?,
+    }
+    Ok(())
+}
+
+// What a Connection talks over: the debugger's TCP connection, or a scripted client in the tests.
+trait Stream: Read + Write {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+// One end of a remote serial protocol conversation. Packets look like $data#checksum, and each one is
+// acknowledged with + (or - to ask for it again) until the debugger switches that off with QStartNoAckMode.
+struct Connection {
+    stream: Box<dyn Stream>,
+    input: VecDeque<u8>, // Bytes that have been received but not handled yet.
+    acks: bool,
+}
+
+impl Connection {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Self { stream, input: VecDeque::new(), acks: true }
+    }
+
+    // Waits for the next packet and returns what is inside it, acknowledging it if acks are on.
+    fn read_packet(&mut self) -> io::Result<String> {
+        loop {
+            // Skip anything between packets, like stray acks and interrupts that came in while stopped.
+            while self.read_byte()? != b'$' {}
+
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                let byte = self.read_byte()?;
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                data.push(byte);
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let valid = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) == Some(sum);
+
+            if self.acks {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid || !self.acks {
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+        }
+    }
+
+    // Sends a packet, sending it again for as long as the debugger asks.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data.as_bytes() {
+            // These mean something in the framing, so they are escaped with } and XORed with 0x20.
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.extend([b'}', byte ^ 0x20]);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let sum = packet[1..].iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        packet.extend(format!("#{:02x}", sum).as_bytes());
+
+        loop {
+            self.stream.write_all(&packet)?;
+            if !self.acks {
+                return Ok(());
+            }
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    // Sends text for the debugger to print, as an O packet.
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        self.send(&format!("O{}", to_hex(text.as_bytes())))
+    }
+
+    // Checks, without waiting, whether the debugger has asked for the running program to stop.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let result = self.fill();
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {},
+            result => result?,
+        }
+
+        let interrupted = self.input.contains(&INTERRUPT);
+        self.input.retain(|&byte| byte != INTERRUPT);
+        Ok(interrupted)
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        loop {
+            if let Some(byte) = self.input.pop_front() {
+                return Ok(byte);
+            }
+            self.fill()?;
+        }
+    }
+
+    // Reads whatever has arrived into the input buffer.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut buffer = [0; 1024];
+        let len = self.stream.read(&mut buffer)?;
+        if len == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        self.input.extend(&buffer[..len]);
+        Ok(())
+    }
+}
+
+struct Stub {
+    emu: Emu,
+    clock: FrameClock,
+    keys: [bool; NUM_KEYS], // Which keys have been held down with monitor key.
+    last_stop: String, // The reply to ?, which asks why the program last stopped.
+}
+
+impl Stub {
+    fn new(emu: Emu, ticks_per_frame: u32) -> Self {
+        Self {
+            emu,
+            clock: FrameClock::new(ticks_per_frame),
+            keys: [false; NUM_KEYS],
+            last_stop: format!("S{:02x}", SIGTRAP),
+        }
+    }
+
+    // Answers packets until the debugger kills the program or detaches.
+    fn serve(&mut self, connection: &mut Connection) -> io::Result<()> {
+        loop {
+            let packet = connection.read_packet()?;
+            match packet.as_str() {
+                "k" => return Ok(()),
+                "D" | "D;1" => return connection.send("OK"),
+                _ => {},
+            }
+            let reply = self.handle(&packet, connection)?;
+            connection.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                connection.acks = false;
+            }
+        }
+    }
+
+    // Works out the reply to a packet. Packets we don't know get an empty reply, as the protocol asks.
+    fn handle(&mut self, packet: &str, connection: &mut Connection) -> io::Result<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+            "g" => to_hex(&register_bytes(&self.emu.cpu_state())),
+            "G" => match from_hex(args).filter(|bytes| bytes.len() == register_size()) {
+                Some(bytes) => {
+                    self.emu.set_cpu_state(state_from_bytes(self.emu.cpu_state(), &bytes));
+                    "OK".to_string()
+                },
+                None => error(),
+            },
+            "p" => match parse_hex(args).and_then(register_range) {
+                Some(range) => to_hex(&register_bytes(&self.emu.cpu_state())[range]),
+                None => error(),
+            },
+            "P" => self.write_register(args).unwrap_or_else(error),
+            "m" => self.read_memory(args).unwrap_or_else(error),
+            "M" => self.write_memory(args).unwrap_or_else(error),
+            "c" | "s" => self.resume(command == "s", args, connection)?,
+            "Z" | "z" => self.change_breakpoint(command == "Z", args).unwrap_or_else(error),
+            "H" | "T" => "OK".to_string(), // There is only one thread, so it is always the one picked, and alive.
+            "q" | "Q" | "v" => self.query(packet, connection)?,
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    // Handles the longer q, Q and v packets.
+    fn query(&mut self, packet: &str, connection: &mut Connection) -> io::Result<String> {
+        let (name, args) = packet.split_once([':', ',', ';']).unwrap_or((packet, ""));
+        let reply = match name {
+            "qSupported" => format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE),
+            "QStartNoAckMode" => "OK".to_string(),
+            "qAttached" => "1".to_string(), // The program was already running, so leave it be when the debugger quits.
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qXfer" => read_features(args).unwrap_or_else(error),
+            "qRcmd" => match from_hex(args) {
+                Some(command) => self.monitor(&String::from_utf8_lossy(&command), connection)?,
+                None => error(),
+            },
+            "vCont?" => "vCont;c;s".to_string(),
+            "vCont" => {
+                // Every action applies to our only thread, so the first one decides.
+                let action = args.split(';').next().unwrap_or("");
+                match action.split(':').next() {
+                    Some("c") => self.resume(false, "", connection)?,
+                    Some("s") => self.resume(true, "", connection)?,
+                    _ => String::new(),
+                }
+            },
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    // Steps one instruction, or continues until something stops the program, then says why it stopped.
+    // args can hold an address to resume from.
+    fn resume(&mut self, single_step: bool, args: &str, connection: &mut Connection) -> io::Result<String> {
+        if !args.is_empty() {
+            let Some(addr) = parse_hex(args) else {
+                return Ok(error());
+            };
+            let mut state = self.emu.cpu_state();
+            state.pc = addr as u16;
+            self.emu.set_cpu_state(state);
+        }
+
+        let result = if single_step { self.clock.step(&mut self.emu).map(Some) } else { self.run(connection)? };
+        self.last_stop = match result {
+            Ok(Some(reason)) => self.stop_reply(reason),
+            Ok(None) => format!("S{:02x}", SIGINT),
+            Err(err) => {
+                connection.print(&format!("The program crashed: {}\n", err))?;
+                let signal = if matches!(err, EmuError::InvalidOpcode { .. }) { SIGILL } else { SIGSEGV };
+                format!("S{:02x}", signal)
+            },
+        };
+        Ok(self.last_stop.clone())
+    }
+
+    // Runs at 60 frames a second until the core stops at a breakpoint or watch, or the program exits.
+    // Returns None if the debugger interrupted it first, which is checked for between frames.
+    fn run(&mut self, connection: &mut Connection) -> io::Result<Result<Option<StopReason>, EmuError>> {
+        // Run the current instruction first, so we don't stop on the breakpoint we are continuing from.
+        match self.clock.step(&mut self.emu) {
+            Ok(StopReason::TickLimit) => {},
+            result => return Ok(result.map(Some)),
+        }
+
+        let mut frame_start = Instant::now();
+        loop {
+            let (ticks, reason) = match self.emu.run_until(self.clock.remaining()) {
+                Ok(stopped) => stopped,
+                Err(err) => return Ok(Err(err)),
+            };
+            if self.clock.advance(&mut self.emu, ticks) {
+                if let Some(remaining) = FRAME_TIME.checked_sub(frame_start.elapsed()) {
+                    thread::sleep(remaining);
+                }
+                frame_start = Instant::now();
+            }
+            if reason != StopReason::TickLimit {
+                return Ok(Ok(Some(reason)));
+            }
+            if connection.interrupted()? {
+                return Ok(Ok(None));
+            }
+        }
+    }
+
+    // The stop reply that tells the debugger why the core stopped.
+    fn stop_reply(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Watchpoint { addr, access, .. } => {
+                let kind = match (self.emu.watchpoints().get(&addr), access) {
+                    (Some(Access::Any), _) => "awatch",
+                    (_, Access::Read) => "rwatch",
+                    _ => "watch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr)
+            },
+            StopReason::Exited => "W00".to_string(),
+            // Breakpoints, and steps that finished without anything else happening.
+            _ => format!("S{:02x}", SIGTRAP),
+        }
+    }
+
+    // Handles P, which sets a single register: n=value.
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (number, value) = args.split_once('=')?;
+        let range = register_range(parse_hex(number)?)?;
+        let value = from_hex(value).filter(|value| value.len() == range.len())?;
+        let mut bytes = register_bytes(&self.emu.cpu_state());
+        bytes[range].copy_from_slice(&value);
+        self.emu.set_cpu_state(state_from_bytes(self.emu.cpu_state(), &bytes));
+        Some("OK".to_string())
+    }
+
+    // Handles m, which reads memory: addr,length. Reads running off the end of RAM are cut short.
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = args.split_once(',')?;
+        let (addr, len) = (parse_hex(addr)? as usize, parse_hex(len)? as usize);
+        let memory = self.emu.memory();
+        if addr >= memory.len() {
+            return None;
+        }
+        Some(to_hex(&memory[addr..(addr + len).min(memory.len())]))
+    }
+
+    // Handles M, which writes memory: addr,length:bytes.
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = range.split_once(',')?;
+        let data = from_hex(data).filter(|data| data.len() == parse_hex(len).unwrap_or(0) as usize)?;
+        self.emu.write_memory(parse_hex(addr)? as usize, &data).ok()?;
+        Some("OK".to_string())
+    }
+
+    // Handles Z and z, which insert and remove breakpoints and watchpoints: type,addr,kind.
+    // Software and hardware breakpoints are the same thing here. For watchpoints, kind is how many bytes to watch.
+    // Anything that reaches past the end of RAM is refused.
+    fn change_breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let (kind, addr, len) = (fields.next()?, parse_hex(fields.next()?)?, parse_hex(fields.next()?)?);
+        let access = match kind {
+            "0" | "1" => None,
+            "2" => Some(Access::Write),
+            "3" => Some(Access::Read),
+            "4" => Some(Access::Any),
+            _ => return Some(String::new()),
+        };
+        let end = addr.checked_add(len.max(1)).filter(|&end| end as usize <= self.emu.memory().len())?;
+        let Some(access) = access else {
+            if insert {
+                self.emu.add_breakpoint(addr as u16);
+            } else {
+                self.emu.remove_breakpoint(addr as u16);
+            }
+            return Some("OK".to_string());
+        };
+        for addr in addr as usize..end as usize {
+            if insert {
+                self.emu.add_watchpoint(addr, access);
+            } else {
+                self.emu.remove_watchpoint(addr);
+            }
+        }
+        Some("OK".to_string())
+    }
+
+    // Handles the commands typed after `monitor` in GDB. Their output is sent back in O packets.
+    fn monitor(&mut self, command: &str, connection: &mut Connection) -> io::Result<String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let output = match words.as_slice() {
+            ["key", key, state] => {
+                let key = usize::from_str_radix(key, 16).ok().filter(|&key| key < NUM_KEYS);
+                match (key, *state) {
+                    (Some(key), "down" | "up") => {
+                        self.keys[key] = *state == "down";
+                        self.emu.keypress(key, self.keys[key]);
+                        String::new()
+                    },
+                    _ => "Usage: monitor key KEY down|up\n".to_string(),
+                }
+            },
+            ["screen"] => run::screen_to_ascii(&self.emu),
+            ["reset"] => {
+                self.emu.reset();
+                self.clock.reset();
+                for (key, &down) in self.keys.iter().enumerate() {
+                    self.emu.keypress(key, down);
+                }
+                self.last_stop = format!("S{:02x}", SIGTRAP);
+                String::new()
+            },
+            _ => MONITOR_HELP.to_string(),
+        };
+        if !output.is_empty() {
+            connection.print(&output)?;
+        }
+        Ok("OK".to_string())
+    }
+}
+
+// Handles qXfer:features:read:target.xml:offset,length, which hands out the register layout in pieces.
+fn read_features(args: &str) -> Option<String> {
+    let range = args.strip_prefix("features:read:target.xml:")?;
+    let (offset, len) = range.split_once(',')?;
+    let (offset, len) = (parse_hex(offset)? as usize, parse_hex(len)? as usize);
+    let xml = target_xml();
+    let start = offset.min(xml.len());
+    let end = (start + len).min(xml.len());
+    // m means there is more to come, and l means this is the last piece.
+    let more = if end < xml.len() { 'm' } else { 'l' };
+    Some(format!("{}{}", more, &xml[start..end]))
+}
+
+// Describes the registers to GDB.
+fn target_xml() -> String {
+    let registers: String = REGISTERS
+        .iter()
+        .map(|&(name, size)| {
+            let kind = match name {
+                "pc" => " type=\"code_ptr\"",
+                "i" => " type=\"data_ptr\"",
+                _ => "",
+            };
+            format!("    <reg name=\"{}\" bitsize=\"{}\"{}/>\n", name, size * 8, kind)
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n  <feature name=\"org.chip8.core\">\n{}  </feature>\n</target>\n",
+        registers
+    )
+}
+
+fn register_size() -> usize {
+    REGISTERS.iter().map(|&(_, size)| size).sum()
+}
+
+// Where register number n is in the bytes of the g packet.
+fn register_range(n: u32) -> Option<std::ops::Range<usize>> {
+    let size = REGISTERS.get(n as usize)?.1;
+    let start: usize = REGISTERS[..n as usize].iter().map(|&(_, size)| size).sum();
+    Some(start..start + size)
+}
+
+// Lays the registers out in the order of REGISTERS.
+fn register_bytes(state: &CpuState) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(register_size());
+    bytes.extend(state.pc.to_be_bytes());
+    bytes.extend(state.i.to_be_bytes());
+    bytes.push(state.sp as u8);
+    bytes.extend(state.v);
+    bytes.push(state.dt);
+    bytes.push(state.st);
+    bytes
+}
+
+// The opposite of register_bytes. The stack itself isn't a register, so it is kept from state.
+fn state_from_bytes(mut state: CpuState, bytes: &[u8]) -> CpuState {
+    state.pc = u16::from_be_bytes([bytes[0], bytes[1]]);
+    state.i = u16::from_be_bytes([bytes[2], bytes[3]]);
+    state.sp = bytes[4] as u16;
+    state.v.copy_from_slice(&bytes[5..5 + NUM_REGS]);
+    state.dt = bytes[5 + NUM_REGS];
+    state.st = bytes[6 + NUM_REGS];
+    state
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use chip8_core::Quirks;
+
+    use super::*;
+
+    // 0200 LD V0, 5; 0202 LD I, 0x300; 0204 LD [I], V0; 0206 JP 0x206
+    const PROGRAM: [u8; 8] = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+
+    // A debugger that has sent everything it is going to send up front, and keeps whatever comes back.
+    struct ScriptedClient {
+        input: VecDeque<u8>,
+        output: Rc<RefCell<Vec<u8>>>,
+        nonblocking: bool,
+    }
+
+    impl Read for ScriptedClient {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.input.is_empty() && self.nonblocking {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let len = buffer.len().min(self.input.len());
+            for (byte, input) in buffer.iter_mut().zip(self.input.drain(..len)) {
+                *byte = input;
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for ScriptedClient {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.output.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Stream for ScriptedClient {
+        fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+            self.nonblocking = nonblocking;
+            Ok(())
+        }
+    }
+
+    // A connection whose client sends input, and the bytes the stub sends back to it.
+    fn connect(input: &str) -> (Connection, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let client = ScriptedClient { input: input.bytes().collect(), output: Rc::clone(&output), nonblocking: false };
+        (Connection::new(Box::new(client)), output)
+    }
+
+    fn packet(data: &str) -> String {
+        let sum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        format!("${}#{:02x}", data, sum)
+    }
+
+    fn stub() -> Stub {
+        let mut emu = Emu::with_seed(Quirks::COSMAC_VIP, 0);
+        emu.load_rom(&PROGRAM).unwrap();
+        Stub::new(emu, 10)
+    }
+
+    // Sends one packet to the stub's handler, the way serve does, and returns the reply.
+    fn send(stub: &mut Stub, data: &str) -> String {
+        let (mut connection, _) = connect("");
+        stub.handle(data, &mut connection).unwrap()
+    }
+
+    #[test]
+    fn serve_acknowledges_packets_until_no_ack_mode() {
+        let input = [packet("?"), "+".to_string(), packet("QStartNoAckMode"), "+".to_string(), packet("qC"), packet("k")];
+        let (mut connection, output) = connect(&input.concat());
+        stub().serve(&mut connection).unwrap();
+        let expected = ["+", &packet("S05"), "+", &packet("OK"), &packet("QC1")].concat();
+        assert_eq!(String::from_utf8_lossy(&output.borrow()), expected);
+    }
+
+    #[test]
+    fn bad_checksums_are_refused() {
+        let (mut connection, output) = connect(&format!("$g#00{}", packet("g")));
+        assert_eq!(connection.read_packet().unwrap(), "g");
+        assert_eq!(output.borrow().as_slice(), b"-+");
+    }
+
+    #[test]
+    fn packets_are_sent_again_until_acknowledged() {
+        let (mut connection, output) = connect("-+");
+        connection.send("OK").unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.borrow()), packet("OK").repeat(2));
+    }
+
+    #[test]
+    fn framing_characters_are_escaped() {
+        let (mut connection, output) = connect("+");
+        connection.send("a#b}").unwrap();
+        assert!(output.borrow().starts_with(b"$a}\x03b}]#"));
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let mut stub = stub();
+        let registers = send(&mut stub, "g");
+        assert_eq!(registers, format!("0200000000{}", "00".repeat(NUM_REGS + 2)));
+
+        let changed = format!("0300123400ab{}0506", "00".repeat(NUM_REGS - 1));
+        assert_eq!(send(&mut stub, &format!("G{}", changed)), "OK");
+        let state = stub.emu.cpu_state();
+        assert_eq!((state.pc, state.i, state.v[0], state.dt, state.st), (0x300, 0x1234, 0xAB, 5, 6));
+        assert_eq!(send(&mut stub, "g"), changed);
+
+        assert_eq!(send(&mut stub, "G00"), "E01");
+        assert_eq!(send(&mut stub, "p3"), "ab");
+        assert_eq!(send(&mut stub, "p99"), "E01");
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let mut stub = stub();
+        assert_eq!(send(&mut stub, "m200,4"), "6005a300");
+        assert_eq!(send(&mut stub, "mffe,10"), "0000"); // Cut short at the end of RAM.
+        assert_eq!(send(&mut stub, "m1000,1"), "E01");
+        assert_eq!(send(&mut stub, "mzz,1"), "E01");
+
+        assert_eq!(send(&mut stub, "M300,2:abcd"), "OK");
+        assert_eq!(&stub.emu.memory()[0x300..0x302], &[0xAB, 0xCD]);
+        assert_eq!(send(&mut stub, "M300,2:ab"), "E01");
+        assert_eq!(send(&mut stub, "Mfff,2:abcd"), "E01");
+    }
+
+    #[test]
+    fn continues_to_breakpoints_and_watchpoints() {
+        let mut stub = stub();
+        assert_eq!(send(&mut stub, "Z0,204,2"), "OK");
+        assert_eq!(send(&mut stub, "c"), "S05");
+        assert_eq!(stub.emu.cpu_state().pc, 0x204);
+        assert_eq!(send(&mut stub, "z0,204,2"), "OK");
+
+        assert_eq!(send(&mut stub, "Z2,300,1"), "OK");
+        assert_eq!(send(&mut stub, "c"), "T05watch:300;");
+        assert_eq!(send(&mut stub, "?"), "T05watch:300;");
+        assert_eq!(send(&mut stub, "z2,300,1"), "OK");
+    }
+
+    #[test]
+    fn steps_one_instruction() {
+        let mut stub = stub();
+        assert_eq!(send(&mut stub, "s"), "S05");
+        assert_eq!(stub.emu.cpu_state().pc, 0x202);
+        assert_eq!(send(&mut stub, "s206"), "S05");
+        assert_eq!(stub.emu.cpu_state().pc, 0x206);
+    }
+
+    #[test]
+    fn refuses_malformed_breakpoints() {
+        let mut stub = stub();
+        assert_eq!(send(&mut stub, "Z2,ffffffff,1"), "E01");
+        assert_eq!(send(&mut stub, "Z2,0,ffffffff"), "E01");
+        assert_eq!(send(&mut stub, "Z2,fff,2"), "E01");
+        assert_eq!(send(&mut stub, "Z0,1000,2"), "E01");
+        assert_eq!(send(&mut stub, "Z0,200"), "E01");
+        assert_eq!(send(&mut stub, "Z9,200,2"), "");
+        assert!(stub.emu.watchpoints().is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use chip8_core::debug;
+use chip8_core::opcode::Op;
+use chip8_core::script::InputScript;
+use chip8_core::{Emu, EmuError};
+
+// Why a headless run came to an end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    FrameLimit, // All of the requested frames were run.
+    Exited, // The program ran 00FD.
+    Halted, // The program is stuck on a 1NNN that jumps to itself, which is how most programs finish.
+}
+
+// Runs the emulator without a screen or keyboard for up to `frames` frames, taking its input from the script.
+// on_frame is called with the frame number at the end of every frame, after the timers have ticked.
+// Stops early if the program exits, or if halt_on_spin is set and the program is jumping to itself.
+// Returns how many frames were run and why it stopped.
+pub fn run_frames(
+    emu: &mut Emu,
+    ticks_per_frame: u32,
+    frames: u32,
+    halt_on_spin: bool,
+    script: &InputScript,
+    mut on_frame: impl FnMut(&Emu, u32),
+) -> Result<(u32, StopReason), EmuError> {
+    for frame in 0..frames {
+        script.apply(emu, frame);
+        for _ in 0..ticks_per_frame {
+            emu.tick()?;
+        }
+        emu.tick_timers();
+        on_frame(emu, frame);
+
+        if emu.has_exited() {
+            return Ok((frame + 1, StopReason::Exited));
+        }
+        if halt_on_spin && is_spinning(emu) {
+            return Ok((frame + 1, StopReason::Halted));
+        }
+    }
+    Ok((frames, StopReason::FrameLimit))
+}
+
+// Keeps time for frontends that run a few instructions at a time, like the debuggers.
+// The timers tick every time a frame's worth of instructions have run.
+pub struct FrameClock {
+    ticks_per_frame: u32,
+    ticks: u32, // How many instructions have run in the current frame.
+}
+
+impl FrameClock {
+    pub fn new(ticks_per_frame: u32) -> Self {
+        Self { ticks_per_frame: ticks_per_frame.max(1), ticks: 0 }
+    }
+
+    // How many more instructions run before the frame ends.
+    pub fn remaining(&self) -> u32 {
+        self.ticks_per_frame - self.ticks
+    }
+
+    // Counts instructions that have run, ticking the timers if that finishes a frame. Returns whether it did.
+    pub fn advance(&mut self, emu: &mut Emu, ticks: u32) -> bool {
+        self.ticks += ticks;
+        if self.ticks < self.ticks_per_frame {
+            return false;
+        }
+        emu.tick_timers();
+        self.ticks = 0;
+        true
+    }
+
+    // Whether a frame has just ended, so none of the next one has run yet.
+    pub fn at_frame_start(&self) -> bool {
+        self.ticks == 0
+    }
+
+    // Starts counting from the beginning of a frame, after the emulator is reset.
+    pub fn reset(&mut self) {
+        self.ticks = 0;
+    }
+
+    // Runs a single instruction with Emu::step, so a breakpoint on it doesn't stop it but watches still do.
+    // A draw that is waiting for the next frame is run until it goes through.
+    // Returns why the core stopped, which is TickLimit if nothing was watched.
+    pub fn step(&mut self, emu: &mut Emu) -> Result<debug::StopReason, EmuError> {
+        let pc = emu.cpu_state().pc;
+        let waits_for_frame = emu.quirks().display_wait && matches!(emu.next_op(), Some(Op::Draw(..)));
+        loop {
+            let result = emu.step();
+            self.advance(emu, 1);
+            let reason = result?;
+            if reason != debug::StopReason::TickLimit || !waits_for_frame || emu.cpu_state().pc != pc {
+                return Ok(reason);
+            }
+        }
+    }
+}
+
+// Whether the next instruction is a jump to itself, which would loop forever.
+fn is_spinning(emu: &Emu) -> bool {
+    let pc = emu.cpu_state().pc;
+    let memory = emu.memory();
+    match memory.get(pc as usize..pc as usize + 2) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]) == 0x1000 | pc,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::Quirks;
+
+    fn emu(quirks: Quirks, rom: &[u8]) -> Emu {
+        let mut emu = Emu::with_seed(quirks, 0);
+        emu.load_rom(rom).unwrap();
+        emu
+    }
+
+    // Runs a ROM for up to 10 frames of 10 instructions, counting how many frames on_frame saw.
+    fn run(emu: &mut Emu, halt_on_spin: bool) -> (u32, StopReason, u32) {
+        let mut seen = 0;
+        let (frames, reason) = run_frames(emu, 10, 10, halt_on_spin, &InputScript::default(), |_, frame| {
+            assert_eq!(frame, seen);
+            seen += 1;
+        })
+        .unwrap();
+        (frames, reason, seen)
+    }
+
+    #[test]
+    fn stops_at_the_frame_limit() {
+        // Counts up in V0 forever, without ever jumping to itself.
+        let rom = [0x70, 0x01, 0x12, 0x00];
+        let mut counting = emu(Quirks::COSMAC_VIP, &rom);
+        assert_eq!(run(&mut counting, true), (10, StopReason::FrameLimit, 10));
+        assert_eq!(counting.cpu_state().v[0], 50);
+
+        // No frames at all runs nothing.
+        let mut counting = emu(Quirks::COSMAC_VIP, &rom);
+        let (frames, reason) = run_frames(&mut counting, 10, 0, true, &InputScript::default(), |_, _| {}).unwrap();
+        assert_eq!((frames, reason), (0, StopReason::FrameLimit));
+        assert_eq!(counting.cpu_state().v[0], 0);
+    }
+
+    #[test]
+    fn halts_on_a_jump_to_itself() {
+        // Sets V0 then jumps to the jump, which takes one frame.
+        let rom = [0x60, 0x07, 0x12, 0x02];
+        let mut spinning = emu(Quirks::COSMAC_VIP, &rom);
+        assert_eq!(run(&mut spinning, true), (1, StopReason::Halted, 1));
+        assert_eq!(spinning.cpu_state().pc, 0x202);
+
+        // Unless that is turned off, as it is for recording audio.
+        let mut spinning = emu(Quirks::COSMAC_VIP, &rom);
+        assert_eq!(run(&mut spinning, false), (10, StopReason::FrameLimit, 10));
+
+        // A jump somewhere else is not a halt.
+        assert!(!is_spinning(&emu(Quirks::COSMAC_VIP, &[0x12, 0x04])));
+    }
+
+    #[test]
+    fn stops_when_the_program_exits() {
+        let rom = [0x60, 0x07, 0x00, 0xFD];
+        let mut exiting = emu(Quirks::SUPER_CHIP, &rom);
+        assert_eq!(run(&mut exiting, true), (1, StopReason::Exited, 1));
+        assert!(exiting.has_exited());
+
+        // 00FD is a SUPER-CHIP instruction, which plain CHIP-8 doesn't have.
+        let mut plain = emu(Quirks::COSMAC_VIP, &rom);
+        assert!(run_frames(&mut plain, 10, 10, true, &InputScript::default(), |_, _| {}).is_err());
+    }
+
+    #[test]
+    fn frame_clock_ticks_the_timers_once_a_frame() {
+        // Sets the delay timer to 5.
+        let mut emu = emu(Quirks::COSMAC_VIP, &[0x60, 0x05, 0xF0, 0x15]);
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+
+        let mut clock = FrameClock::new(3);
+        assert!(clock.at_frame_start());
+        assert!(!clock.advance(&mut emu, 2));
+        assert_eq!(clock.remaining(), 1);
+        assert_eq!(emu.cpu_state().dt, 5);
+        assert!(clock.advance(&mut emu, 1));
+        assert!(clock.at_frame_start());
+        assert_eq!(emu.cpu_state().dt, 4);
+    }
+}
@@ -0,0 +1,424 @@
+// Just enough JSON for the debug adapter's messages: a value type, a parser and a writer.
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    // Builds an object from its fields.
+    pub fn object<const N: usize>(fields: [(&str, Json); N]) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    // Looks up a field of an object. Anything else, or a missing field, gives Null.
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields.get(key).unwrap_or(&Json::Null),
+            _ => &Json::Null,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    // Numbers that are whole and not negative.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= u64::MAX as f64 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(n) if n.fract() == 0.0 && n.abs() <= i64::MAX as f64 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != text.len() {
+            return Err(parser.error("unexpected text after the value"));
+        }
+        Ok(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Self {
+        Json::String(text.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(text: String) -> Self {
+        Json::String(text)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self {
+        Json::Array(items)
+    }
+}
+
+macro_rules! from_number {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Json {
+            fn from(n: $t) -> Self {
+                Json::Number(n as f64)
+            }
+        })*
+    };
+}
+
+from_number!(u8, u16, u32, u64, usize, i64);
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Json::Number(_) => write!(f, "null"), // JSON can't hold infinities or NaN.
+            Json::String(text) => write_string(f, text),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut fields = BTreeMap::new();
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a field name"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            fields.insert(key, self.value()?);
+            self.skip_whitespace();
+            if self.eat(b'}') {
+                return Ok(Json::Object(fields));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat(b']') {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.eat(b']') {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.next() {
+                Some(b'"') => break,
+                Some(b'\\') => match self.next() {
+                    Some(b'"') => bytes.push(b'"'),
+                    Some(b'\\') => bytes.push(b'\\'),
+                    Some(b'/') => bytes.push(b'/'),
+                    Some(b'b') => bytes.push(0x08),
+                    Some(b'f') => bytes.push(0x0C),
+                    Some(b'n') => bytes.push(b'\n'),
+                    Some(b'r') => bytes.push(b'\r'),
+                    Some(b't') => bytes.push(b'\t'),
+                    Some(b'u') => {
+                        let mut code = self.hex4()?;
+                        // Characters outside the basic plane come as a pair of surrogates. A surrogate
+                        // without its other half isn't a character, so it becomes the replacement character.
+                        if (0xD800..0xDC00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+                            let saved = self.pos;
+                            self.pos += 2;
+                            match self.hex4()? {
+                                low @ 0xDC00..=0xDFFF => code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00),
+                                _ => self.pos = saved,
+                            }
+                        }
+                        let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                        bytes.extend(c.to_string().as_bytes());
+                    },
+                    _ => return Err(self.error("invalid escape")),
+                },
+                Some(byte) => bytes.push(byte),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("short \\u escape"))?;
+        let code = std::str::from_utf8(digits).ok().and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        self.pos += 4;
+        code.ok_or_else(|| self.error("invalid \\u escape"))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.text[start..self.pos]).unwrap_or("");
+        text.parse().map(Json::Number).map_err(|_| self.error("invalid number"))
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("invalid JSON at byte {}: {}", self.pos, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> Json {
+        Json::from(text)
+    }
+
+    #[test]
+    fn parses_string_escapes() {
+        assert_eq!(Json::parse(r#""a\"b\\c\/d""#), Ok(string("a\"b\\c/d")));
+        assert_eq!(Json::parse(r#""\b\f\n\r\t""#), Ok(string("\u{8}\u{c}\n\r\t")));
+        assert_eq!(Json::parse(r#""\u00e9\u20AC""#), Ok(string("é€")));
+        assert_eq!(Json::parse("\"é€\""), Ok(string("é€")));
+        assert!(Json::parse(r#""\q""#).is_err());
+        assert!(Json::parse(r#""\u12""#).is_err());
+        assert!(Json::parse(r#""\u12zz""#).is_err());
+    }
+
+    #[test]
+    fn parses_surrogate_pairs() {
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#), Ok(string("😀")));
+        // Surrogates on their own can't be decoded, but don't take the escape after them with them.
+        assert_eq!(Json::parse(r#""\ud83d""#), Ok(string("\u{fffd}")));
+        assert_eq!(Json::parse(r#""\ude00x""#), Ok(string("\u{fffd}x")));
+        assert_eq!(Json::parse(r#""\ud83d\u0041""#), Ok(string("\u{fffd}A")));
+        assert_eq!(Json::parse(r#""\ud83d\ud83d\ude00""#), Ok(string("\u{fffd}😀")));
+    }
+
+    #[test]
+    fn parses_numbers() {
+        assert_eq!(Json::parse("0"), Ok(Json::Number(0.0)));
+        assert_eq!(Json::parse("-12"), Ok(Json::Number(-12.0)));
+        assert_eq!(Json::parse("1.5e3"), Ok(Json::Number(1500.0)));
+        assert_eq!(Json::parse("2E-1"), Ok(Json::Number(0.2)));
+        assert!(Json::parse("-").is_err());
+        assert!(Json::parse("1-2").is_err());
+        assert!(Json::parse("+1").is_err());
+        assert_eq!(Json::parse("9007199254740993").unwrap().as_u64(), Some(9007199254740992));
+        assert_eq!(Json::parse("1.5").unwrap().as_u64(), None);
+        assert_eq!(Json::parse("-1").unwrap().as_u64(), None);
+        assert_eq!(Json::parse("-1").unwrap().as_i64(), Some(-1));
+    }
+
+    #[test]
+    fn parses_nested_values() {
+        let value = Json::parse(r#" { "a" : [1, true, null, {"b": "c"}], "d": {} } "#).unwrap();
+        assert_eq!(value.get("a").as_array().len(), 4);
+        assert_eq!(value.get("a").as_array()[1].as_bool(), Some(true));
+        assert_eq!(value.get("a").as_array()[3].get("b").as_str(), Some("c"));
+        assert_eq!(value.get("d"), &Json::Object(BTreeMap::new()));
+        assert_eq!(value.get("missing"), &Json::Null);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for text in ["", "  ", "{", r#"{"a""#, r#"{"a":"#, r#"{"a":1"#, r#"{"a":1,"#, "[", "[1", "[1,", r#""abc"#, "tru", "nul"] {
+            assert!(Json::parse(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_text() {
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("{} x").is_err());
+        assert!(Json::parse("[]]").is_err());
+        assert!(Json::parse("true false").is_err());
+        assert_eq!(Json::parse("[] \r\n"), Ok(Json::Array(Vec::new())));
+    }
+
+    #[test]
+    fn rejects_malformed_objects_and_arrays() {
+        for text in ["{1:2}", r#"{"a" 1}"#, r#"{"a":1 "b":2}"#, "[1 2]", "[,]", "{,}"] {
+            assert!(Json::parse(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn writes_escaped_strings() {
+        assert_eq!(string("a\"b\\c").to_string(), r#""a\"b\\c""#);
+        assert_eq!(string("\n\r\t\u{1}\u{1f}").to_string(), r#""\n\r\t\u0001\u001f""#);
+        assert_eq!(string("é😀/").to_string(), "\"é😀/\"");
+    }
+
+    #[test]
+    fn writes_values() {
+        let value = Json::object([
+            ("b", Json::from(vec![Json::from(1u8), Json::Number(-2.5), Json::Null])),
+            ("a", Json::from(true)),
+            ("c", Json::Number(f64::NAN)),
+        ]);
+        assert_eq!(value.to_string(), r#"{"a":true,"b":[1,-2.5,null],"c":null}"#);
+    }
+
+    #[test]
+    fn round_trips_through_the_writer() {
+        let value = Json::object([
+            ("text", string("quote \" slash \\ newline \n tab \t bell \u{7} é 😀")),
+            ("numbers", Json::from(vec![Json::from(0u8), Json::from(65535u16), Json::Number(-0.125), Json::Number(1e20)])),
+            ("nested", Json::object([("empty", Json::Array(Vec::new())), ("no", Json::from(false))])),
+        ]);
+        assert_eq!(Json::parse(&value.to_string()), Ok(value));
+    }
+}
@@ -0,0 +1,30 @@
+// The CHIP-8 keypad is a 4x4 grid of hex keys. We map it onto the left hand side of a QWERTY
+// keyboard, so that the keys keep the same layout as the original:
+//
+//  1 2 3 C        1 2 3 4
+//  4 5 6 D   ->   Q W E R
+//  7 8 9 E        A S D F
+//  A 0 B F        Z X C V
+//
+// Returns the hex key for a character typed on the keyboard, or None if it is not part of the keypad.
+pub fn key_to_button(key: u8) -> Option<usize> {
+    match key.to_ascii_lowercase() {
+        b'1' => Some(0x1),
+        b'2' => Some(0x2),
+        b'3' => Some(0x3),
+        b'4' => Some(0xC),
+        b'q' => Some(0x4),
+        b'w' => Some(0x5),
+        b'e' => Some(0x6),
+        b'r' => Some(0xD),
+        b'a' => Some(0x7),
+        b's' => Some(0x8),
+        b'd' => Some(0x9),
+        b'f' => Some(0xE),
+        b'z' => Some(0xA),
+        b'x' => Some(0x0),
+        b'c' => Some(0xB),
+        b'v' => Some(0xF),
+        _ => None,
+    }
+}
@@ -1,3 +1,57 @@
+mod asm;
+mod audio;
+mod cli;
+mod dap;
+mod debug;
+mod diff;
+mod disasm;
+mod gdb;
+mod headless;
+mod json;
+mod keymap;
+mod palette;
+mod play;
+mod rom;
+mod run;
+mod slots;
+mod terminal;
+mod wav;
+mod window;
+
+use std::env;
+use std::process;
+
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].as_str();
+
+    // The first argument picks the command. Anything else is a ROM to play.
+    let result = match args.get(1).map(String::as_str) {
+        Some("asm") => asm::main(program, &args[2..]),
+        Some("dap") => dap::main(program, &args[2..]),
+        Some("debug") => debug::main(program, &args[2..]),
+        Some("diff") => diff::main(program, &args[2..]),
+        Some("disasm") => disasm::main(program, &args[2..]),
+        Some("gdb") => gdb::main(program, &args[2..]),
+        Some("run") => run::main(program, &args[2..]),
+        Some("wav") => wav::main(program, &args[2..]),
+        Some("--help") | None => {
+            eprintln!("Usage: {}", play::usage(program));
+            eprintln!("       {}", run::usage(program));
+            eprintln!("       {}", wav::usage(program));
+            eprintln!("       {}", diff::usage(program));
+            eprintln!("       {}", debug::usage(program));
+            eprintln!("       {}", gdb::usage(program));
+            eprintln!("       {}", dap::usage(program));
+            eprintln!("       {}", disasm::usage(program));
+            eprintln!("       {}", asm::usage(program));
+            process::exit(1);
+        },
+        _ => play::main(program, &args[1..]),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
 }
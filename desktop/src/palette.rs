@@ -0,0 +1,8 @@
+// The colors used for each pixel value, by the terminal and the window alike. Plain CHIP-8 only uses
+// the first two, XO-CHIP's two bitplanes can use all four.
+pub const PALETTE: [(u8, u8, u8); 4] = [
+    (0, 0, 0),
+    (255, 255, 255),
+    (170, 170, 170),
+    (85, 85, 85),
+];
@@ -0,0 +1,299 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip8_core::{Emu, NUM_KEYS};
+
+use crate::audio::{self, AudioControl, AudioSettings};
+use crate::cli::{self, EmuOptions};
+use crate::window::{self, Key, Window};
+use crate::{keymap, slots, terminal};
+
+const FRAME_TIME: Duration = Duration::from_micros(16_667); // 60 frames per second.
+const ESCAPE: u8 = 0x1B;
+
+// Save state controls. The number keys 5 to 9 pick a slot, which the save and load keys then use.
+const SAVE_KEY: u8 = b'k';
+const LOAD_KEY: u8 = b'l';
+const MUTE_KEY: u8 = b'm';
+const DEFAULT_SLOT: u8 = 5;
+
+// Terminals only tell us when a key is typed, not when it is let go, unlike a window.
+// So there a key counts as held for this long after it was last typed, which is long enough
+// to bridge the gap between the terminal's key repeats.
+const KEY_HOLD_TIME: Duration = Duration::from_millis(200);
+
+// Everything that can be set on the command line.
+struct Options {
+    rom: PathBuf,
+    emu: EmuOptions,
+    audio: AudioSettings,
+    terminal: bool, // Play in the terminal rather than a window.
+}
+
+pub fn usage(program: &str) -> String {
+    format!("{} {}\n       {} [--mute] [--terminal] <ROM>", program, cli::EMU_OPTIONS_USAGE, cli::AUDIO_OPTIONS_USAGE)
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut rom = None;
+    let mut emu = EmuOptions::default();
+    let mut audio = AudioSettings::default();
+    let mut terminal = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if emu.parse_arg(arg, &mut iter)? || cli::parse_audio_arg(&mut audio, arg, &mut iter)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--mute" => audio.muted = true,
+            "--terminal" => terminal = true,
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    Some(Options { rom: rom?, emu, audio, terminal })
+}
+
+// What the player did, as far as the main loop cares.
+#[derive(Debug, PartialEq)]
+enum Input {
+    // A hex key went down or up.
+    Keypad(usize, bool),
+    // Any other key was pressed, for the save state and mute controls.
+    Command(u8),
+    Quit,
+}
+
+// Somewhere to play: shows the screen and reports what the player does.
+trait Frontend {
+    fn poll(&mut self, now: Instant) -> io::Result<Vec<Input>>;
+    fn show(&mut self, emu: &Emu, status: &str) -> io::Result<()>;
+}
+
+// Plays a ROM in a window, or in the terminal when asked to or when no window can be opened.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let mut emu = options.emu.create_emu(&options.rom)?;
+
+    // Sound is nice to have, so the game still runs without it.
+    let audio = match audio::start(options.audio) {
+        Ok((sink, control)) => {
+            emu.set_audio_sink(Box::new(sink));
+            Some(control)
+        },
+        Err(err) => {
+            eprintln!("Audio disabled: {}", err);
+            None
+        },
+    };
+
+    let mut frontend: Box<dyn Frontend> = if options.terminal {
+        Box::new(TerminalFrontend::start()?)
+    } else {
+        match WindowFrontend::open(&options.rom) {
+            Ok(frontend) => Box::new(frontend),
+            Err(err) => {
+                eprintln!("Could not open a window, playing in the terminal instead: {}", err);
+                Box::new(TerminalFrontend::start()?)
+            },
+        }
+    };
+
+    run(&mut emu, frontend.as_mut(), &options.rom, options.emu.ticks_per_frame, audio.as_ref())
+}
+
+// The main loop. Runs until the player quits, the program exits, or the emulator hits an error.
+fn run(
+    emu: &mut Emu,
+    frontend: &mut dyn Frontend,
+    rom: &Path,
+    ticks_per_frame: u32,
+    audio: Option<&AudioControl>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut slot = DEFAULT_SLOT;
+    let mut status = format!("Save state slot {}", slot);
+
+    loop {
+        let frame_start = Instant::now();
+
+        for input in frontend.poll(frame_start)? {
+            match input {
+                Input::Keypad(button, pressed) => emu.keypress(button, pressed),
+                Input::Quit => return Ok(()),
+                Input::Command(key @ b'5'..=b'9') => {
+                    slot = key - b'0';
+                    status = format!("Save state slot {}", slot);
+                },
+                Input::Command(SAVE_KEY) => {
+                    status = match slots::save_slot(emu, rom, slot) {
+                        Ok(()) => format!("Saved to slot {}", slot),
+                        Err(err) => format!("Could not save to slot {}: {}", slot, err),
+                    };
+                },
+                Input::Command(MUTE_KEY) => {
+                    status = match audio {
+                        Some(audio) if audio.toggle_mute() => "Muted".to_string(),
+                        Some(_) => "Unmuted".to_string(),
+                        None => "No audio".to_string(),
+                    };
+                },
+                Input::Command(LOAD_KEY) => {
+                    status = match slots::load_slot(emu, rom, slot) {
+                        Ok(()) => format!("Loaded slot {}", slot),
+                        Err(err) => format!("Could not load slot {}: {}", slot, err),
+                    };
+                },
+                Input::Command(_) => {},
+            }
+        }
+
+        for _ in 0..ticks_per_frame {
+            emu.tick()?;
+        }
+        emu.tick_timers();
+        frontend.show(emu, &status)?;
+
+        if emu.has_exited() {
+            return Ok(());
+        }
+
+        // Sleep for whatever is left of this frame to keep a steady 60 Hz.
+        if let Some(remaining) = FRAME_TIME.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+// Plays in a window, which says when keys go up as well as down. The status goes in the title bar.
+struct WindowFrontend {
+    window: Window,
+    name: String, // The ROM's file name, to start the title with.
+    status: String, // The status in the title bar, so it's only set when it changes.
+}
+
+impl WindowFrontend {
+    fn open(rom: &Path) -> io::Result<Self> {
+        let name = rom.file_name().unwrap_or(rom.as_os_str()).to_string_lossy().into_owned();
+        Ok(Self { window: Window::open(&name)?, name, status: String::new() })
+    }
+}
+
+impl Frontend for WindowFrontend {
+    fn poll(&mut self, _now: Instant) -> io::Result<Vec<Input>> {
+        Ok(self.window.events()?.into_iter().flat_map(window_input).collect())
+    }
+
+    fn show(&mut self, emu: &Emu, status: &str) -> io::Result<()> {
+        if self.status != status {
+            self.window.set_title(&format!("{} - {}", self.name, status))?;
+            self.status = status.to_string();
+        }
+        self.window.draw(emu)
+    }
+}
+
+// What a window event means for the game.
+fn window_input(event: window::Event) -> Vec<Input> {
+    match event {
+        window::Event::Pressed(Key::Char(c)) => match keymap::key_to_button(c) {
+            Some(button) => vec![Input::Keypad(button, true)],
+            None => vec![Input::Command(c)],
+        },
+        window::Event::Released(Key::Char(c)) => match keymap::key_to_button(c) {
+            Some(button) => vec![Input::Keypad(button, false)],
+            None => Vec::new(),
+        },
+        window::Event::Pressed(Key::Escape) | window::Event::Closed => vec![Input::Quit],
+        window::Event::Released(Key::Escape) => Vec::new(),
+        // Keys let go while another window had the keyboard would otherwise stay down for good.
+        window::Event::FocusLost => (0..NUM_KEYS).map(|button| Input::Keypad(button, false)).collect(),
+    }
+}
+
+// Plays in the terminal, which only says when keys are typed, so they are held for KEY_HOLD_TIME.
+struct TerminalFrontend {
+    _raw_mode: terminal::RawMode,
+    input: Receiver<Vec<u8>>,
+    last_pressed: [Option<Instant>; NUM_KEYS], // When each hex key was last typed.
+}
+
+impl TerminalFrontend {
+    fn start() -> io::Result<Self> {
+        let raw_mode = terminal::RawMode::enable()?;
+        Ok(Self { _raw_mode: raw_mode, input: terminal::spawn_input_reader(), last_pressed: [None; NUM_KEYS] })
+    }
+}
+
+impl Frontend for TerminalFrontend {
+    fn poll(&mut self, now: Instant) -> io::Result<Vec<Input>> {
+        let mut inputs = Vec::new();
+
+        // Handle everything that was typed since the last frame.
+        loop {
+            match self.input.try_recv() {
+                Ok(bytes) => {
+                    // A lone Escape is the Escape key. Escape followed by more bytes is
+                    // something like an arrow key, which we ignore.
+                    if bytes == [ESCAPE] {
+                        inputs.push(Input::Quit);
+                    }
+                    if bytes.first() == Some(&ESCAPE) {
+                        continue;
+                    }
+                    for &byte in &bytes {
+                        match keymap::key_to_button(byte) {
+                            Some(button) => self.last_pressed[button] = Some(now),
+                            None => inputs.push(Input::Command(byte)),
+                        }
+                    }
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    inputs.push(Input::Quit);
+                    break;
+                },
+            }
+        }
+
+        for (button, pressed_at) in self.last_pressed.iter().enumerate() {
+            let held = pressed_at.is_some_and(|at| now - at < KEY_HOLD_TIME);
+            inputs.push(Input::Keypad(button, held));
+        }
+        Ok(inputs)
+    }
+
+    fn show(&mut self, emu: &Emu, status: &str) -> io::Result<()> {
+        terminal::render(emu, status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::Event;
+
+    #[test]
+    fn window_keys_go_down_and_up() {
+        assert_eq!(window_input(Event::Pressed(Key::Char(b'q'))), [Input::Keypad(0x4, true)]);
+        assert_eq!(window_input(Event::Released(Key::Char(b'q'))), [Input::Keypad(0x4, false)]);
+        assert_eq!(window_input(Event::Pressed(Key::Char(SAVE_KEY))), [Input::Command(SAVE_KEY)]);
+        assert_eq!(window_input(Event::Released(Key::Char(SAVE_KEY))), []);
+    }
+
+    #[test]
+    fn window_quits_on_escape_or_close() {
+        assert_eq!(window_input(Event::Pressed(Key::Escape)), [Input::Quit]);
+        assert_eq!(window_input(Event::Closed), [Input::Quit]);
+    }
+
+    #[test]
+    fn losing_focus_lets_go_of_every_key() {
+        let inputs = window_input(Event::FocusLost);
+        assert_eq!(inputs, (0..NUM_KEYS).map(|button| Input::Keypad(button, false)).collect::<Vec<_>>());
+    }
+}
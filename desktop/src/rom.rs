@@ -0,0 +1,43 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use chip8_core::{Emu, EmuError};
+
+// Anything that can stop a ROM file from making it into the emulator.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error), // The file could not be read.
+    Emu(EmuError), // The file was read, but the core rejected it (for example, it was too large).
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "could not read ROM: {}", err),
+            LoadError::Emu(err) => write!(f, "could not load ROM: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl From<EmuError> for LoadError {
+    fn from(err: EmuError) -> Self {
+        LoadError::Emu(err)
+    }
+}
+
+// Reads a ROM file from disk and loads it into the emulator.
+pub fn load_rom_file(emu: &mut Emu, path: &Path) -> Result<(), LoadError> {
+    let data = fs::read(path)?;
+    emu.load_rom(&data)?;
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use chip8_core::trace::{TraceFormat, Tracer};
+use chip8_core::Emu;
+
+use crate::cli::{self, EmuOptions};
+use crate::headless::{self, StopReason};
+
+const DEFAULT_FRAMES: u32 = 600; // Ten seconds.
+
+// How each pixel value is drawn. XO-CHIP's extra colors get their own characters.
+const PIXEL_CHARS: [char; 4] = ['.', '#', '+', '@'];
+
+// Everything that can be set on the command line.
+struct Options {
+    rom: PathBuf,
+    frames: u32,
+    script: Option<PathBuf>,
+    trace: Option<Trace>,
+    emu: EmuOptions,
+}
+
+// Where to trace the instructions that run.
+enum Trace {
+    File(PathBuf, TraceFormat),
+    Last(usize), // Keep the last few instructions, and print them at the end.
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "{} run [--frames <count>] [--input <script>] [--trace <file> [--trace-format text|binary] | --trace-last <count>] {} <ROM>",
+        program,
+        cli::EMU_OPTIONS_USAGE
+    )
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut rom = None;
+    let mut frames = DEFAULT_FRAMES;
+    let mut script = None;
+    let mut trace_file = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_last = None;
+    let mut emu = EmuOptions::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if emu.parse_arg(arg, &mut iter)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--frames" => frames = iter.next()?.parse().ok()?,
+            "--input" => script = Some(PathBuf::from(iter.next()?)),
+            "--trace" => trace_file = Some(PathBuf::from(iter.next()?)),
+            "--trace-format" => trace_format = parse_trace_format(iter.next()?)?,
+            "--trace-last" => trace_last = Some(iter.next()?.parse().ok()?),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    // Runs in a pipeline should be repeatable, so the random numbers are fixed unless asked otherwise.
+    emu.seed = Some(emu.seed.unwrap_or(0));
+
+    let trace = match (trace_file, trace_last) {
+        (Some(path), None) => Some(Trace::File(path, trace_format)),
+        (None, Some(count)) => Some(Trace::Last(count)),
+        (None, None) => None,
+        (Some(_), Some(_)) => return None,
+    };
+
+    Some(Options { rom: rom?, frames, script, trace, emu })
+}
+
+fn parse_trace_format(name: &str) -> Option<TraceFormat> {
+    match name {
+        "text" => Some(TraceFormat::Text),
+        "binary" => Some(TraceFormat::Binary),
+        _ => None,
+    }
+}
+
+// Runs a ROM without a screen, then prints what the screen and registers ended up as.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let mut emu = options.emu.create_emu(&options.rom)?;
+    let script = cli::load_script(options.script.as_deref())?;
+
+    match &options.trace {
+        Some(Trace::File(path, format)) => emu.set_tracer(Tracer::to_writer(Box::new(BufWriter::new(File::create(path)?)), *format)?),
+        Some(Trace::Last(count)) => emu.set_tracer(Tracer::to_buffer(*count)),
+        None => {},
+    }
+
+    let result = headless::run_frames(&mut emu, options.emu.ticks_per_frame, options.frames, true, &script, |_, _| {});
+
+    // Show the final state even if the program crashed, since that is when it is most useful.
+    print!("{}", screen_to_ascii(&emu));
+    print!("{}", registers_to_string(&emu));
+    if let Some(tracer) = emu.take_tracer() {
+        if let Some(Trace::Last(_)) = options.trace {
+            println!("Last instructions:");
+            for entry in tracer.entries() {
+                println!("{}", entry);
+            }
+        }
+        tracer.finish()?;
+    }
+    let (frames, reason) = result?;
+    let reason = match reason {
+        StopReason::FrameLimit => "frame limit reached",
+        StopReason::Exited => "program exited",
+        StopReason::Halted => "program halted on a jump to itself",
+    };
+    println!("Stopped after {} frames: {}", frames, reason);
+    Ok(())
+}
+
+// Draws the screen one character per pixel.
+pub fn screen_to_ascii(emu: &Emu) -> String {
+    let (width, _) = emu.resolution();
+    let mut out = String::new();
+    for row in emu.planes().chunks(width) {
+        out.extend(row.iter().map(|&pixel| PIXEL_CHARS[(pixel & 0x3) as usize]));
+        out.push('\n');
+    }
+    out
+}
+
+// Lists the CPU registers, timers and call stack.
+pub fn registers_to_string(emu: &Emu) -> String {
+    let state = emu.cpu_state();
+    let mut out = format!(
+        "PC={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X}\n",
+        state.pc, state.i, state.sp, state.dt, state.st
+    );
+    for (idx, value) in state.v.iter().enumerate() {
+        out.push_str(&format!("V{:X}={:02X}", idx, value));
+        out.push(if idx % 8 == 7 { '\n' } else { ' ' });
+    }
+    let stack: Vec<String> = state.stack[..state.sp as usize].iter().map(|addr| format!("{:04X}", addr)).collect();
+    out.push_str(&format!("Stack: [{}]\n", stack.join(", ")));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::script::InputScript;
+    use chip8_core::Quirks;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn frames_option() {
+        assert_eq!(parse_args(&args(&["pong.ch8"])).unwrap().frames, DEFAULT_FRAMES);
+        assert_eq!(parse_args(&args(&["--frames", "5", "pong.ch8"])).unwrap().frames, 5);
+        assert!(parse_args(&args(&["--frames", "many", "pong.ch8"])).is_none());
+        assert!(parse_args(&args(&["pong.ch8", "--frames"])).is_none());
+        // Tracing to a file and keeping the last few can't be asked for together.
+        assert!(parse_args(&args(&["--trace", "out.txt", "--trace-last", "5", "pong.ch8"])).is_none());
+    }
+
+    #[test]
+    fn screen_is_one_character_per_pixel() {
+        // Draws the top row of the 0 font sprite, 1111, at (2, 1).
+        let mut emu = Emu::with_seed(Quirks::COSMAC_VIP, 0);
+        emu.load_rom(&[0x60, 0x02, 0x61, 0x01, 0xA0, 0x00, 0xD0, 0x11, 0x12, 0x08]).unwrap();
+        // The draw waits for the next frame, so this takes two.
+        headless::run_frames(&mut emu, 10, 2, false, &InputScript::default(), |_, _| {}).unwrap();
+
+        let screen = screen_to_ascii(&emu);
+        let lines: Vec<&str> = screen.lines().collect();
+        assert_eq!(lines.len(), 32);
+        assert!(lines.iter().all(|line| line.len() == 64));
+        assert_eq!(lines[0], ".".repeat(64));
+        assert_eq!(lines[1], format!("..####{}", ".".repeat(58)));
+    }
+
+    #[test]
+    fn register_dump() {
+        // Sets V0 and VA, then calls a subroutine and sets the delay timer from there.
+        let mut emu = Emu::with_seed(Quirks::COSMAC_VIP, 0);
+        emu.load_rom(&[0x60, 0x12, 0x6A, 0xFF, 0x22, 0x08, 0x00, 0x00, 0xF0, 0x15]).unwrap();
+        for _ in 0..4 {
+            emu.tick().unwrap();
+        }
+
+        assert_eq!(
+            registers_to_string(&emu),
+            "PC=020A I=0000 SP=1 DT=12 ST=00\n\
+             V0=12 V1=00 V2=00 V3=00 V4=00 V5=00 V6=00 V7=00\n\
+             V8=00 V9=00 VA=FF VB=00 VC=00 VD=00 VE=00 VF=00\n\
+             Stack: [0206]\n"
+        );
+    }
+}
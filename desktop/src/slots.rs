@@ -0,0 +1,28 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chip8_core::Emu;
+
+use crate::rom::LoadError;
+
+// Save states are kept next to the ROM, named after it with the slot number on the end.
+// For example, slot 5 for rom_files/PONG is rom_files/PONG.state5.
+pub fn slot_path(rom: &Path, slot: u8) -> PathBuf {
+    let mut name = rom.file_name().map(OsString::from).unwrap_or_default();
+    name.push(format!(".state{}", slot));
+    rom.with_file_name(name)
+}
+
+// Writes the emulator's current state to a slot, replacing whatever was saved there before.
+pub fn save_slot(emu: &Emu, rom: &Path, slot: u8) -> io::Result<()> {
+    fs::write(slot_path(rom, slot), emu.save_state())
+}
+
+// Restores the emulator from a slot that was saved earlier.
+pub fn load_slot(emu: &mut Emu, rom: &Path, slot: u8) -> Result<(), LoadError> {
+    let data = fs::read(slot_path(rom, slot))?;
+    emu.load_state(&data)?;
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use chip8_core::Emu;
+
+use crate::palette::PALETTE;
+
+// Puts the terminal into a mode where key presses are delivered straight away and are not echoed.
+// The previous settings are put back when this is dropped, so the shell is usable again after we exit.
+// This is done with stty, so it only works on Unix. std has no way to do it on Windows.
+pub struct RawMode {
+    #[cfg(unix)]
+    saved: String, // The terminal settings from before we changed them, as printed by `stty -g`.
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    pub fn enable() -> io::Result<Self> {
+        let output = stty(&["-g"])?;
+        let saved = String::from_utf8_lossy(&output).trim().to_string();
+        stty(&["-icanon", "-echo", "min", "1"])?;
+
+        // Hide the cursor and clear the screen.
+        print!("\x1b[?25l\x1b[2J");
+        io::stdout().flush()?;
+
+        Ok(Self { saved })
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable() -> io::Result<Self> {
+        let message = "playing in the terminal needs Unix, since it uses stty. The other commands, like `chip8 run`, work here";
+        Err(io::Error::new(io::ErrorKind::Unsupported, message))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // Show the cursor again and put the colors back to normal.
+        print!("\x1b[0m\x1b[?25h\r\n");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+// Runs stty against our terminal. stty works on whatever its stdin is, so we have to pass ours through.
+#[cfg(unix)]
+fn stty(args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed, is stdin a terminal?"));
+    }
+    Ok(output.stdout)
+}
+
+// Reads from stdin on a background thread, so that the main loop never blocks waiting for input.
+// Each message is one chunk of bytes, as it arrived from the terminal.
+pub fn spawn_input_reader() -> Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buf = [0; 64];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                },
+            }
+        }
+    });
+    rx
+}
+
+// Draws the emulator's screen to the terminal, with a line of status text underneath it.
+// Every character cell shows two pixels stacked on top of each other, using the upper half block
+// character with the top pixel as the foreground color and the bottom pixel as the background color.
+pub fn render(emu: &Emu, status: &str) -> io::Result<()> {
+    let (width, height) = emu.resolution();
+    let pixels = emu.planes();
+
+    // Move the cursor back to the top left, so each frame draws over the last one.
+    let mut frame = String::from("\x1b[H");
+    for row in (0..height).step_by(2) {
+        let mut last = None;
+        for x in 0..width {
+            let top = pixels[x + width * row] as usize;
+            let bottom = pixels[x + width * (row + 1)] as usize;
+
+            // Only send color codes when the colors actually change, to keep the frame small.
+            if last != Some((top, bottom)) {
+                let (fr, fg, fb) = PALETTE[top & 0x3];
+                let (br, bg, bb) = PALETTE[bottom & 0x3];
+                frame.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", fr, fg, fb, br, bg, bb));
+                last = Some((top, bottom));
+            }
+            frame.push('▀');
+        }
+        frame.push_str("\x1b[0m\r\n");
+    }
+    // Clear the rest of the status line, in case the last message was longer.
+    frame.push_str(status);
+    frame.push_str("\x1b[K");
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(frame.as_bytes())?;
+    stdout.flush()
+}
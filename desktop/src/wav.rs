@@ -0,0 +1,259 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use chip8_core::{Emu, AUDIO_PATTERN_SIZE};
+
+use crate::audio::{AudioSettings, Tone, SAMPLE_RATE};
+use crate::cli::{self, EmuOptions};
+use crate::headless;
+
+const FRAME_RATE: u32 = 60;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAME_RATE) as usize;
+const DEFAULT_FRAMES: u32 = 600; // Ten seconds.
+const PATTERN_BITS: f64 = (AUDIO_PATTERN_SIZE * 8) as f64;
+
+// Everything that can be set on the command line.
+struct Options {
+    rom: PathBuf,
+    out: PathBuf,
+    frames: u32,
+    script: Option<PathBuf>,
+    emu: EmuOptions,
+    audio: AudioSettings,
+}
+
+pub fn usage(program: &str) -> String {
+    format!(
+        "{} wav [--frames <count>] [--input <script>] {}\n       {} <ROM> <WAV>",
+        program,
+        cli::EMU_OPTIONS_USAGE,
+        cli::AUDIO_OPTIONS_USAGE
+    )
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut paths = Vec::new();
+    let mut frames = DEFAULT_FRAMES;
+    let mut script = None;
+    let mut emu = EmuOptions::default();
+    let mut audio = AudioSettings::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if emu.parse_arg(arg, &mut iter)? || cli::parse_audio_arg(&mut audio, arg, &mut iter)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--frames" => frames = iter.next()?.parse().ok()?,
+            "--input" => script = Some(PathBuf::from(iter.next()?)),
+            _ if !arg.starts_with("--") => paths.push(PathBuf::from(arg)),
+            _ => return None,
+        }
+    }
+
+    // The output has to be the same on every run, so the random numbers are fixed unless asked otherwise.
+    emu.seed = Some(emu.seed.unwrap_or(0));
+
+    let [rom, out]: [PathBuf; 2] = paths.try_into().ok()?;
+    Some(Options { rom, out, frames, script, emu, audio })
+}
+
+// Runs a ROM without a screen and records the buzzer to a WAV file.
+pub fn main(program: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args(args).ok_or_else(|| format!("Usage: {}", usage(program)))?;
+    let mut emu = options.emu.create_emu(&options.rom)?;
+    let script = cli::load_script(options.script.as_deref())?;
+
+    let mut recorder = Recorder::new(&options.audio);
+    // Keep recording after a program halts, since the sound timer may still be running.
+    let (frames, _) = headless::run_frames(
+        &mut emu,
+        options.emu.ticks_per_frame,
+        options.frames,
+        false,
+        &script,
+        |emu, _| recorder.record_frame(emu),
+    )?;
+
+    write_wav(&mut BufWriter::new(File::create(&options.out)?), &recorder.samples)?;
+    println!("Wrote {} frames of audio to {}", frames, options.out.display());
+    Ok(())
+}
+
+// Turns the buzzer into samples, one frame at a time.
+struct Recorder {
+    samples: Vec<i16>,
+    tone: Tone,
+    amplitude: i16,
+    pattern_position: f64, // How far through the XO-CHIP audio pattern we are, in bits.
+}
+
+impl Recorder {
+    fn new(settings: &AudioSettings) -> Self {
+        Self {
+            samples: Vec::new(),
+            tone: Tone::new(settings),
+            amplitude: (settings.volume.clamp(0.0, 1.0) * i16::MAX as f32) as i16,
+            pattern_position: 0.0,
+        }
+    }
+
+    fn record_frame(&mut self, emu: &Emu) {
+        if !emu.is_buzzer_on() {
+            self.samples.resize(self.samples.len() + SAMPLES_PER_FRAME, 0);
+            return;
+        }
+
+        // XO-CHIP programs play their own audio pattern. Until one has been loaded,
+        // it is all zeros, so we fall back to the plain buzzer.
+        let pattern = emu.audio_pattern();
+        if emu.quirks().xo_chip && pattern.iter().any(|&byte| byte != 0) {
+            let step = emu.playback_rate() / SAMPLE_RATE as f64;
+            for _ in 0..SAMPLES_PER_FRAME {
+                let bit = self.pattern_position as usize;
+                let high = (pattern[bit / 8] >> (7 - bit % 8)) & 1 != 0;
+                self.samples.push(if high { self.amplitude } else { -self.amplitude });
+                self.pattern_position = (self.pattern_position + step) % PATTERN_BITS;
+            }
+        } else {
+            for _ in 0..SAMPLES_PER_FRAME {
+                self.samples.push(self.tone.next_sample());
+            }
+        }
+    }
+}
+
+// Writes 16-bit mono samples as a standard PCM WAV file.
+fn write_wav(out: &mut impl Write, samples: &[i16]) -> io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?; // Size of the format chunk.
+    out.write_all(&1u16.to_le_bytes())?; // PCM.
+    out.write_all(&1u16.to_le_bytes())?; // Mono.
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // Bytes per second.
+    out.write_all(&2u16.to_le_bytes())?; // Bytes per sample.
+    out.write_all(&16u16.to_le_bytes())?; // Bits per sample.
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        out.write_all(&sample.to_le_bytes())?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_core::Quirks;
+
+    // Sets the XO-CHIP audio pattern to a single high bit followed by 127 low ones, sets the pitch
+    // from V0, and starts the sound timer.
+    fn pattern_rom(pitch: u8) -> Vec<u8> {
+        let mut rom = vec![
+            0xA2, 0x10, // I = pattern
+            0xF0, 0x02, // Load the audio pattern
+            0x60, pitch, // V0 = pitch
+            0xF0, 0x3A, // Set the pitch
+            0x61, 0x0A, // V1 = 10
+            0xF1, 0x18, // Sound timer = V1
+            0x12, 0x0C, // Loop forever
+            0x00, 0x00,
+        ];
+        rom.push(0x80);
+        rom.resize(rom.len() + AUDIO_PATTERN_SIZE - 1, 0);
+        rom
+    }
+
+    // Records a frame of a ROM after it has had time to start the buzzer.
+    fn record(quirks: Quirks, rom: &[u8]) -> Vec<i16> {
+        let mut emu = Emu::with_seed(quirks, 0);
+        emu.load_rom(rom).unwrap();
+        for _ in 0..6 {
+            emu.tick().unwrap();
+        }
+        emu.tick_timers();
+        assert!(emu.is_buzzer_on());
+
+        let mut recorder = Recorder::new(&AudioSettings::default());
+        recorder.record_frame(&emu);
+        recorder.samples
+    }
+
+    // How many samples the first bit of the pattern lasts for.
+    fn first_bit_len(samples: &[i16]) -> usize {
+        samples.iter().take_while(|&&sample| sample > 0).count()
+    }
+
+    #[test]
+    fn header() {
+        let mut out = Vec::new();
+        write_wav(&mut out, &[1, -2, 3]).unwrap();
+
+        let u16_at = |pos: usize| u16::from_le_bytes([out[pos], out[pos + 1]]);
+        let u32_at = |pos: usize| u32::from_le_bytes([out[pos], out[pos + 1], out[pos + 2], out[pos + 3]]);
+        assert_eq!(out.len(), 44 + 6);
+        assert_eq!(&out[0..4], b"RIFF");
+        assert_eq!(u32_at(4), 36 + 6);
+        assert_eq!(&out[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(16), 16);
+        assert_eq!(u16_at(20), 1); // PCM.
+        assert_eq!(u16_at(22), 1); // Mono.
+        assert_eq!(u32_at(24), 44_100);
+        assert_eq!(u32_at(28), 88_200);
+        assert_eq!(u16_at(32), 2);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(&out[36..40], b"data");
+        assert_eq!(u32_at(40), 6);
+        assert_eq!(&out[44..], [1, 0, 0xFE, 0xFF, 3, 0]);
+    }
+
+    #[test]
+    fn every_frame_is_a_sixtieth_of_a_second() {
+        assert_eq!(SAMPLES_PER_FRAME, 735);
+
+        // Silent frames as well as ones with the buzzer on.
+        let mut recorder = Recorder::new(&AudioSettings::default());
+        recorder.record_frame(&Emu::with_seed(Quirks::COSMAC_VIP, 0));
+        assert_eq!(recorder.samples, [0; SAMPLES_PER_FRAME]);
+        assert_eq!(record(Quirks::XO_CHIP, &pattern_rom(64)).len(), SAMPLES_PER_FRAME);
+    }
+
+    #[test]
+    fn plain_buzzer_is_a_square_wave() {
+        // Without XO-CHIP there is no pattern, so this is the 440 Hz tone: about 50 samples high, then 50 low.
+        let rom = [0x61, 0x0A, 0xF1, 0x18, 0x12, 0x04];
+        let mut emu = Emu::with_seed(Quirks::COSMAC_VIP, 0);
+        emu.load_rom(&rom).unwrap();
+        emu.tick().unwrap();
+        emu.tick().unwrap();
+        emu.tick_timers();
+
+        let mut recorder = Recorder::new(&AudioSettings::default());
+        recorder.record_frame(&emu);
+        let amplitude = (0.25 * i16::MAX as f32) as i16;
+        assert_eq!(first_bit_len(&recorder.samples), 51);
+        assert!(recorder.samples.iter().all(|&sample| sample == amplitude || sample == -amplitude));
+    }
+
+    #[test]
+    fn xo_chip_plays_the_pattern_at_its_pitch() {
+        // At the default pitch of 64 the pattern plays 4000 bits a second, about 11 samples each.
+        let samples = record(Quirks::XO_CHIP, &pattern_rom(64));
+        assert_eq!(first_bit_len(&samples), 12);
+        // The rest of the pattern is low, and 128 bits take longer than a frame.
+        assert!(samples[12..].iter().all(|&sample| sample < 0));
+
+        // 48 higher is an octave up, twice as fast.
+        assert_eq!(first_bit_len(&record(Quirks::XO_CHIP, &pattern_rom(112))), 6);
+        // And 48 lower is an octave down.
+        assert_eq!(first_bit_len(&record(Quirks::XO_CHIP, &pattern_rom(16))), 23);
+    }
+}
@@ -0,0 +1,125 @@
+// A window to play in. The screen is drawn into it as an image every frame, and it reports keys
+// being pressed and let go, which a terminal can't do.
+//
+// There are no windowing crates to build on, so each platform gets a small backend of its own that
+// only needs the standard library: the X11 protocol spoken straight over the X server's socket on
+// Unix, and the Win32 API on Windows. Anywhere else, opening a window fails.
+use std::io;
+
+use chip8_core::Emu;
+
+use crate::palette::PALETTE;
+
+#[cfg(unix)]
+mod x11;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(unix)]
+use x11 as platform;
+#[cfg(windows)]
+use win32 as platform;
+
+// How big the window starts out: 10 window pixels to every pixel of the 64x32 screen.
+pub const WIDTH: u16 = 640;
+pub const HEIGHT: u16 = 320;
+
+// The keys we care about. Letters are always lower case, whether or not shift is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(u8),
+    Escape,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Pressed(Key),
+    Released(Key),
+    // The window stopped getting the keyboard, so any keys that are down won't be seen going up.
+    FocusLost,
+    // The window's close button was clicked.
+    Closed,
+}
+
+pub struct Window {
+    inner: platform::Window,
+    pixels: Vec<u32>, // The screen as 0xRRGGBB colors, kept to save allocating one every frame.
+}
+
+impl Window {
+    pub fn open(title: &str) -> io::Result<Self> {
+        Ok(Self { inner: platform::Window::open(title, WIDTH, HEIGHT)?, pixels: Vec::new() })
+    }
+
+    pub fn set_title(&mut self, title: &str) -> io::Result<()> {
+        self.inner.set_title(title)
+    }
+
+    // Draws the emulator's screen, scaled up as far as it will go and centered in the window.
+    pub fn draw(&mut self, emu: &Emu) -> io::Result<()> {
+        let (width, height) = emu.resolution();
+        self.pixels.clear();
+        self.pixels.extend(emu.planes().iter().map(|&pixel| {
+            let (r, g, b) = PALETTE[pixel as usize & 0x3];
+            (r as u32) << 16 | (g as u32) << 8 | b as u32
+        }));
+        self.inner.draw(width, height, &self.pixels)
+    }
+
+    // Everything that happened since the last call, oldest first.
+    pub fn events(&mut self) -> io::Result<Vec<Event>> {
+        self.inner.events()
+    }
+}
+
+// How many times over a width x height image fits in the window, and where it goes to be centered.
+#[cfg(any(unix, windows))]
+fn fit(width: usize, height: usize, window: (u16, u16)) -> (usize, usize, usize) {
+    let (window_width, window_height) = (window.0 as usize, window.1 as usize);
+    let scale = (window_width / width).min(window_height / height).max(1);
+    let x = window_width.saturating_sub(width * scale) / 2;
+    let y = window_height.saturating_sub(height * scale) / 2;
+    (scale, x, y)
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use std::io;
+
+    use super::Event;
+
+    pub struct Window;
+
+    impl Window {
+        pub fn open(_title: &str, _width: u16, _height: u16) -> io::Result<Self> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "windows can only be opened on Unix and Windows"))
+        }
+
+        pub fn set_title(&mut self, _title: &str) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn draw(&mut self, _width: usize, _height: usize, _pixels: &[u32]) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn events(&mut self) -> io::Result<Vec<Event>> {
+            Ok(Vec::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_scaled_to_fit_and_centered() {
+        assert_eq!(fit(64, 32, (WIDTH, HEIGHT)), (10, 0, 0));
+        assert_eq!(fit(128, 64, (WIDTH, HEIGHT)), (5, 0, 0));
+        // The height decides here, leaving a gap either side.
+        assert_eq!(fit(64, 32, (800, 320)), (10, 80, 0));
+        // Too small for the image, which is drawn at its own size and cut off.
+        assert_eq!(fit(128, 64, (100, 50)), (1, 0, 0));
+    }
+}
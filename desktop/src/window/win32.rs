@@ -0,0 +1,353 @@
+// A window made with the Win32 API, which is linked against directly since std has no bindings for it.
+// It does only what playing needs: one window, an image stretched into it every frame, a title,
+// and the keyboard and close events that come back.
+//
+// Windows delivers events by calling the window procedure while we pump messages, on our own thread,
+// so the procedure leaves them in a thread local queue for events() to collect.
+use std::cell::{Cell, RefCell};
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::ptr;
+
+use super::{fit, Event, Key};
+
+type Handle = *mut c_void;
+type WndProc = unsafe extern "system" fn(Handle, u32, usize, isize) -> isize;
+
+const WS_OVERLAPPEDWINDOW: u32 = 0x00CF_0000;
+const WS_VISIBLE: u32 = 0x1000_0000;
+const CW_USEDEFAULT: i32 = 0x8000_0000_u32 as i32;
+const IDC_ARROW: usize = 32512;
+const PM_REMOVE: u32 = 1;
+
+const WM_SIZE: u32 = 0x0005;
+const WM_KILLFOCUS: u32 = 0x0008;
+const WM_PAINT: u32 = 0x000F;
+const WM_CLOSE: u32 = 0x0010;
+const WM_KEYDOWN: u32 = 0x0100;
+const WM_KEYUP: u32 = 0x0101;
+const WM_SYSKEYDOWN: u32 = 0x0104;
+const WM_SYSKEYUP: u32 = 0x0105;
+
+const VK_ESCAPE: usize = 0x1B;
+const KEY_WAS_DOWN: isize = 1 << 30; // Set in a key down message's lParam when it is a repeat.
+
+const BI_RGB: u32 = 0;
+const DIB_RGB_COLORS: u32 = 0;
+const SRCCOPY: u32 = 0x00CC_0020;
+const BLACKNESS: u32 = 0x0000_0042;
+
+#[repr(C)]
+struct WndClassExW {
+    size: u32,
+    style: u32,
+    wnd_proc: WndProc,
+    cls_extra: i32,
+    wnd_extra: i32,
+    instance: Handle,
+    icon: Handle,
+    cursor: Handle,
+    background: Handle,
+    menu_name: *const u16,
+    class_name: *const u16,
+    icon_small: Handle,
+}
+
+#[repr(C)]
+struct Msg {
+    hwnd: Handle,
+    message: u32,
+    wparam: usize,
+    lparam: isize,
+    time: u32,
+    pt: [i32; 2],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Rect {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+#[repr(C)]
+struct PaintStruct {
+    hdc: Handle,
+    erase: i32,
+    paint: Rect,
+    restore: i32,
+    inc_update: i32,
+    reserved: [u8; 32],
+}
+
+#[repr(C)]
+struct BitmapInfoHeader {
+    size: u32,
+    width: i32,
+    height: i32,
+    planes: u16,
+    bit_count: u16,
+    compression: u32,
+    size_image: u32,
+    x_pels_per_meter: i32,
+    y_pels_per_meter: i32,
+    clr_used: u32,
+    clr_important: u32,
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn RegisterClassExW(class: *const WndClassExW) -> u16;
+    fn CreateWindowExW(
+        ex_style: u32,
+        class_name: *const u16,
+        window_name: *const u16,
+        style: u32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        parent: Handle,
+        menu: Handle,
+        instance: Handle,
+        param: *mut c_void,
+    ) -> Handle;
+    fn DestroyWindow(hwnd: Handle) -> i32;
+    fn DefWindowProcW(hwnd: Handle, message: u32, wparam: usize, lparam: isize) -> isize;
+    fn PeekMessageW(msg: *mut Msg, hwnd: Handle, filter_min: u32, filter_max: u32, remove: u32) -> i32;
+    fn TranslateMessage(msg: *const Msg) -> i32;
+    fn DispatchMessageW(msg: *const Msg) -> isize;
+    fn LoadCursorW(instance: Handle, name: *const u16) -> Handle;
+    fn AdjustWindowRect(rect: *mut Rect, style: u32, menu: i32) -> i32;
+    fn GetClientRect(hwnd: Handle, rect: *mut Rect) -> i32;
+    fn SetWindowTextW(hwnd: Handle, text: *const u16) -> i32;
+    fn BeginPaint(hwnd: Handle, paint: *mut PaintStruct) -> Handle;
+    fn EndPaint(hwnd: Handle, paint: *const PaintStruct) -> i32;
+    fn GetDC(hwnd: Handle) -> Handle;
+    fn ReleaseDC(hwnd: Handle, hdc: Handle) -> i32;
+}
+
+#[link(name = "gdi32")]
+extern "system" {
+    fn StretchDIBits(
+        hdc: Handle,
+        x_dest: i32,
+        y_dest: i32,
+        dest_width: i32,
+        dest_height: i32,
+        x_src: i32,
+        y_src: i32,
+        src_width: i32,
+        src_height: i32,
+        bits: *const c_void,
+        info: *const BitmapInfoHeader,
+        usage: u32,
+        rop: u32,
+    ) -> i32;
+    fn PatBlt(hdc: Handle, x: i32, y: i32, width: i32, height: i32, rop: u32) -> i32;
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetModuleHandleW(name: *const u16) -> Handle;
+}
+
+thread_local! {
+    // What the window procedure has seen since events() was last called.
+    static EVENTS: RefCell<Vec<Event>> = const { RefCell::new(Vec::new()) };
+    // Set when part of the window was uncovered or it changed size, and needs drawing again.
+    static STALE: Cell<bool> = const { Cell::new(false) };
+}
+
+pub struct Window {
+    hwnd: Handle,
+    shown: Option<(usize, usize, Vec<u32>)>, // What was drawn last, to skip frames that are the same.
+}
+
+impl Window {
+    pub fn open(title: &str, width: u16, height: u16) -> io::Result<Self> {
+        let class_name = wide("chip8");
+        let title = wide(title);
+
+        // The size asked for is the inside of the window, so make room for the frame around it.
+        let mut rect = Rect { right: width as i32, bottom: height as i32, ..Rect::default() };
+
+        // SAFETY: every pointer passed is to a live local, and the strings are NUL terminated.
+        unsafe {
+            let instance = GetModuleHandleW(ptr::null());
+            let class = WndClassExW {
+                size: mem::size_of::<WndClassExW>() as u32,
+                style: 0,
+                wnd_proc: window_proc,
+                cls_extra: 0,
+                wnd_extra: 0,
+                instance,
+                icon: ptr::null_mut(),
+                cursor: LoadCursorW(ptr::null_mut(), IDC_ARROW as *const u16),
+                background: ptr::null_mut(),
+                menu_name: ptr::null(),
+                class_name: class_name.as_ptr(),
+                icon_small: ptr::null_mut(),
+            };
+            // This fails if the class was registered by an earlier window, which is fine.
+            RegisterClassExW(&class);
+
+            AdjustWindowRect(&mut rect, WS_OVERLAPPEDWINDOW, 0);
+            let hwnd = CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                title.as_ptr(),
+                WS_OVERLAPPEDWINDOW | WS_VISIBLE,
+                CW_USEDEFAULT,
+                CW_USEDEFAULT,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                instance,
+                ptr::null_mut(),
+            );
+            if hwnd.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { hwnd, shown: None })
+        }
+    }
+
+    pub fn set_title(&mut self, title: &str) -> io::Result<()> {
+        let title = wide(title);
+        // SAFETY: the window is ours and the title is NUL terminated.
+        if unsafe { SetWindowTextW(self.hwnd, title.as_ptr()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // Stretches a width x height image of 0xRRGGBB pixels into the window, scaled up to fit.
+    // Nothing is drawn if it's the same as last time and the window hasn't needed redrawing since.
+    pub fn draw(&mut self, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+        let same_size = self.shown.as_ref().is_some_and(|(w, h, _)| (*w, *h) == (width, height));
+        let stale = STALE.with(|stale| stale.replace(false));
+        if !stale && same_size && self.shown.as_ref().is_some_and(|(_, _, shown)| shown == pixels) {
+            return Ok(());
+        }
+
+        let mut client = Rect::default();
+        // SAFETY: the window is ours and client is a live local.
+        unsafe { GetClientRect(self.hwnd, &mut client) };
+        let (scale, x, y) = fit(width, height, (client.right as u16, client.bottom as u16));
+
+        // 32 bits per pixel with no compression is 0x00RRGGBB, just as we have it.
+        // The negative height says the rows go from the top down.
+        let info = BitmapInfoHeader {
+            size: mem::size_of::<BitmapInfoHeader>() as u32,
+            width: width as i32,
+            height: -(height as i32),
+            planes: 1,
+            bit_count: 32,
+            compression: BI_RGB,
+            size_image: 0,
+            x_pels_per_meter: 0,
+            y_pels_per_meter: 0,
+            clr_used: 0,
+            clr_important: 0,
+        };
+
+        // SAFETY: the device context is released before returning, and pixels holds width x height values.
+        unsafe {
+            let hdc = GetDC(self.hwnd);
+            if hdc.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            // Black out the border around the image when it could have moved.
+            if stale || !same_size {
+                PatBlt(hdc, 0, 0, client.right, client.bottom, BLACKNESS);
+            }
+            StretchDIBits(
+                hdc,
+                x as i32,
+                y as i32,
+                (width * scale) as i32,
+                (height * scale) as i32,
+                0,
+                0,
+                width as i32,
+                height as i32,
+                pixels.as_ptr().cast(),
+                &info,
+                DIB_RGB_COLORS,
+                SRCCOPY,
+            );
+            ReleaseDC(self.hwnd, hdc);
+        }
+
+        self.shown = Some((width, height, pixels.to_vec()));
+        Ok(())
+    }
+
+    pub fn events(&mut self) -> io::Result<Vec<Event>> {
+        // SAFETY: msg is only read after PeekMessageW has filled it in.
+        unsafe {
+            let mut msg: Msg = mem::zeroed();
+            while PeekMessageW(&mut msg, ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {
+                TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+        Ok(EVENTS.with(|events| mem::take(&mut *events.borrow_mut())))
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        // SAFETY: the window is ours and isn't used again.
+        unsafe { DestroyWindow(self.hwnd) };
+    }
+}
+
+unsafe extern "system" fn window_proc(hwnd: Handle, message: u32, wparam: usize, lparam: isize) -> isize {
+    let event = match message {
+        // Held keys repeat as more key downs, which are left out so that a key is pressed once.
+        WM_KEYDOWN | WM_SYSKEYDOWN if lparam & KEY_WAS_DOWN == 0 => key(wparam).map(Event::Pressed),
+        WM_KEYUP | WM_SYSKEYUP => key(wparam).map(Event::Released),
+        WM_KILLFOCUS => Some(Event::FocusLost),
+        // The close button. Left alone, DefWindowProcW would destroy the window under us.
+        WM_CLOSE => {
+            EVENTS.with(|events| events.borrow_mut().push(Event::Closed));
+            return 0;
+        },
+        WM_PAINT => {
+            let mut paint: PaintStruct = mem::zeroed();
+            BeginPaint(hwnd, &mut paint);
+            EndPaint(hwnd, &paint);
+            STALE.with(|stale| stale.set(true));
+            return 0;
+        },
+        WM_SIZE => {
+            STALE.with(|stale| stale.set(true));
+            None
+        },
+        _ => None,
+    };
+    if let Some(event) = event {
+        EVENTS.with(|events| events.borrow_mut().push(event));
+    }
+    DefWindowProcW(hwnd, message, wparam, lparam)
+}
+
+// The key for a virtual key code. The codes for digits and letters are their upper case ASCII.
+fn key(code: usize) -> Option<Key> {
+    match code {
+        VK_ESCAPE => Some(Key::Escape),
+        0x30..=0x39 | 0x41..=0x5A => Some(Key::Char((code as u8).to_ascii_lowercase())),
+        _ => None,
+    }
+}
+
+// A string as UTF-16 with a NUL on the end, the way Win32 takes them.
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain([0]).collect()
+}
@@ -0,0 +1,948 @@
+// An X11 client that speaks the wire protocol itself, over the socket to the X server named by
+// $DISPLAY. It does only what playing needs: one window, an image put into it every frame, a title,
+// and the keyboard and window manager events that come back.
+//
+// We ask the server to send everything to us little endian, and send it everything the same way.
+// The protocol is described in https://www.x.org/releases/X11R7.7/doc/xproto/x11protocol.html.
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use super::{fit, Event, Key};
+
+// Request opcodes.
+const CREATE_WINDOW: u8 = 1;
+const MAP_WINDOW: u8 = 8;
+const INTERN_ATOM: u8 = 16;
+const CHANGE_PROPERTY: u8 = 18;
+const CREATE_GC: u8 = 55;
+const CLEAR_AREA: u8 = 61;
+const PUT_IMAGE: u8 = 72;
+const GET_KEYBOARD_MAPPING: u8 = 101;
+
+// Event codes. Replies and errors come in the same stream, as codes 1 and 0.
+const ERROR: u8 = 0;
+const REPLY: u8 = 1;
+const KEY_PRESS: u8 = 2;
+const KEY_RELEASE: u8 = 3;
+const FOCUS_OUT: u8 = 10;
+const EXPOSE: u8 = 12;
+const CONFIGURE_NOTIFY: u8 = 22;
+const CLIENT_MESSAGE: u8 = 33;
+const GENERIC_EVENT: u8 = 35;
+
+// The events we ask for: KeyPress, KeyRelease, Exposure, StructureNotify and FocusChange.
+const EVENT_MASK: u32 = 0x0000_0001 | 0x0000_0002 | 0x0000_8000 | 0x0002_0000 | 0x0020_0000;
+
+// Atoms that every server has, so they don't need looking up.
+const ATOM_ATOM: u32 = 4;
+const ATOM_STRING: u32 = 31;
+const ATOM_WM_NAME: u32 = 39;
+
+const TRUE_COLOR: u8 = 4;
+const Z_PIXMAP: u8 = 2;
+const KEYSYM_ESCAPE: u32 = 0xFF1B;
+const AUTH_NAME: &[u8] = b"MIT-MAGIC-COOKIE-1";
+const X_TCP_PORT: u16 = 6000; // Display N listens on this port plus N.
+
+pub struct Window {
+    out: BufWriter<Stream>,
+    stream: Stream, // Kept to shut the connection down, which also stops the reader thread.
+    incoming: Receiver<[u8; 32]>,
+    pending: VecDeque<[u8; 32]>, // Events taken from incoming that haven't been handled yet.
+    setup: Setup,
+    window: u32,
+    gc: u32,
+    wm_protocols: u32,
+    wm_delete_window: u32,
+    keysyms: Vec<u32>, // The first keysym of every keycode, starting at setup.min_keycode.
+    size: (u16, u16),
+    shown: Option<(usize, usize, Vec<u32>)>, // What was drawn last, to skip frames that are the same.
+}
+
+// Where the X server is, from $DISPLAY.
+#[derive(Debug, PartialEq)]
+struct Display {
+    address: Address,
+    number: String,
+    screen: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum Address {
+    Unix(PathBuf),
+    Tcp(String, u16),
+}
+
+// What we need from the server's reply to connecting.
+#[derive(Debug)]
+struct Setup {
+    id_base: u32,
+    id_mask: u32,
+    max_request_len: usize, // In bytes.
+    min_keycode: u8,
+    max_keycode: u8,
+    image_msb_first: bool,
+    root: u32,
+    depth: u8,
+    bits_per_pixel: usize,
+    scanline_pad: usize, // Each row of an image is padded to a multiple of this many bits.
+    masks: [u32; 3], // Which bits of a pixel are red, green and blue.
+}
+
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Window {
+    pub fn open(title: &str, width: u16, height: u16) -> io::Result<Self> {
+        let name = env::var("DISPLAY").map_err(|_| io::Error::other("DISPLAY isn't set, so there is no X server to use"))?;
+        let display = parse_display(&name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("can't understand DISPLAY={}", name)))?;
+
+        let stream = match &display.address {
+            Address::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+            Address::Tcp(host, port) => {
+                let stream = TcpStream::connect((host.as_str(), *port))?;
+                stream.set_nodelay(true)?;
+                Stream::Tcp(stream)
+            },
+        };
+        let cookie = find_cookie(&display.number);
+        Self::connect(stream, cookie.as_deref(), display.screen, title, (width, height))
+            .map_err(|err| io::Error::new(err.kind(), format!("X server at DISPLAY={}: {}", name, err)))
+    }
+
+    // Sets up a window over a connection that has just been made.
+    fn connect(stream: Stream, cookie: Option<&[u8]>, screen: usize, title: &str, size: (u16, u16)) -> io::Result<Self> {
+        let mut input = stream.try_clone()?;
+        let mut out = BufWriter::new(stream.try_clone()?);
+
+        out.write_all(&setup_request(cookie))?;
+        out.flush()?;
+        let setup = parse_setup(&read_setup_reply(&mut input)?, screen)?;
+
+        let id = |n: u32| setup.id_base | ((n << setup.id_mask.trailing_zeros()) & setup.id_mask);
+        let (window, gc) = (id(1), id(2));
+
+        // Everything that has a reply is asked for before the window exists, so no events can get mixed in.
+        let wm_protocols = intern_atom(&mut out, &mut input, "WM_PROTOCOLS")?;
+        let wm_delete_window = intern_atom(&mut out, &mut input, "WM_DELETE_WINDOW")?;
+        let keysyms = keyboard_mapping(&mut out, &mut input, &setup)?;
+
+        let mut body = Vec::new();
+        put_u32(&mut body, window);
+        put_u32(&mut body, setup.root);
+        body.extend_from_slice(&[0, 0, 0, 0]); // x and y, which the window manager decides anyway.
+        put_u16(&mut body, size.0);
+        put_u16(&mut body, size.1);
+        put_u16(&mut body, 0); // Border width.
+        put_u16(&mut body, 1); // InputOutput.
+        put_u32(&mut body, 0); // The root's visual.
+        put_u32(&mut body, 0x0000_0002 | 0x0000_0800); // BackPixel and EventMask.
+        put_u32(&mut body, 0); // A black background, which every visual we accept has as 0.
+        put_u32(&mut body, EVENT_MASK);
+        out.write_all(&request(CREATE_WINDOW, 0, &body))?; // A depth of 0 is the root's depth.
+
+        // Ask the window manager to tell us about the close button, rather than cutting us off.
+        let mut body = Vec::new();
+        put_u32(&mut body, window);
+        put_u32(&mut body, wm_protocols);
+        put_u32(&mut body, ATOM_ATOM);
+        body.extend_from_slice(&[32, 0, 0, 0]);
+        put_u32(&mut body, 1);
+        put_u32(&mut body, wm_delete_window);
+        out.write_all(&request(CHANGE_PROPERTY, 0, &body))?;
+
+        let mut body = Vec::new();
+        put_u32(&mut body, gc);
+        put_u32(&mut body, window);
+        put_u32(&mut body, 0); // No values, the defaults are fine for putting images.
+        out.write_all(&request(CREATE_GC, 0, &body))?;
+
+        let mut window = Self {
+            out,
+            stream,
+            incoming: spawn_reader(input),
+            pending: VecDeque::new(),
+            setup,
+            window,
+            gc,
+            wm_protocols,
+            wm_delete_window,
+            keysyms,
+            size,
+            shown: None,
+        };
+        window.set_title(title)?;
+        window.out.write_all(&request(MAP_WINDOW, 0, &window.window.to_le_bytes()))?;
+        window.out.flush()?;
+        Ok(window)
+    }
+
+    pub fn set_title(&mut self, title: &str) -> io::Result<()> {
+        // WM_NAME is Latin-1, so anything outside ASCII becomes a question mark.
+        let title: Vec<u8> = title.chars().map(|c| if c.is_ascii() { c as u8 } else { b'?' }).collect();
+        let mut body = Vec::new();
+        put_u32(&mut body, self.window);
+        put_u32(&mut body, ATOM_WM_NAME);
+        put_u32(&mut body, ATOM_STRING);
+        body.extend_from_slice(&[8, 0, 0, 0]);
+        put_u32(&mut body, title.len() as u32);
+        body.extend_from_slice(&title);
+        self.out.write_all(&request(CHANGE_PROPERTY, 0, &body))?;
+        self.out.flush()
+    }
+
+    // Puts a width x height image of 0xRRGGBB pixels into the window, scaled up to fit.
+    // Nothing is sent if it's the same as last time and the window hasn't needed redrawing since.
+    pub fn draw(&mut self, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+        let same_size = self.shown.as_ref().is_some_and(|(w, h, _)| (*w, *h) == (width, height));
+        if same_size && self.shown.as_ref().is_some_and(|(_, _, shown)| shown == pixels) {
+            return Ok(());
+        }
+        let (scale, x, y) = fit(width, height, self.size);
+
+        // When the image moves or changes size, clear the whole window for the border around it.
+        if !same_size {
+            let mut body = Vec::new();
+            put_u32(&mut body, self.window);
+            body.extend_from_slice(&[0; 8]); // A width and height of 0 reach to the edges.
+            self.out.write_all(&request(CLEAR_AREA, 0, &body))?;
+        }
+
+        // Each row of the screen becomes scale rows of the image, each one made of scale copies of every pixel.
+        let bytes_per_pixel = self.setup.bits_per_pixel / 8;
+        let row_len = pad_to(width * scale * self.setup.bits_per_pixel, self.setup.scanline_pad) / 8;
+        let mut rows = Vec::with_capacity(row_len * height * scale);
+        for line in pixels.chunks(width) {
+            let start = rows.len();
+            for &pixel in line {
+                let value = self.encode(pixel);
+                for _ in 0..scale {
+                    if self.setup.image_msb_first {
+                        rows.extend_from_slice(&value.to_be_bytes()[4 - bytes_per_pixel..]);
+                    } else {
+                        rows.extend_from_slice(&value.to_le_bytes()[..bytes_per_pixel]);
+                    }
+                }
+            }
+            rows.resize(start + row_len, 0);
+            for _ in 1..scale {
+                rows.extend_from_within(start..start + row_len);
+            }
+        }
+
+        // Requests have a size limit, so big images go in strips of as many rows as will fit.
+        let rows_per_request = (self.setup.max_request_len.saturating_sub(24) / row_len).max(1);
+        for (strip, data) in rows.chunks(row_len * rows_per_request).enumerate() {
+            let mut body = Vec::with_capacity(20 + data.len());
+            put_u32(&mut body, self.window);
+            put_u32(&mut body, self.gc);
+            put_u16(&mut body, (width * scale) as u16);
+            put_u16(&mut body, (data.len() / row_len) as u16);
+            put_u16(&mut body, x as u16);
+            put_u16(&mut body, (y + strip * rows_per_request) as u16);
+            body.extend_from_slice(&[0, self.setup.depth, 0, 0]); // No left padding, then the depth.
+            body.extend_from_slice(data);
+            self.out.write_all(&request(PUT_IMAGE, Z_PIXMAP, &body))?;
+        }
+        self.out.flush()?;
+
+        self.shown = Some((width, height, pixels.to_vec()));
+        Ok(())
+    }
+
+    // Turns a 0xRRGGBB color into a pixel value for the screen's visual.
+    fn encode(&self, rgb: u32) -> u32 {
+        let channels = [rgb >> 16 & 0xFF, rgb >> 8 & 0xFF, rgb & 0xFF];
+        channels.iter().zip(self.setup.masks).fold(0, |value, (&channel, mask)| {
+            let shift = mask.trailing_zeros();
+            let max = mask >> shift;
+            value | (channel * max / 255) << shift
+        })
+    }
+
+    pub fn events(&mut self) -> io::Result<Vec<Event>> {
+        loop {
+            match self.incoming.try_recv() {
+                Ok(event) => self.pending.push_back(event),
+                Err(TryRecvError::Empty) => break,
+                // The server has gone, and the window with it.
+                Err(TryRecvError::Disconnected) => {
+                    self.pending.clear();
+                    return Ok(vec![Event::Closed]);
+                },
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(event) = self.pending.pop_front() {
+            // The top bit is set on events that another client sent us, which we treat the same.
+            match event[0] & 0x7F {
+                ERROR => return Err(io::Error::other(format!("error {} from request {}", event[1], event[10]))),
+                KEY_PRESS => events.extend(self.key(event[1]).map(Event::Pressed)),
+                KEY_RELEASE => {
+                    // A held key repeats as a release and a press with the same time. Those cancel out,
+                    // so the key stays down.
+                    let time = &event[4..8];
+                    if let Some(next) = self.pending.front() {
+                        if next[0] & 0x7F == KEY_PRESS && next[1] == event[1] && &next[4..8] == time {
+                            self.pending.pop_front();
+                            continue;
+                        }
+                    }
+                    events.extend(self.key(event[1]).map(Event::Released));
+                },
+                FOCUS_OUT => events.push(Event::FocusLost),
+                EXPOSE => self.shown = None,
+                CONFIGURE_NOTIFY => {
+                    let size = (get_u16(&event, 20), get_u16(&event, 22));
+                    if size != self.size {
+                        self.size = size;
+                        self.shown = None;
+                    }
+                },
+                CLIENT_MESSAGE
+                    if get_u32(&event, 8) == self.wm_protocols && get_u32(&event, 12) == self.wm_delete_window =>
+                {
+                    events.push(Event::Closed);
+                },
+                _ => {},
+            }
+        }
+        Ok(events)
+    }
+
+    // The key that a keycode is for, going by the first keysym the keyboard mapping gives it.
+    fn key(&self, keycode: u8) -> Option<Key> {
+        let keysym = *self.keysyms.get(keycode.checked_sub(self.setup.min_keycode)? as usize)?;
+        match keysym {
+            KEYSYM_ESCAPE => Some(Key::Escape),
+            // Keysyms for the printable ASCII characters are the characters themselves.
+            0x20..=0x7E => Some(Key::Char((keysym as u8).to_ascii_lowercase())),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        // Closing the connection takes the window away with it.
+        self.stream.shutdown();
+    }
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+        })
+    }
+
+    fn shutdown(&self) {
+        let _ = match self {
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Both),
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+// Reads events on a background thread, so that the main loop never blocks waiting for them.
+// Every event is 32 bytes, apart from generic events, which we don't ask for but skip over properly.
+fn spawn_reader(mut input: Stream) -> Receiver<[u8; 32]> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut event = [0; 32];
+        while input.read_exact(&mut event).is_ok() {
+            if event[0] & 0x7F == GENERIC_EVENT {
+                let extra = get_u32(&event, 4) as u64 * 4;
+                if io::copy(&mut (&mut input).take(extra), &mut io::sink()).is_err() {
+                    break;
+                }
+                continue;
+            }
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+// Works out where the server is from $DISPLAY, which looks like [host]:number[.screen].
+// No host, or "unix", is the local socket for that display. A host starting with / is a socket path
+// on its own, which is how XQuartz on macOS does it.
+fn parse_display(name: &str) -> Option<Display> {
+    let (host, rest) = name.rsplit_once(':')?;
+    let (number, screen) = match rest.split_once('.') {
+        Some((number, screen)) => (number, screen.parse().ok()?),
+        None => (rest, 0),
+    };
+    let display: u16 = number.parse().ok()?;
+
+    let address = match host {
+        "" | "unix" => Address::Unix(PathBuf::from(format!("/tmp/.X11-unix/X{}", display))),
+        _ if host.starts_with('/') => Address::Unix(PathBuf::from(format!("{}:{}", host, number))),
+        _ => Address::Tcp(host.to_string(), X_TCP_PORT.checked_add(display)?),
+    };
+    Some(Display { address, number: number.to_string(), screen })
+}
+
+// Finds the cookie that lets us in to a display, from $XAUTHORITY or ~/.Xauthority.
+// Without one we try to connect anyway, which works for servers that don't check.
+fn find_cookie(number: &str) -> Option<Vec<u8>> {
+    let path = match env::var_os("XAUTHORITY") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env::var_os("HOME")?).join(".Xauthority"),
+    };
+    cookie_for(&fs::read(path).ok()?, number)
+}
+
+// Picks out the MIT-MAGIC-COOKIE-1 entry for a display number from an Xauthority file.
+// Every entry is a family number and then four length-prefixed fields: address, display number,
+// auth name and auth data. The lengths and the family are big endian, unlike the protocol itself.
+fn cookie_for(file: &[u8], number: &str) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let field = |pos: &mut usize| -> Option<&[u8]> {
+        let len = u16::from_be_bytes(file.get(*pos..*pos + 2)?.try_into().ok()?) as usize;
+        let data = file.get(*pos + 2..*pos + 2 + len)?;
+        *pos += 2 + len;
+        Some(data)
+    };
+    while pos < file.len() {
+        pos += 2; // The family. We don't know our own host name to check the address against.
+        let _address = field(&mut pos)?;
+        let entry_number = field(&mut pos)?;
+        let name = field(&mut pos)?;
+        let data = field(&mut pos)?;
+        if (entry_number.is_empty() || entry_number == number.as_bytes()) && name == AUTH_NAME {
+            return Some(data.to_vec());
+        }
+    }
+    None
+}
+
+// The first thing sent to the server, saying how we talk and how we're allowed in.
+fn setup_request(cookie: Option<&[u8]>) -> Vec<u8> {
+    let (name, data) = match cookie {
+        Some(cookie) => (AUTH_NAME, cookie),
+        None => (&[][..], &[][..]),
+    };
+    let mut out = vec![b'l', 0]; // Little endian.
+    put_u16(&mut out, 11); // Protocol version 11.0.
+    put_u16(&mut out, 0);
+    put_u16(&mut out, name.len() as u16);
+    put_u16(&mut out, data.len() as u16);
+    put_u16(&mut out, 0);
+    out.extend_from_slice(name);
+    out.resize(pad_to(out.len(), 4), 0);
+    out.extend_from_slice(data);
+    out.resize(pad_to(out.len(), 4), 0);
+    out
+}
+
+// Reads the whole reply to the setup request, failing with the server's reason if it turned us away.
+fn read_setup_reply(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut reply = vec![0; 8];
+    input.read_exact(&mut reply)?;
+    let len = get_u16(&reply, 6) as usize * 4;
+    reply.resize(8 + len, 0);
+    input.read_exact(&mut reply[8..])?;
+
+    match reply[0] {
+        1 => Ok(reply),
+        0 => {
+            let reason = reply.get(8..8 + reply[1] as usize).unwrap_or_default();
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("the server refused the connection: {}", String::from_utf8_lossy(reason).trim_end()),
+            ))
+        },
+        _ => Err(io::Error::new(io::ErrorKind::PermissionDenied, "the server wants a kind of authentication we can't do")),
+    }
+}
+
+// Pulls what we need out of a successful setup reply, for the given screen.
+fn parse_setup(reply: &[u8], screen: usize) -> io::Result<Setup> {
+    let short = || io::Error::new(io::ErrorKind::InvalidData, "the server's setup reply is too short");
+    let u8_at = |pos: usize| reply.get(pos).copied().ok_or_else(short);
+    let u16_at = |pos: usize| Ok::<_, io::Error>(u16::from_le_bytes(reply.get(pos..pos + 2).ok_or_else(short)?.try_into().unwrap()));
+    let u32_at = |pos: usize| Ok::<_, io::Error>(u32::from_le_bytes(reply.get(pos..pos + 4).ok_or_else(short)?.try_into().unwrap()));
+
+    let vendor_len = u16_at(24)? as usize;
+    let num_screens = u8_at(28)? as usize;
+    let num_formats = u8_at(29)? as usize;
+    let formats = 40 + pad_to(vendor_len, 4);
+
+    // Walk past the screens before ours. Each is 40 bytes, then its depths, each with its visuals.
+    if screen >= num_screens {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("there is no screen {}", screen)));
+    }
+    let mut pos = formats + 8 * num_formats;
+    for _ in 0..screen {
+        let num_depths = u8_at(pos + 39)?;
+        pos += 40;
+        for _ in 0..num_depths {
+            pos += 8 + 24 * u16_at(pos + 2)? as usize;
+        }
+    }
+
+    let root = u32_at(pos)?;
+    let root_visual = u32_at(pos + 32)?;
+    let depth = u8_at(pos + 38)?;
+    let num_depths = u8_at(pos + 39)?;
+
+    // Find the root visual, to know which bits of a pixel are which color.
+    let mut masks = None;
+    let mut depth_pos = pos + 40;
+    for _ in 0..num_depths {
+        let num_visuals = u16_at(depth_pos + 2)? as usize;
+        for visual in 0..num_visuals {
+            let visual_pos = depth_pos + 8 + 24 * visual;
+            if u32_at(visual_pos)? == root_visual {
+                if u8_at(visual_pos + 4)? != TRUE_COLOR {
+                    return Err(io::Error::new(io::ErrorKind::Unsupported, "the screen isn't true color"));
+                }
+                masks = Some([u32_at(visual_pos + 8)?, u32_at(visual_pos + 12)?, u32_at(visual_pos + 16)?]);
+            }
+        }
+        depth_pos += 8 + 24 * num_visuals;
+    }
+    let masks = masks.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the screen's visual isn't listed"))?;
+
+    // The pixmap format for the root's depth says how big each pixel of an image is.
+    let format = (0..num_formats).map(|n| formats + 8 * n).find(|&format| reply.get(format) == Some(&depth));
+    let format = format.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the screen's depth has no image format"))?;
+    let bits_per_pixel = u8_at(format + 1)? as usize;
+    if ![8, 16, 24, 32].contains(&bits_per_pixel) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} bits per pixel isn't supported", bits_per_pixel)));
+    }
+
+    Ok(Setup {
+        id_base: u32_at(12)?,
+        id_mask: u32_at(16)?,
+        max_request_len: u16_at(26)? as usize * 4,
+        min_keycode: u8_at(34)?,
+        max_keycode: u8_at(35)?,
+        image_msb_first: u8_at(30)? == 1,
+        root,
+        depth,
+        bits_per_pixel,
+        scanline_pad: u8_at(format + 2)? as usize,
+        masks,
+    })
+}
+
+// Looks up the number the server uses for a name.
+fn intern_atom(out: &mut impl Write, input: &mut impl Read, name: &str) -> io::Result<u32> {
+    let mut body = Vec::new();
+    put_u16(&mut body, name.len() as u16);
+    put_u16(&mut body, 0);
+    body.extend_from_slice(name.as_bytes());
+    out.write_all(&request(INTERN_ATOM, 0, &body))?;
+    out.flush()?;
+    Ok(get_u32(&read_reply(input)?, 8))
+}
+
+// Asks for the keysyms on every key, keeping the first one of each: the one without shift.
+fn keyboard_mapping(out: &mut impl Write, input: &mut impl Read, setup: &Setup) -> io::Result<Vec<u32>> {
+    let count = setup.max_keycode.saturating_sub(setup.min_keycode) as usize + 1;
+    out.write_all(&request(GET_KEYBOARD_MAPPING, 0, &[setup.min_keycode, count as u8, 0, 0]))?;
+    out.flush()?;
+
+    let reply = read_reply(input)?;
+    let per_keycode = (reply[1] as usize).max(1);
+    let keysyms = reply[32..].chunks_exact(4).step_by(per_keycode).map(|keysym| get_u32(keysym, 0));
+    Ok(keysyms.take(count).collect())
+}
+
+// Reads the reply to the request we just sent, including any data past the first 32 bytes.
+fn read_reply(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut reply = vec![0; 32];
+    input.read_exact(&mut reply)?;
+    match reply[0] {
+        REPLY => {
+            let extra = get_u32(&reply, 4) as usize * 4;
+            reply.resize(32 + extra, 0);
+            input.read_exact(&mut reply[32..])?;
+            Ok(reply)
+        },
+        ERROR => Err(io::Error::other(format!("error {} from request {}", reply[1], reply[10]))),
+        code => Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected a reply, got event {}", code))),
+    }
+}
+
+// Builds a request: the opcode, one byte that depends on the request, the length in 4 byte units,
+// then the body padded out to a multiple of 4 bytes.
+fn request(opcode: u8, data: u8, body: &[u8]) -> Vec<u8> {
+    let len = pad_to(4 + body.len(), 4);
+    let mut out = Vec::with_capacity(len);
+    out.push(opcode);
+    out.push(data);
+    put_u16(&mut out, (len / 4) as u16);
+    out.extend_from_slice(body);
+    out.resize(len, 0);
+    out
+}
+
+fn pad_to(len: usize, multiple: usize) -> usize {
+    len.div_ceil(multiple) * multiple
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn get_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn get_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+    use std::time::{Duration, Instant};
+
+    const MIN_KEYCODE: u8 = 8;
+    const ROOT: u32 = 0x100;
+    const ID_BASE: u32 = 0x0400_0000;
+
+    // The keysyms for a small keyboard: Escape on keycode 9, Q on 24 and A on 38, with their shifted forms.
+    fn keysym_table() -> Vec<[u32; 2]> {
+        let mut table = vec![[0, 0]; 40];
+        table[(9 - MIN_KEYCODE) as usize] = [KEYSYM_ESCAPE, 0];
+        table[(24 - MIN_KEYCODE) as usize] = [b'q' as u32, b'Q' as u32];
+        table[(38 - MIN_KEYCODE) as usize] = [b'A' as u32, b'A' as u32]; // Some layouts only list upper case.
+        table
+    }
+
+    // A setup reply for a single 24-bit true color screen, the usual kind.
+    fn setup_reply(max_request_units: u16) -> Vec<u8> {
+        let vendor = b"Fake";
+        let mut out = vec![1, 0];
+        put_u16(&mut out, 11);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0); // The length, filled in at the end.
+        put_u32(&mut out, 1); // Release number.
+        put_u32(&mut out, ID_BASE);
+        put_u32(&mut out, 0x001F_FFFF);
+        put_u32(&mut out, 0); // Motion buffer size.
+        put_u16(&mut out, vendor.len() as u16);
+        put_u16(&mut out, max_request_units);
+        out.extend_from_slice(&[1, 2, 0, 0, 32, 32, MIN_KEYCODE, MIN_KEYCODE + keysym_table().len() as u8 - 1]);
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(vendor);
+        out.resize(pad_to(out.len(), 4), 0);
+
+        // Pixmap formats: depth 1 and depth 24, the second with 32 bits per pixel.
+        out.extend_from_slice(&[1, 1, 32, 0, 0, 0, 0, 0]);
+        out.extend_from_slice(&[24, 32, 32, 0, 0, 0, 0, 0]);
+
+        // The screen, then its one depth with its one visual.
+        put_u32(&mut out, ROOT);
+        out.extend_from_slice(&[0; 28]);
+        put_u32(&mut out, 0x21); // The root visual.
+        out.extend_from_slice(&[0, 0, 24, 1]);
+        out.extend_from_slice(&[24, 0]);
+        put_u16(&mut out, 1);
+        out.extend_from_slice(&[0; 4]);
+        put_u32(&mut out, 0x21);
+        out.extend_from_slice(&[TRUE_COLOR, 8, 0, 1]);
+        put_u32(&mut out, 0xFF_0000);
+        put_u32(&mut out, 0x00_FF00);
+        put_u32(&mut out, 0x00_00FF);
+        out.extend_from_slice(&[0; 4]);
+
+        let len = ((out.len() - 8) / 4) as u16;
+        out[6..8].copy_from_slice(&len.to_le_bytes());
+        out
+    }
+
+    // Plays the part of the X server on the other end of a socket: answers the setup request with
+    // setup, then answers InternAtom and GetKeyboardMapping, passing every request it gets to requests.
+    fn fake_server(mut server: UnixStream, setup: Vec<u8>, requests: Sender<Vec<u8>>) {
+        thread::spawn(move || {
+            let mut header = [0; 12];
+            server.read_exact(&mut header).unwrap();
+            let auth_len = pad_to(get_u16(&header, 6) as usize, 4) + pad_to(get_u16(&header, 8) as usize, 4);
+            let mut auth = vec![0; auth_len];
+            server.read_exact(&mut auth).unwrap();
+            requests.send([&header[..], &auth].concat()).unwrap();
+            server.write_all(&setup).unwrap();
+
+            let mut next_atom = 0x200;
+            let mut header = [0; 4];
+            while server.read_exact(&mut header).is_ok() {
+                let mut request = header.to_vec();
+                request.resize(get_u16(&header, 2) as usize * 4, 0);
+                server.read_exact(&mut request[4..]).unwrap();
+
+                let mut reply = vec![REPLY, 0, 0, 0, 0, 0, 0, 0];
+                match request[0] {
+                    INTERN_ATOM => {
+                        put_u32(&mut reply, next_atom);
+                        next_atom += 1;
+                    },
+                    GET_KEYBOARD_MAPPING => {
+                        let table = keysym_table();
+                        reply[1] = 2;
+                        reply[4..8].copy_from_slice(&(table.len() as u32 * 2).to_le_bytes());
+                        reply.resize(32, 0);
+                        for keysyms in table {
+                            keysyms.iter().for_each(|&keysym| put_u32(&mut reply, keysym));
+                        }
+                    },
+                    _ => reply.clear(),
+                }
+                if !reply.is_empty() {
+                    reply.resize(reply.len().max(32), 0);
+                    server.write_all(&reply).unwrap();
+                }
+                if requests.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // Opens a window against a fake server. Returns the window, the server's end to send events down,
+    // and what the client has sent, starting with the setup request.
+    fn open(max_request_units: u16) -> (Window, UnixStream, Receiver<Vec<u8>>) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (tx, requests) = mpsc::channel();
+        fake_server(server.try_clone().unwrap(), setup_reply(max_request_units), tx);
+        let window = Window::connect(Stream::Unix(client), Some(b"0123456789abcdef"), 0, "Pong", (640, 320)).unwrap();
+        (window, server, requests)
+    }
+
+    fn event(code: u8, detail: u8, time: u32) -> [u8; 32] {
+        let mut event = [0; 32];
+        event[0] = code;
+        event[1] = detail;
+        event[4..8].copy_from_slice(&time.to_le_bytes());
+        event
+    }
+
+    // Collects events from the window until there are at least count of them, or a second has gone by.
+    fn wait_for_events(window: &mut Window, count: usize) -> Vec<Event> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut events = Vec::new();
+        while events.len() < count && Instant::now() < deadline {
+            events.extend(window.events().unwrap());
+            thread::sleep(Duration::from_millis(5));
+        }
+        events
+    }
+
+    #[test]
+    fn displays() {
+        let local = |path: &str, number: &str, screen| Display {
+            address: Address::Unix(PathBuf::from(path)),
+            number: number.to_string(),
+            screen,
+        };
+        assert_eq!(parse_display(":0"), Some(local("/tmp/.X11-unix/X0", "0", 0)));
+        assert_eq!(parse_display("unix:1.2"), Some(local("/tmp/.X11-unix/X1", "1", 2)));
+        assert_eq!(
+            parse_display("/private/tmp/com.apple.launchd.abc/org.xquartz:0"),
+            Some(local("/private/tmp/com.apple.launchd.abc/org.xquartz:0", "0", 0))
+        );
+        assert_eq!(
+            parse_display("localhost:10.0"),
+            Some(Display { address: Address::Tcp("localhost".to_string(), 6010), number: "10".to_string(), screen: 0 })
+        );
+        assert_eq!(parse_display("localhost"), None);
+        assert_eq!(parse_display(":x"), None);
+        assert_eq!(parse_display(":0.x"), None);
+    }
+
+    #[test]
+    fn cookies_are_found_by_display_number() {
+        let entry = |number: &[u8], name: &[u8], data: &[u8]| {
+            let mut out = vec![0x01, 0x00]; // FamilyLocal.
+            for field in [&b"host"[..], number, name, data] {
+                out.extend_from_slice(&(field.len() as u16).to_be_bytes());
+                out.extend_from_slice(field);
+            }
+            out
+        };
+        let file = [entry(b"0", AUTH_NAME, b"zero"), entry(b"1", b"XDM-AUTHORIZATION-1", b"other"), entry(b"1", AUTH_NAME, b"one")]
+            .concat();
+
+        assert_eq!(cookie_for(&file, "0"), Some(b"zero".to_vec()));
+        assert_eq!(cookie_for(&file, "1"), Some(b"one".to_vec()));
+        assert_eq!(cookie_for(&file, "2"), None);
+        // An entry without a display number is for all of them.
+        assert_eq!(cookie_for(&entry(b"", AUTH_NAME, b"any"), "7"), Some(b"any".to_vec()));
+        // A damaged file gives up rather than reading past the end.
+        assert_eq!(cookie_for(&file[..file.len() - 1], "1"), None);
+    }
+
+    #[test]
+    fn opening_a_window() {
+        let (window, _server, requests) = open(0xFFFF);
+
+        let setup = requests.recv().unwrap();
+        assert_eq!(&setup[..4], &[b'l', 0, 11, 0]);
+        assert_eq!(&setup[12..12 + AUTH_NAME.len()], AUTH_NAME);
+        assert!(setup.ends_with(b"0123456789abcdef"));
+
+        let opcodes: Vec<u8> = (0..8).map(|_| requests.recv().unwrap()[0]).collect();
+        assert_eq!(
+            opcodes,
+            [INTERN_ATOM, INTERN_ATOM, GET_KEYBOARD_MAPPING, CREATE_WINDOW, CHANGE_PROPERTY, CREATE_GC, CHANGE_PROPERTY, MAP_WINDOW]
+        );
+        assert_eq!(window.window, ID_BASE | 1);
+        assert_eq!(window.gc, ID_BASE | 2);
+        assert_eq!(window.keysyms.len(), keysym_table().len());
+    }
+
+    #[test]
+    fn keys_go_down_and_up() {
+        let (mut window, mut server, _requests) = open(0xFFFF);
+        let q = Key::Char(b'q');
+
+        server.write_all(&event(KEY_PRESS, 24, 1)).unwrap();
+        // Holding the key repeats it as a release and a press with the same time, which cancel out.
+        server.write_all(&event(KEY_RELEASE, 24, 5)).unwrap();
+        server.write_all(&event(KEY_PRESS, 24, 5)).unwrap();
+        server.write_all(&event(KEY_RELEASE, 24, 9)).unwrap();
+        // Letters come out lower case even when the keyboard mapping only has upper case.
+        server.write_all(&event(KEY_PRESS, 38, 10)).unwrap();
+        server.write_all(&event(KEY_PRESS, 9, 11)).unwrap();
+        // A key with nothing we use on it is left out.
+        server.write_all(&event(KEY_PRESS, 30, 12)).unwrap();
+
+        let events = wait_for_events(&mut window, 4);
+        assert_eq!(events, [Event::Pressed(q), Event::Released(q), Event::Pressed(Key::Char(b'a')), Event::Pressed(Key::Escape)]);
+    }
+
+    #[test]
+    fn window_manager_events() {
+        let (mut window, mut server, _requests) = open(0xFFFF);
+
+        server.write_all(&event(FOCUS_OUT, 0, 0)).unwrap();
+        // Another property's message is ignored, the delete window one is the close button.
+        let mut message = event(CLIENT_MESSAGE, 32, 0);
+        message[8..12].copy_from_slice(&(window.wm_protocols + 7).to_le_bytes());
+        server.write_all(&message).unwrap();
+        message[8..12].copy_from_slice(&window.wm_protocols.to_le_bytes());
+        message[12..16].copy_from_slice(&window.wm_delete_window.to_le_bytes());
+        // Sent by the window manager rather than the server, so the top bit is set.
+        message[0] |= 0x80;
+        server.write_all(&message).unwrap();
+
+        assert_eq!(wait_for_events(&mut window, 2), [Event::FocusLost, Event::Closed]);
+
+        // The server going away closes the window too.
+        server.shutdown(Shutdown::Both).unwrap();
+        assert_eq!(wait_for_events(&mut window, 1), [Event::Closed]);
+    }
+
+    #[test]
+    fn images_are_put_in_strips() {
+        let (mut window, _server, requests) = open(0xFFFF);
+        requests.recv().unwrap(); // The setup request.
+        for _ in 0..8 {
+            requests.recv().unwrap();
+        }
+
+        let mut pixels = vec![0; 64 * 32];
+        pixels[0] = 0xFF8000;
+        window.draw(64, 32, &pixels).unwrap();
+        window.set_title("Pong - Saved to slot 5").unwrap();
+
+        assert_eq!(requests.recv().unwrap()[0], CLEAR_AREA);
+        // Each row is 640 pixels of 4 bytes, and 102 of them fit under the 256 KiB request limit.
+        let mut y = 0;
+        for rows in [102, 102, 102, 14] {
+            let request = requests.recv().unwrap();
+            assert_eq!((request[0], request[1]), (PUT_IMAGE, Z_PIXMAP));
+            assert_eq!(request.len(), 24 + 640 * 4 * rows);
+            assert_eq!((get_u16(&request, 12), get_u16(&request, 14)), (640, rows as u16));
+            assert_eq!((get_u16(&request, 16), get_u16(&request, 18)), (0, y));
+            assert_eq!(request[21], 24);
+            if y == 0 {
+                // The first pixel is scaled up to 10x10, and is little endian 0x00RRGGBB.
+                assert_eq!(&request[24..24 + 40], [0x00, 0x80, 0xFF, 0x00].repeat(10));
+                assert_eq!(&request[24 + 40..24 + 44], [0; 4]);
+                assert_eq!(&request[24 + 2560 * 9..24 + 2560 * 9 + 4], [0x00, 0x80, 0xFF, 0x00]);
+                assert_eq!(&request[24 + 2560 * 10..24 + 2560 * 10 + 4], [0; 4]);
+            }
+            y += rows as u16;
+        }
+
+        // The same frame again sends nothing, so the next request is the title.
+        window.draw(64, 32, &pixels).unwrap();
+        window.set_title("Pong").unwrap();
+        assert_eq!(requests.recv().unwrap()[0], CHANGE_PROPERTY);
+        assert_eq!(requests.recv().unwrap()[0], CHANGE_PROPERTY);
+    }
+
+    #[test]
+    fn refused_connections_say_why() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let reason = b"No protocol specified\n";
+        let mut reply = vec![0, reason.len() as u8];
+        put_u16(&mut reply, 11);
+        put_u16(&mut reply, 0);
+        put_u16(&mut reply, (pad_to(reason.len(), 4) / 4) as u16);
+        reply.extend_from_slice(reason);
+        reply.resize(pad_to(reply.len(), 4), 0);
+        server.write_all(&reply).unwrap();
+
+        let Err(err) = Window::connect(Stream::Unix(client), None, 0, "Pong", (640, 320)) else {
+            panic!("the connection was refused");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "the server refused the connection: No protocol specified");
+    }
+
+    #[test]
+    fn missing_screens_are_an_error() {
+        let err = parse_setup(&setup_reply(0xFFFF), 1).unwrap_err();
+        assert_eq!(err.to_string(), "there is no screen 1");
+    }
+}